@@ -0,0 +1,836 @@
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// Periods in CPU cycles. Dendy uses the NTSC tables.
+const NOISE_PERIOD_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const NOISE_PERIOD_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+const DMC_RATE_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+const DMC_RATE_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// Frame counter steps in CPU cycles: quarter frames, and where the sequence restarts
+const FRAME_STEPS_4_NTSC: [u32; 4] = [7457, 14913, 22371, 29829];
+const FRAME_PERIOD_4_NTSC: u32 = 29830;
+const FRAME_STEPS_5_NTSC: [u32; 4] = [7457, 14913, 22371, 37281];
+const FRAME_PERIOD_5_NTSC: u32 = 37282;
+
+const FRAME_STEPS_4_PAL: [u32; 4] = [8313, 16627, 24939, 33253];
+const FRAME_PERIOD_4_PAL: u32 = 33254;
+const FRAME_STEPS_5_PAL: [u32; 4] = [8313, 16627, 24939, 41565];
+const FRAME_PERIOD_5_PAL: u32 = 41566;
+
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    period: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope { start: false, looping: false, constant: false, period: 0, divider: 0, decay: 0 }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.looping = (data & 0x20) != 0;
+        self.constant = (data & 0x10) != 0;
+        self.period = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn volume(&self) -> u8 {
+        if self.constant { self.period } else { self.decay }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.start);
+        writer.write_bool(self.looping);
+        writer.write_bool(self.constant);
+        writer.write_u8(self.period);
+        writer.write_u8(self.divider);
+        writer.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.start = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.constant = reader.read_bool()?;
+        self.period = reader.read_u8()?;
+        self.divider = reader.read_u8()?;
+        self.decay = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+struct Pulse {
+    enabled: bool,
+    ones_complement: bool, // Pulse 1 negates with ones' complement
+    duty: u8,
+    duty_step: u8,
+    timer: u16,
+    timer_period: u16,
+    length: u8,
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            enabled: false,
+            ones_complement,
+            duty: 0,
+            duty_step: 0,
+            timer: 0,
+            timer_period: 0,
+            length: 0,
+            envelope: Envelope::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = (data & 0x80) != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = (data & 0x08) != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => {
+                self.timer_period = (self.timer_period & 0x0700) | data as u16;
+            }
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.duty_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // Clocked every APU cycle (two CPU cycles)
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let negated = self.timer_period.wrapping_sub(change);
+            if self.ones_complement { negated.wrapping_sub(1) } else { negated }
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTY_TABLE[self.duty as usize][self.duty_step as usize] == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_step);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.timer_period);
+        writer.write_u8(self.length);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u8(self.sweep_period);
+        writer.write_bool(self.sweep_negate);
+        writer.write_u8(self.sweep_shift);
+        writer.write_u8(self.sweep_divider);
+        writer.write_bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()?;
+        self.duty_step = reader.read_u8()?;
+        self.timer = reader.read_u16()?;
+        self.timer_period = reader.read_u16()?;
+        self.length = reader.read_u8()?;
+        self.envelope.load_state(reader)?;
+        self.sweep_enabled = reader.read_bool()?;
+        self.sweep_period = reader.read_u8()?;
+        self.sweep_negate = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()?;
+        self.sweep_divider = reader.read_u8()?;
+        self.sweep_reload = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+struct Triangle {
+    enabled: bool,
+    control: bool,
+    step: u8,
+    timer: u16,
+    timer_period: u16,
+    length: u8,
+    linear_counter: u8,
+    linear_period: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn new() -> Self {
+        Triangle {
+            enabled: false,
+            control: false,
+            step: 0,
+            timer: 0,
+            timer_period: 0,
+            length: 0,
+            linear_counter: 0,
+            linear_period: 0,
+            linear_reload: false,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = (data & 0x80) != 0;
+                self.linear_period = data & 0x7F;
+            }
+            2 => {
+                self.timer_period = (self.timer_period & 0x0700) | data as u16;
+            }
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Clocked every CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.control);
+        writer.write_u8(self.step);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.timer_period);
+        writer.write_u8(self.length);
+        writer.write_u8(self.linear_counter);
+        writer.write_u8(self.linear_period);
+        writer.write_bool(self.linear_reload);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.control = reader.read_bool()?;
+        self.step = reader.read_u8()?;
+        self.timer = reader.read_u16()?;
+        self.timer_period = reader.read_u16()?;
+        self.length = reader.read_u8()?;
+        self.linear_counter = reader.read_u8()?;
+        self.linear_period = reader.read_u8()?;
+        self.linear_reload = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    mode: bool,
+    shift: u16,
+    timer: u16,
+    timer_period: u16,
+    period_table: &'static [u16; 16],
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new(period_table: &'static [u16; 16]) -> Self {
+        Noise {
+            enabled: false,
+            mode: false,
+            shift: 1,
+            timer: 0,
+            timer_period: period_table[0],
+            period_table,
+            length: 0,
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.envelope.write(data),
+            2 => {
+                self.mode = (data & 0x80) != 0;
+                self.timer_period = self.period_table[(data & 0x0F) as usize];
+            }
+            3 => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Clocked every CPU cycle, the period table is in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || (self.shift & 1) != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.mode);
+        writer.write_u16(self.shift);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.timer_period);
+        writer.write_u8(self.length);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.mode = reader.read_bool()?;
+        self.shift = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.timer_period = reader.read_u16()?;
+        self.length = reader.read_u8()?;
+        self.envelope.load_state(reader)?;
+        Ok(())
+    }
+}
+
+struct Dmc {
+    irq_enabled: bool,
+    irq_flag: bool,
+    looping: bool,
+    timer: u16,
+    timer_period: u16,
+    rate_table: &'static [u16; 16],
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+
+    sample_buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn new(rate_table: &'static [u16; 16]) -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq_flag: false,
+            looping: false,
+            timer: 0,
+            timer_period: rate_table[0],
+            rate_table,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = (data & 0x80) != 0;
+                self.looping = (data & 0x40) != 0;
+                self.timer_period = self.rate_table[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            1 => self.output_level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            _ => self.sample_length = ((data as u16) << 4) | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Clocked every CPU cycle, the rate table is in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if (self.shift & 1) != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+            self.shift >>= 1;
+        }
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_flag);
+        writer.write_bool(self.looping);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.timer_period);
+        writer.write_u8(self.output_level);
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.bytes_remaining);
+        writer.write_bool(self.sample_buffer.is_some());
+        writer.write_u8(self.sample_buffer.unwrap_or(0));
+        writer.write_u8(self.shift);
+        writer.write_u8(self.bits_remaining);
+        writer.write_bool(self.silence);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_flag = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.timer = reader.read_u16()?;
+        self.timer_period = reader.read_u16()?;
+        self.output_level = reader.read_u8()?;
+        self.sample_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.bytes_remaining = reader.read_u16()?;
+        let has_sample = reader.read_bool()?;
+        let sample = reader.read_u8()?;
+        self.sample_buffer = if has_sample { Some(sample) } else { None };
+        self.shift = reader.read_u8()?;
+        self.bits_remaining = reader.read_u8()?;
+        self.silence = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+// Named like CPU and PPU
+#[allow(clippy::upper_case_acronyms)]
+pub struct APU {
+    region: Region,
+
+    pulse: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    odd_cycle: bool,
+
+    // One mixed sample in 0.0..1.0 per CPU cycle, drained by the audio output
+    pub output_enabled: bool,
+    pub samples: Vec<f32>,
+}
+
+impl APU {
+    pub fn new(region: Region) -> Self {
+        let (noise_periods, dmc_rates) = match region {
+            Region::Pal => (&NOISE_PERIOD_PAL, &DMC_RATE_PAL),
+            Region::Ntsc | Region::Dendy => (&NOISE_PERIOD_NTSC, &DMC_RATE_NTSC),
+        };
+
+        APU {
+            region,
+
+            pulse: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::new(),
+            noise: Noise::new(noise_periods),
+            dmc: Dmc::new(dmc_rates),
+
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+
+            output_enabled: false,
+            samples: Vec::new(),
+        }
+    }
+
+    // Advances the APU by one CPU cycle
+    pub fn step(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.odd_cycle {
+            self.pulse[0].clock_timer();
+            self.pulse[1].clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.step_frame_counter();
+
+        if self.output_enabled {
+            self.samples.push(self.mix());
+        }
+    }
+
+    fn step_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        let (steps, period) = match (self.region, self.five_step_mode) {
+            (Region::Pal, true) => (&FRAME_STEPS_5_PAL, FRAME_PERIOD_5_PAL),
+            (Region::Pal, false) => (&FRAME_STEPS_4_PAL, FRAME_PERIOD_4_PAL),
+            (_, true) => (&FRAME_STEPS_5_NTSC, FRAME_PERIOD_5_NTSC),
+            (_, false) => (&FRAME_STEPS_4_NTSC, FRAME_PERIOD_4_NTSC),
+        };
+
+        if let Some(step) = steps.iter().position(|&cycle| cycle == self.frame_cycle) {
+            self.clock_quarter_frame();
+            if step == 1 || step == 3 {
+                self.clock_half_frame();
+            }
+            if step == 3 && !self.five_step_mode && !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        }
+
+        if self.frame_cycle >= period {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse[0].envelope.clock();
+        self.pulse[1].envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn clock_half_frame(&mut self) {
+        for pulse in self.pulse.iter_mut() {
+            pulse.clock_length();
+            pulse.clock_sweep();
+        }
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    // Non-linear mixer approximation from the NESdev wiki
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+        let triangle = self.triangle.output() as f32 / 8227.0;
+        let noise = self.noise.output() as f32 / 12241.0;
+        let dmc = self.dmc.output_level as f32 / 22638.0;
+        let tnd = triangle + noise + dmc;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+        pulse_out + tnd_out
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq_flag
+    }
+
+    // Address the DMC wants to fetch its next sample byte from, if any
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        if self.dmc.sample_buffer.is_none() && self.dmc.bytes_remaining > 0 {
+            Some(self.dmc.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => {
+                let mut data = 0;
+                if self.pulse[0].length > 0 { data |= 0x01; }
+                if self.pulse[1].length > 0 { data |= 0x02; }
+                if self.triangle.length > 0 { data |= 0x04; }
+                if self.noise.length > 0 { data |= 0x08; }
+                if self.dmc.bytes_remaining > 0 { data |= 0x10; }
+                if self.frame_irq { data |= 0x40; }
+                if self.dmc.irq_flag { data |= 0x80; }
+
+                self.frame_irq = false;
+                data
+            }
+            _ => 0
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse[0].write(addr & 0x03, data),
+            0x4004..=0x4007 => self.pulse[1].write(addr & 0x03, data),
+            0x4008..=0x400B => self.triangle.write(addr & 0x03, data),
+            0x400C..=0x400F => self.noise.write(addr & 0x03, data),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, data),
+
+            0x4015 => {
+                self.pulse[0].enabled = (data & 0x01) != 0;
+                self.pulse[1].enabled = (data & 0x02) != 0;
+                self.triangle.enabled = (data & 0x04) != 0;
+                self.noise.enabled = (data & 0x08) != 0;
+
+                if !self.pulse[0].enabled { self.pulse[0].length = 0; }
+                if !self.pulse[1].enabled { self.pulse[1].length = 0; }
+                if !self.triangle.enabled { self.triangle.length = 0; }
+                if !self.noise.enabled { self.noise.length = 0; }
+
+                if (data & 0x10) == 0 {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq_flag = false;
+            }
+
+            0x4017 => {
+                self.five_step_mode = (data & 0x80) != 0;
+                self.irq_inhibit = (data & 0x40) != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for pulse in self.pulse.iter() {
+            pulse.save_state(writer);
+        }
+        self.triangle.save_state(writer);
+        self.noise.save_state(writer);
+        self.dmc.save_state(writer);
+
+        writer.write_bool(self.five_step_mode);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.frame_irq);
+        writer.write_u64(self.frame_cycle as u64);
+        writer.write_bool(self.odd_cycle);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        for pulse in self.pulse.iter_mut() {
+            pulse.load_state(reader)?;
+        }
+        self.triangle.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.dmc.load_state(reader)?;
+
+        self.five_step_mode = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.frame_irq = reader.read_bool()?;
+        self.frame_cycle = reader.read_u64()? as u32;
+        self.odd_cycle = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::f32::consts::PI;
+
+// Largest relative change dynamic rate control may apply to the output rate.
+// Half a percent is below what the ear notices as a pitch change.
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// First-order RC filter, as found in the NES output stage
+struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter {
+    fn high_pass(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter { high_pass: true, alpha: rc / (rc + dt), prev_in: 0.0, prev_out: 0.0 }
+    }
+
+    fn low_pass(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter { high_pass: false, alpha: dt / (rc + dt), prev_in: 0.0, prev_out: 0.0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = if self.high_pass {
+            self.alpha * (self.prev_out + input - self.prev_in)
+        } else {
+            self.prev_out + self.alpha * (input - self.prev_out)
+        };
+        self.prev_in = input;
+        self.prev_out = output;
+        output
+    }
+}
+
+// Band-limited step synthesis, as in blip_buf: every change of the input level
+// adds a windowed-sinc step to the output, so content above the output Nyquist
+// rate is filtered out instead of aliasing
+const KERNEL_HALF_WIDTH: usize = 16; // Output samples on each side of a step
+const KERNEL_WIDTH: usize = 2 * KERNEL_HALF_WIDTH;
+const KERNEL_PHASES: usize = 64;     // Positions a step can take between two output samples
+const KERNEL_CUTOFF: f64 = 0.4;      // In cycles per output sample; the stopband starts near 0.5
+
+// Output sample differences of a unit step at each phase. Tap j lands on
+// output sample floor(t) + j for a step at time t, t counted from tap 0 of
+// the first output sample, which keeps the output KERNEL_HALF_WIDTH behind.
+fn step_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half_width = KERNEL_HALF_WIDTH as f64;
+    (0..KERNEL_PHASES).map(|phase| {
+        let offset = phase as f64 / KERNEL_PHASES as f64;
+        let mut taps = [0.0f64; KERNEL_WIDTH];
+        for (j, tap) in taps.iter_mut().enumerate() {
+            // Centre of the output sample period, relative to the step
+            let x = j as f64 - half_width + 0.5 - offset;
+            let arg = 2.0 * KERNEL_CUTOFF * x * std::f64::consts::PI;
+            let sinc = if arg == 0.0 { 1.0 } else { arg.sin() / arg };
+            let angle = std::f64::consts::PI * x / half_width;
+            let blackman = 0.42 + 0.5 * angle.cos() + 0.08 * (2.0 * angle).cos();
+            *tap = sinc * blackman;
+        }
+        // Each phase adds up to exactly one step
+        let sum: f64 = taps.iter().sum();
+        taps.map(|tap| (tap / sum) as f32)
+    }).collect()
+}
+
+// Converts the APU's one-sample-per-CPU-cycle stream to the host sample rate
+// with band-limited steps, then runs it through the 90Hz/440Hz high-pass and
+// 14kHz low-pass filters of the console.
+pub struct AudioResampler {
+    input_rate: f64,
+    output_rate: f64,
+    step: f64,      // Input samples per output sample, adjusted by rate control
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    time: f64,        // Of the next input sample, in output samples from buffer[0]
+    level: f32,       // Input level after the samples so far
+    buffer: Vec<f32>, // Differences between output samples, not all steps added yet
+    integrator: f32,  // Output level before buffer[0]
+    filters: [Filter; 3],
+    pub output: Vec<f32>,
+}
+
+impl AudioResampler {
+    pub fn new(input_rate: f64, output_rate: f64) -> Self {
+        let rate = output_rate as f32;
+        AudioResampler {
+            input_rate,
+            output_rate,
+            step: input_rate / output_rate,
+            kernel: step_kernel(),
+            time: 0.0,
+            level: 0.0,
+            buffer: Vec::new(),
+            integrator: 0.0,
+            filters: [
+                Filter::high_pass(90.0, rate),
+                Filter::high_pass(440.0, rate),
+                Filter::low_pass(14000.0, rate),
+            ],
+            output: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        let time_step = 1.0 / self.step;
+        for sample in samples.iter() {
+            let delta = *sample - self.level;
+            if delta != 0.0 {
+                self.level = *sample;
+                let start = self.time.floor();
+                let phase = (((self.time - start) * KERNEL_PHASES as f64) as usize).min(KERNEL_PHASES - 1);
+                let start = start as usize;
+                if self.buffer.len() < start + KERNEL_WIDTH {
+                    self.buffer.resize(start + KERNEL_WIDTH, 0.0);
+                }
+                for (out, tap) in self.buffer[start..start + KERNEL_WIDTH].iter_mut().zip(self.kernel[phase].iter()) {
+                    *out += delta * tap;
+                }
+            }
+            self.time += time_step;
+        }
+
+        // Output samples before the next input sample get no more steps
+        let complete = self.time.floor() as usize;
+        if self.buffer.len() < complete {
+            self.buffer.resize(complete, 0.0);
+        }
+        for difference in self.buffer.drain(..complete) {
+            self.integrator += difference;
+            let mut value = self.integrator;
+            for filter in self.filters.iter_mut() {
+                value = filter.process(value);
+            }
+            self.output.push(value);
+        }
+        self.time -= complete as f64;
+    }
+
+    // Dynamic rate control: nudges the resampling ratio so the host buffer
+    // settles around `target` queued samples instead of draining or growing.
+    pub fn update_rate(&mut self, queued: usize, target: usize) {
+        let target = target.max(1) as f64;
+        let error = ((target - queued as f64) / target).clamp(-1.0, 1.0);
+        let adjusted_rate = self.output_rate * (1.0 + MAX_RATE_DELTA * error);
+        self.step = self.input_rate / adjusted_rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU_CLOCK: f64 = 1_789_773.0;
+    const OUTPUT_RATE: f64 = 48_000.0;
+
+    // RMS of a resampled sine tone, once the filters have settled
+    fn output_level(frequency: f64) -> f32 {
+        let mut resampler = AudioResampler::new(CPU_CLOCK, OUTPUT_RATE);
+        let input: Vec<f32> = (0..CPU_CLOCK as usize / 5)
+            .map(|i| 0.5 * (2.0 * std::f64::consts::PI * frequency * i as f64 / CPU_CLOCK).sin() as f32)
+            .collect();
+        for chunk in input.chunks(29_781) {
+            resampler.process(chunk);
+        }
+
+        let settled = &resampler.output[resampler.output.len() / 2..];
+        (settled.iter().map(|value| value * value).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn produces_the_output_rate() {
+        let mut resampler = AudioResampler::new(CPU_CLOCK, OUTPUT_RATE);
+        resampler.process(&vec![0.25; CPU_CLOCK as usize]);
+        assert!((resampler.output.len() as f64 - OUTPUT_RATE).abs() <= 1.0);
+    }
+
+    #[test]
+    fn kernel_phases_are_unit_steps() {
+        for taps in step_kernel() {
+            assert!((taps.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn passes_audible_tones() {
+        // A 0.5 sine has an RMS of 0.35, less what the high-pass filters take
+        assert!(output_level(1000.0) > 0.3);
+        assert!(output_level(8000.0) > 0.2);
+    }
+
+    #[test]
+    fn filters_tones_above_nyquist_instead_of_aliasing_them() {
+        let reference = output_level(1000.0);
+        for frequency in [26_000.0, 30_000.0, 45_000.0, 100_000.0] {
+            let level = output_level(frequency);
+            assert!(level < reference * 0.01, "{} Hz comes out at {} against {}", frequency, level, reference);
+        }
+    }
+}
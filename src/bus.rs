@@ -1,43 +1,324 @@
 use crate::ppu::PPU;
+use crate::apu::APU;
 use crate::ines_file::Rom;
 use crate::controller::Controller;
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+use crate::cdl::{CodeDataLog, CHR_READ};
+use crate::event_log::{EventKind, EventLog, PpuEvent};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+// A bus access recorded for the debugger's watchpoints. PPU accesses are the
+// ones the CPU makes through $2007.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryAccess {
+    pub space: AddressSpace,
+    pub write: bool,
+    pub addr: u16,
+    pub value: u8,
+}
+
+// Memories the debugger's memory editor can show, by offset
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryRegion {
+    CpuRam,
+    PrgRam,
+    PrgRomMapped, // $8000-$FFFF through the mapper
+    PrgRomRaw,    // The whole PRG ROM image
+    Chr,
+    Nametables,   // $2000-$2FFF through the mirroring
+    Palette,
+    Oam,
+}
+
+impl MemoryRegion {
+    pub const ALL: [MemoryRegion; 8] = [
+        MemoryRegion::CpuRam,
+        MemoryRegion::PrgRam,
+        MemoryRegion::PrgRomMapped,
+        MemoryRegion::PrgRomRaw,
+        MemoryRegion::Chr,
+        MemoryRegion::Nametables,
+        MemoryRegion::Palette,
+        MemoryRegion::Oam,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryRegion::CpuRam => "RAM",
+            MemoryRegion::PrgRam => "PRG-RAM",
+            MemoryRegion::PrgRomMapped => "PRG",
+            MemoryRegion::PrgRomRaw => "PRG raw",
+            MemoryRegion::Chr => "CHR",
+            MemoryRegion::Nametables => "Nametables",
+            MemoryRegion::Palette => "Palette",
+            MemoryRegion::Oam => "OAM",
+        }
+    }
+
+    // Address of offset 0 in the CPU or PPU address space, if it has one
+    pub fn base_address(&self) -> usize {
+        match self {
+            MemoryRegion::PrgRam => 0x6000,
+            MemoryRegion::PrgRomMapped => 0x8000,
+            MemoryRegion::Nametables => 0x2000,
+            MemoryRegion::Palette => 0x3F00,
+            _ => 0,
+        }
+    }
+}
 
 pub struct Bus {
     pub ram: [u8; 2 * 1024],
     pub rom: Rom,
     pub ppu: PPU,
+    pub apu: APU,
     pub controller: [Controller; 2],
+
+    pub region: Region,
+    ppu_clock: u32, // Fractional PPU dots carried between CPU cycles, in fifths
+
+    // Page written to $4014, copied by the CPU once the current instruction ends
+    pub oam_dma: Option<u8>,
+
+    // Last value driven on the CPU data bus, read back from undriven addresses
+    pub open_bus: u8,
+
+    // Accesses since the debugger last looked, only recorded while it watches
+    pub log_accesses: bool,
+    pub accesses: Vec<MemoryAccess>,
+
+    // Code/data log, kept while recording
+    pub cdl: Option<CodeDataLog>,
+
+    // Register accesses for the event viewer, kept while it is open
+    pub events: Option<EventLog>,
 }
 
 impl Bus {
+    pub fn new(rom: Rom, region: Region) -> Self {
+        Bus {
+            ram: [0x00; 2 * 1024],
+            rom,
+            ppu: PPU::new(region),
+            apu: APU::new(region),
+            controller: [Controller::new(), Controller::new()],
+
+            region,
+            ppu_clock: 0,
+
+            oam_dma: None,
+
+            open_bus: 0,
+
+            log_accesses: false,
+            accesses: Vec::new(),
+
+            cdl: None,
+
+            events: None,
+        }
+    }
+
+    // Power cycle: everything but the cartridge contents and the debug logs starts over
+    pub fn power_on(&mut self) {
+        self.ram = [0x00; 2 * 1024];
+        self.rom.power_on();
+        self.ppu = PPU::new(self.region);
+        self.apu = APU::new(self.region);
+        self.controller = [Controller::new(), Controller::new()];
+        self.ppu_clock = 0;
+        self.oam_dma = None;
+        self.open_bus = 0;
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
-        match addr {
+        let ppu_addr = self.ppu.vram_address();
+        let mut internal = None; // Read from inside the CPU, the external bus keeps its value
+        let data = match addr {
             0x0000..=0x1FFF => {
-                self.ram[(addr as usize) & 0x07FF as usize]
+                Some(self.ram[(addr as usize) & 0x07FF])
             }
             
             0x2000..=0x3FFF => {
-                self.ppu.cpu_read(addr & 0x0007, false, &mut self.rom)
+                if let Some(cdl) = self.cdl.as_mut()
+                    && (addr & 0x0007) == 0x0007 && (ppu_addr & 0x3FFF) < 0x2000 {
+                    cdl.log_chr(self.rom.mapper.ppu_read_mapper_addr(ppu_addr & 0x3FFF), CHR_READ);
+                }
+                Some(self.ppu.cpu_read(addr & 0x0007, false, &mut self.rom))
+            }
+
+            // $4015 is inside the CPU and does not drive the external bus; bit 5 is not connected
+            0x4015 => {
+                internal = Some((self.apu.cpu_read(addr) & !0x20) | (self.open_bus & 0x20));
+                None
             }
 
-            0x4016 => self.controller[0].read(),
+            // Controllers only drive the low bits
+            0x4016 => Some(self.controller[0].read() | (self.open_bus & 0xE0)),
+
+            0x4017 => Some(self.controller[1].read() | (self.open_bus & 0xE0)),
 
-            0x4017 => self.controller[1].read(),
+            0x4020..=0x5FFF => self.rom.mapper.expansion_read(addr),
+
+            0x6000..=0x7FFF if !self.rom.prg_ram.is_empty() => {
+                Some(self.rom.prg_ram[(addr as usize - 0x6000) % self.rom.prg_ram.len()])
+            }
 
             0x8000..=0xFFFF => {
-                let offset = addr - 0x8000;
-                let rom_size = self.rom.prg_rom.len() as u16;
-                let index = (offset % rom_size) as usize;
-                self.rom.prg_rom[index]
+                let index = self.rom.mapper.cpu_read_mapper_addr(addr) % self.rom.prg_rom.len();
+                if let Some(cdl) = self.cdl.as_mut() {
+                    cdl.log_prg_read(addr, index);
+                }
+                Some(self.rom.prg_rom[index])
+            }
+            _ => None
+        };
+
+        if let Some(data) = data {
+            self.open_bus = data;
+        }
+        let value = internal.unwrap_or(self.open_bus);
+
+        if self.events.is_some() {
+            self.log_event(addr, value, false);
+        }
+
+        if self.log_accesses {
+            self.log_access(AddressSpace::Cpu, false, addr, value);
+            if (0x2000..=0x3FFF).contains(&addr) && (addr & 0x0007) == 0x0007 {
+                self.log_access(AddressSpace::Ppu, false, ppu_addr, value);
             }
-            _ => {0}
+        }
+        value
+    }
+
+    // Reads without side effects, for the debugger. Registers that cannot be
+    // read that way show the open bus value.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr as usize) & 0x07FF],
+            0x2000..=0x3FFF => self.ppu.cpu_read(addr & 0x0007, true, &mut self.rom),
+            0x6000..=0x7FFF if !self.rom.prg_ram.is_empty() => {
+                self.rom.prg_ram[(addr as usize - 0x6000) % self.rom.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.rom.prg_rom[self.prg_rom_index(addr as usize - 0x8000)],
+            _ => self.open_bus,
+        }
+    }
+
+    // The write counterpart of peek: stores into RAM, PRG-RAM or the mapped
+    // PRG-ROM byte without reaching registers or the mapper
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr as usize) & 0x07FF] = data,
+            0x6000..=0x7FFF if !self.rom.prg_ram.is_empty() => {
+                let len = self.rom.prg_ram.len();
+                self.rom.prg_ram[(addr as usize - 0x6000) % len] = data;
+            }
+            0x8000..=0xFFFF => self.poke_memory(MemoryRegion::PrgRomMapped, addr as usize - 0x8000, data),
+            _ => {}
+        }
+    }
+
+    pub fn memory_len(&self, region: MemoryRegion) -> usize {
+        match region {
+            MemoryRegion::CpuRam => self.ram.len(),
+            MemoryRegion::PrgRam => self.rom.prg_ram.len(),
+            MemoryRegion::PrgRomMapped => 0x8000,
+            MemoryRegion::PrgRomRaw => self.rom.prg_rom.len(),
+            MemoryRegion::Chr => self.rom.chr_rom.len(),
+            MemoryRegion::Nametables => 0x1000,
+            MemoryRegion::Palette => 32,
+            MemoryRegion::Oam => 256,
         }
     }
 
+    pub fn peek_memory(&self, region: MemoryRegion, offset: usize) -> u8 {
+        match region {
+            MemoryRegion::CpuRam => self.ram[offset],
+            MemoryRegion::PrgRam => self.rom.prg_ram[offset],
+            MemoryRegion::PrgRomMapped => self.rom.prg_rom[self.prg_rom_index(offset)],
+            MemoryRegion::PrgRomRaw => self.rom.prg_rom[offset],
+            MemoryRegion::Chr => self.rom.chr_rom[offset],
+            MemoryRegion::Nametables | MemoryRegion::Palette => {
+                self.ppu.ppu_read((region.base_address() + offset) as u16, &self.rom)
+            }
+            MemoryRegion::Oam => self.ppu.oam_data[offset],
+        }
+    }
+
+    // Writes straight into the memory, ROM included
+    pub fn poke_memory(&mut self, region: MemoryRegion, offset: usize, data: u8) {
+        match region {
+            MemoryRegion::CpuRam => self.ram[offset] = data,
+            MemoryRegion::PrgRam => self.rom.prg_ram[offset] = data,
+            MemoryRegion::PrgRomMapped => {
+                let index = self.prg_rom_index(offset);
+                self.rom.prg_rom[index] = data;
+            }
+            MemoryRegion::PrgRomRaw => self.rom.prg_rom[offset] = data,
+            MemoryRegion::Chr => self.rom.chr_rom[offset] = data,
+            MemoryRegion::Nametables | MemoryRegion::Palette => {
+                self.ppu.ppu_write((region.base_address() + offset) as u16, data, &mut self.rom);
+            }
+            MemoryRegion::Oam => self.ppu.oam_data[offset] = data,
+        }
+    }
+
+    // PRG-ROM byte currently mapped at a CPU address
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_rom_index(addr as usize - 0x8000))
+    }
+
+    fn prg_rom_index(&self, offset: usize) -> usize {
+        self.rom.mapper.cpu_read_mapper_addr(0x8000 + offset as u16) % self.rom.prg_rom.len()
+    }
+
+    fn log_event(&mut self, addr: u16, value: u8, write: bool) {
+        if let (Some(kind), Some(events)) = (EventKind::of(addr, write), self.events.as_mut()) {
+            events.record(self.ppu.frame(), PpuEvent {
+                kind,
+                addr,
+                value,
+                write,
+                scanline: self.ppu.scanline(),
+                dot: self.ppu.dot(),
+            });
+        }
+    }
+
+    fn log_access(&mut self, space: AddressSpace, write: bool, addr: u16, value: u8) {
+        self.accesses.push(MemoryAccess {
+            space,
+            write,
+            addr,
+            value,
+        });
+    }
+
     pub fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+
+        if self.events.is_some() {
+            self.log_event(addr, data, true);
+        }
+
+        if self.log_accesses {
+            self.log_access(AddressSpace::Cpu, true, addr, data);
+            if (0x2000..=0x3FFF).contains(&addr) && (addr & 0x0007) == 0x0007 {
+                self.log_access(AddressSpace::Ppu, true, self.ppu.vram_address(), data);
+            }
+        }
+
         match addr as usize {
             0x0000..=0x1FFF => {
-                self.ram[(addr as usize) & 0x07FF as usize] = data;
+                self.ram[(addr as usize) & 0x07FF] = data;
             }
 
             0x2000..=0x3FFF => {
@@ -45,11 +326,11 @@ impl Bus {
             }
 
             0x4014 => {
-                let start = (data as u16) << 8;
-                for i in 0..256 {
-                    let byte = self.read(start + i);
-                    self.ppu.oam_data[self.ppu.oam_addr.wrapping_add(i as u8) as usize] = byte;
-                }
+                self.oam_dma = Some(data);
+            }
+
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.apu.cpu_write(addr, data);
             }
 
             0x4016 => {
@@ -57,13 +338,130 @@ impl Bus {
                 self.controller[1].write(data);
             }
 
+            0x4020..=0x5FFF => {
+                self.rom.mapper.expansion_write(addr, data);
+            }
+
+            0x6000..=0x7FFF if !self.rom.prg_ram.is_empty() => {
+                let len = self.rom.prg_ram.len();
+                self.rom.prg_ram[(addr as usize - 0x6000) % len] = data;
+            }
+
             0x8000..=0xFFFF => {
-                let offset = addr - 0x8000;
-                let rom_size = self.rom.prg_rom.len() as u16;
-                let index = (offset % rom_size) as usize;
-                self.rom.prg_rom[index] = data;
+                self.rom.mapper.cpu_write(addr, data);
             }
             _ => {}
         }
     }
+
+    // Advances the PPU and APU by one CPU cycle and returns the NMI line as the
+    // CPU samples it. DMC sample fetches are left to the CPU, which has to
+    // stall for them.
+    pub fn tick(&mut self) -> bool {
+        let nmi_line = self.clock_ppu();
+        self.apu.step();
+        nmi_line
+    }
+
+    // Runs the PPU dots that fit in one CPU cycle: 3, or 3.2 on PAL. The CPU
+    // samples /NMI after the first of them, so a $2002 read at the end of the
+    // cycle can still clear a flag set on the second or third dot.
+    pub fn clock_ppu(&mut self) -> bool {
+        self.ppu_clock += self.region.ppu_dots_per_cycle_x5() - 5;
+        self.ppu.step();
+        let nmi_line = self.ppu.nmi_line();
+        while self.ppu_clock >= 5 {
+            self.ppu.step();
+            self.ppu_clock -= 5;
+        }
+        nmi_line
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_u8(self.ppu_clock as u8);
+        writer.write_u8(self.open_bus);
+        self.rom.mapper.save_state(writer);
+        writer.write_bytes(&self.rom.extra_vram);
+        writer.write_bytes(&self.rom.prg_ram);
+        if self.rom.chr_ram {
+            writer.write_bytes(&self.rom.chr_rom);
+        }
+        self.ppu.save_state(writer);
+        self.apu.save_state(writer);
+        for controller in self.controller.iter() {
+            controller.save_state(writer);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_bytes(&mut self.ram)?;
+        self.ppu_clock = reader.read_u8()? as u32;
+        self.open_bus = reader.read_u8()?;
+        self.rom.mapper.load_state(reader)?;
+        reader.read_bytes(&mut self.rom.extra_vram)?;
+        reader.read_bytes(&mut self.rom.prg_ram)?;
+        if self.rom.chr_ram {
+            reader.read_bytes(&mut self.rom.chr_rom)?;
+        }
+        self.ppu.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        for controller in self.controller.iter_mut() {
+            controller.load_state(reader)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines_file::test_rom;
+
+    #[test]
+    fn apu_status_reads_are_logged_but_leave_the_open_bus() {
+        let mut bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        bus.log_accesses = true;
+        bus.write(0x4015, 0x01); // Enable pulse 1
+        bus.write(0x4003, 0x08); // and load its length counter
+        bus.write(0x0000, 0xA0);
+        bus.read(0x0000);
+
+        assert_eq!(bus.read(0x4015), 0x01 | (0xA0 & 0x20));
+        assert_eq!(bus.open_bus, 0xA0);
+
+        let last = bus.accesses.last().unwrap();
+        assert_eq!((last.write, last.addr, last.value), (false, 0x4015, 0x21));
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_value_on_the_bus() {
+        let mut bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        bus.write(0x0000, 0x5A);
+        assert_eq!(bus.read(0x0000), 0x5A);
+        // No expansion hardware, and no PRG RAM on this board
+        assert_eq!(bus.read(0x5000), 0x5A);
+        assert_eq!(bus.read(0x6000), 0x5A);
+        // Nor a register at $4018
+        bus.write(0x0001, 0x3C);
+        bus.read(0x0001);
+        assert_eq!(bus.read(0x4018), 0x3C);
+    }
+
+    #[test]
+    fn controller_reads_leave_the_upper_bits_to_the_open_bus() {
+        let mut bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        bus.write(0x0000, 0x47);
+        for port in [0x4016, 0x4017] {
+            bus.read(0x0000);
+            assert_eq!(bus.read(port) & 0xE0, 0x40);
+        }
+        // The read itself is what is left on the bus afterwards
+        bus.write(0x0000, 0xFF);
+        bus.read(0x0000);
+        let value = bus.read(0x4016);
+        assert_eq!(bus.open_bus, value);
+        assert_eq!(bus.read(0x5000), value);
+    }
 }
+
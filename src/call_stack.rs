@@ -0,0 +1,152 @@
+use crate::cpu::Registers;
+
+const BRK: u8 = 0x00;
+const PHP: u8 = 0x08;
+const JSR: u8 = 0x20;
+const PLP: u8 = 0x28;
+const RTI: u8 = 0x40;
+const PHA: u8 = 0x48;
+const RTS: u8 = 0x60;
+const PLA: u8 = 0x68;
+
+const MAX_PROBLEMS: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum FrameKind {
+    Subroutine,
+    Brk,
+    Nmi,
+    Irq,
+}
+
+impl FrameKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameKind::Subroutine => "JSR",
+            FrameKind::Brk => "BRK",
+            FrameKind::Nmi => "NMI",
+            FrameKind::Irq => "IRQ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StackFrame {
+    pub kind: FrameKind,
+    pub call_addr: u16,   // The JSR or BRK, or the instruction an interrupt came before
+    pub entry: u16,       // Subroutine or handler address
+    pub return_addr: u16, // Where the matching RTS or RTI should land
+    pub sp: u8,           // SP before the call, which the return restores
+}
+
+// Shadow of the hardware stack built from calls, interrupts and returns.
+// Frames are dropped once SP moves back above them, however that happens
+// (return, PLA pairs, TXS), so the shadow stays in step with the real one.
+pub struct CallStack {
+    pub frames: Vec<StackFrame>,
+    pub problems: Vec<String>, // Mismatched returns and stack wraps, oldest first
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack {
+            frames: Vec::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn report(&mut self, problem: String) {
+        if self.problems.contains(&problem) {
+            return;
+        }
+        if self.problems.len() == MAX_PROBLEMS {
+            self.problems.remove(0);
+        }
+        self.problems.push(problem);
+    }
+
+    fn unwind(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    // Called after each instruction with the PC and SP it started with
+    pub fn on_instruction(&mut self, opcode: u8, pc: u16, sp_before: u8, registers: &Registers) {
+        let sp = registers.sp;
+        match opcode {
+            JSR | PHA | PHP | BRK if sp > sp_before => {
+                self.report(format!("Stack overflow at ${:04X}: SP wrapped from ${:02X} to ${:02X}", pc, sp_before, sp));
+            }
+            RTS | RTI | PLA | PLP if sp < sp_before => {
+                self.report(format!("Stack underflow at ${:04X}: SP wrapped from ${:02X} to ${:02X}", pc, sp_before, sp));
+            }
+            _ => {}
+        }
+
+        match opcode {
+            JSR => self.frames.push(StackFrame {
+                kind: FrameKind::Subroutine,
+                call_addr: pc,
+                entry: registers.pc,
+                return_addr: pc.wrapping_add(3),
+                sp: sp_before,
+            }),
+
+            BRK => self.frames.push(StackFrame {
+                kind: FrameKind::Brk,
+                call_addr: pc,
+                entry: registers.pc,
+                return_addr: pc.wrapping_add(2),
+                sp: sp_before,
+            }),
+
+            RTS | RTI => {
+                self.check_return(opcode, pc, registers);
+                self.unwind(sp);
+            }
+
+            _ => self.unwind(sp),
+        }
+    }
+
+    pub fn on_interrupt(&mut self, kind: FrameKind, return_addr: u16, sp_before: u8, registers: &Registers) {
+        if registers.sp > sp_before {
+            self.report(format!("Stack overflow in {} at ${:04X}: SP wrapped from ${:02X} to ${:02X}",
+                kind.name(), return_addr, sp_before, registers.sp));
+        }
+        self.frames.push(StackFrame {
+            kind,
+            call_addr: return_addr,
+            entry: registers.pc,
+            return_addr,
+            sp: sp_before,
+        });
+    }
+
+    // A return should pop exactly the innermost frame, of its own kind, and
+    // land where that frame's call would resume
+    fn check_return(&mut self, opcode: u8, pc: u16, registers: &Registers) {
+        let name = if opcode == RTS { "RTS" } else { "RTI" };
+        let Some(frame) = self.frames.last().copied() else {
+            return;
+        };
+
+        if registers.sp < frame.sp {
+            // Still inside the frame: a return used as a jump (pushed address + RTS)
+            self.report(format!("{} at ${:04X} to ${:04X} without a matching call", name, pc, registers.pc));
+            return;
+        }
+
+        let popped = self.frames.iter().rev().take_while(|frame| frame.sp <= registers.sp).count();
+        let kind_matches = (opcode == RTS) == (frame.kind == FrameKind::Subroutine);
+        if popped > 1 || !kind_matches || frame.return_addr != registers.pc || frame.sp != registers.sp {
+            self.report(format!("{} at ${:04X} returned to ${:04X}, expected ${:04X} ({} at ${:04X})",
+                name, pc, registers.pc, frame.return_addr, frame.kind.name(), frame.call_addr));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::symbols::SymbolTable;
+
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 520;
+const LINE_HEIGHT: i32 = 18;
+
+const PROBLEM_LINES: usize = 10;
+const PROBLEMS_Y: i32 = WINDOW_HEIGHT as i32 - (PROBLEM_LINES as i32 + 1) * LINE_HEIGHT - 4;
+
+// The CPU's shadow call stack, innermost frame first, above the mismatched
+// returns and stack wraps it noticed
+pub struct CallStackViewer {
+    window: DebugWindow,
+}
+
+fn address(symbols: &SymbolTable, bus: &Bus, addr: u16) -> String {
+    match symbols.describe(bus, addr) {
+        Some(name) => format!("${:04X} {}", addr, name),
+        None => format!("${:04X}", addr),
+    }
+}
+
+impl CallStackViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(CallStackViewer {
+            window: DebugWindow::open(video, "Call stack (C: clear problems)", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+        })
+    }
+}
+
+impl DebugView for CallStackViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, _debugger: &mut Debugger) {
+        if let Event::KeyDown { keycode: Some(Keycode::C), repeat: false, .. } = event
+            && let Some(stack) = cpu.call_stack.as_mut() {
+            stack.problems.clear();
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, debugger: &Debugger, font: &mut Font) {
+        let white = Color::RGB(255, 255, 255);
+        let grey = Color::RGB(160, 160, 160);
+
+        self.window.canvas.set_draw_color(Color::RGB(24, 24, 24));
+        self.window.canvas.clear();
+
+        let symbols = &debugger.symbols;
+        let bus = &*cpu.bus;
+        let mut lines = vec![
+            (format!("PC {}  SP ${:02X}", address(symbols, bus, cpu.registers.pc), cpu.registers.sp), white),
+            (String::new(), white),
+        ];
+
+        let (frames, problems) = match &cpu.call_stack {
+            Some(stack) => (stack.frames.as_slice(), stack.problems.as_slice()),
+            None => (&[][..], &[][..]),
+        };
+        if frames.is_empty() {
+            lines.push(("No calls on the stack".to_string(), grey));
+        }
+        for (depth, frame) in frames.iter().rev().enumerate() {
+            lines.push((format!("#{:<2} {} {}", depth, frame.kind.name(), address(symbols, bus, frame.entry)), white));
+            lines.push((format!("      from {}  SP ${:02X}", address(symbols, bus, frame.call_addr), frame.sp), grey));
+        }
+
+        let max_lines = (PROBLEMS_Y / LINE_HEIGHT) as usize;
+        if lines.len() > max_lines {
+            let hidden = lines.len() - max_lines + 1;
+            lines.truncate(max_lines - 1);
+            lines.push((format!("... {} more lines", hidden), grey));
+        }
+        for (i, (text, color)) in lines.iter().enumerate() {
+            self.window.draw_text(font, text, *color, 4, i as i32 * LINE_HEIGHT);
+        }
+
+        self.window.draw_text(font, &format!("Problems: {}", problems.len()), white, 4, PROBLEMS_Y);
+        let recent = &problems[problems.len().saturating_sub(PROBLEM_LINES)..];
+        for (i, problem) in recent.iter().enumerate() {
+            self.window.draw_text(font, problem, Color::RGB(255, 96, 96), 4, PROBLEMS_Y + (i as i32 + 1) * LINE_HEIGHT);
+        }
+
+        self.window.canvas.present();
+    }
+}
@@ -0,0 +1,225 @@
+use crate::bus::Bus;
+use crate::cpu::AddrMode;
+
+// FCEUX .cdl layout: one flag byte per PRG-ROM byte, followed by one per
+// CHR-ROM byte (none for CHR-RAM boards).
+// PRG flags are xPdcAADC: AA is the 8KB CPU window ($8000, $A000, $C000,
+// $E000) of the last access.
+pub const PRG_CODE: u8 = 0x01;
+pub const PRG_DATA: u8 = 0x02;
+const PRG_WINDOW_MASK: u8 = 0x0C;
+pub const PRG_INDIRECT_CODE: u8 = 0x10; // Target of JMP ($nnnn)
+pub const PRG_INDIRECT_DATA: u8 = 0x20; // Read through ($nn,X) or ($nn),Y
+pub const PRG_PCM: u8 = 0x40;           // Fetched by the DMC
+
+pub const CHR_DRAWN: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02; // Read by the CPU through $2007
+
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+
+    // Bytes of the instruction being executed; PRG reads inside them are code
+    instruction_start: u16,
+    instruction_length: u16,
+    indirect_data: bool,
+    indirect_jump: bool, // The instruction was reached through JMP ($nnnn)
+    previous_mode: AddrMode,
+
+    pub dmc_fetch: bool,
+    pub dummy_read: bool, // Reads the CPU makes and throws away say nothing about the byte
+}
+
+impl CodeDataLog {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+
+            instruction_start: 0,
+            instruction_length: 0,
+            indirect_data: false,
+            indirect_jump: false,
+            previous_mode: AddrMode::Implied,
+
+            dmc_fetch: false,
+            dummy_read: false,
+        }
+    }
+
+    // Continues an existing log of the same ROM
+    pub fn load(path: &str, prg_len: usize, chr_len: usize) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if data.len() != prg_len + chr_len {
+            return Err(format!("{} is {} bytes, expected {} for this ROM", path, data.len(), prg_len + chr_len));
+        }
+
+        let mut log = Self::new(prg_len, chr_len);
+        log.prg.copy_from_slice(&data[..prg_len]);
+        log.chr.copy_from_slice(&data[prg_len..]);
+        Ok(log)
+    }
+
+    // Starts logging on the bus, continuing the log in `path` if there is one
+    pub fn start(bus: &mut Bus, path: Option<&str>) -> Result<(), String> {
+        let prg_len = bus.rom.prg_rom.len();
+        let chr_len = if bus.rom.chr_ram { 0 } else { bus.rom.chr_rom.len() };
+        bus.cdl = Some(match path {
+            Some(path) if std::path::Path::new(path).exists() => Self::load(path, prg_len, chr_len)?,
+            _ => Self::new(prg_len, chr_len),
+        });
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut data = self.prg.clone();
+        data.extend_from_slice(&self.chr);
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn begin_instruction(&mut self, pc: u16, mode: AddrMode) {
+        self.instruction_start = pc;
+        self.instruction_length = 1 + mode.operand_length();
+        self.indirect_data = matches!(mode, AddrMode::IndirectX | AddrMode::IndirectY);
+        self.indirect_jump = self.previous_mode == AddrMode::Indirect;
+        self.previous_mode = mode;
+    }
+
+    // A CPU read of PRG-ROM byte `index`, mapped at `addr`
+    pub fn log_prg_read(&mut self, addr: u16, index: usize) {
+        if self.dummy_read {
+            return;
+        }
+        let mut flags = ((addr >> 13) & 0x03) as u8 * 4;
+        if self.dmc_fetch {
+            flags |= PRG_DATA | PRG_PCM;
+        } else if addr.wrapping_sub(self.instruction_start) < self.instruction_length {
+            flags |= PRG_CODE;
+            if self.indirect_jump && addr == self.instruction_start {
+                flags |= PRG_INDIRECT_CODE;
+            }
+        } else {
+            flags |= PRG_DATA;
+            if self.indirect_data {
+                flags |= PRG_INDIRECT_DATA;
+            }
+        }
+
+        if let Some(entry) = self.prg.get_mut(index) {
+            *entry = (*entry & !PRG_WINDOW_MASK) | flags;
+        }
+    }
+
+    pub fn log_chr(&mut self, index: usize, flag: u8) {
+        if let Some(entry) = self.chr.get_mut(index) {
+            *entry |= flag;
+        }
+    }
+
+    // Logged as data and never executed
+    pub fn is_data(&self, index: usize) -> bool {
+        self.prg.get(index).is_some_and(|flags| flags & (PRG_CODE | PRG_DATA) == PRG_DATA)
+    }
+
+    pub fn summary(&self) -> String {
+        let count = |bytes: &[u8], mask: u8| bytes.iter().filter(|flags| *flags & mask != 0).count();
+        let percent = |n: usize, total: usize| if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 };
+
+        let code = count(&self.prg, PRG_CODE);
+        let data = count(&self.prg, PRG_DATA);
+        let unknown = self.prg.iter().filter(|flags| **flags & (PRG_CODE | PRG_DATA) == 0).count();
+        let mut text = format!("PRG: {} code ({:.1}%), {} data ({:.1}%), {} unlogged",
+            code, percent(code, self.prg.len()), data, percent(data, self.prg.len()), unknown);
+        if !self.chr.is_empty() {
+            let drawn = count(&self.chr, CHR_DRAWN);
+            let read = count(&self.chr, CHR_READ);
+            text += &format!("\nCHR: {} drawn ({:.1}%), {} read", drawn, percent(drawn, self.chr.len()), read);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::ines_file::test_rom;
+    use crate::region::Region;
+    use crate::runner;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn logs_code_data_and_the_cpu_window() {
+        // LDA $C010; JMP ($8020) -> $8000
+        let mut bus = Bus::new(test_rom(&[0xAD, 0x10, 0xC0, 0x6C, 0x20, 0x80], &[0x40]), Region::Ntsc);
+        bus.rom.prg_rom[0x20..0x22].copy_from_slice(&[0x00, 0x80]);
+        CodeDataLog::start(&mut bus, None).unwrap();
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        for _ in 0..3 {
+            runner::step_instruction(&mut cpu);
+        }
+
+        let cdl = cpu.bus.cdl.as_ref().unwrap();
+        assert_eq!(cdl.prg.len(), 16 * 1024);
+        assert_eq!(cdl.chr.len(), 8 * 1024);
+        assert_eq!(cdl.prg[0x01], PRG_CODE);
+        assert_eq!(cdl.prg[0x00], PRG_CODE | PRG_INDIRECT_CODE);
+        // Read through the $C000 mirror, the third 8KB window
+        assert_eq!(cdl.prg[0x10], PRG_DATA | 0x08);
+        assert_eq!(cdl.prg[0x20], PRG_DATA);
+        assert!(cdl.is_data(0x10));
+        assert!(!cdl.is_data(0x00));
+        assert!(!cdl.is_data(0x30));
+    }
+
+    #[test]
+    fn ignores_dummy_reads_when_cycle_stepped() {
+        // NOP; LDA $80F0,X with X = $20, which first reads $8010 from the wrong page
+        let mut bus = Bus::new(test_rom(&[0xEA, 0xBD, 0xF0, 0x80], &[0x40]), Region::Ntsc);
+        CodeDataLog::start(&mut bus, None).unwrap();
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        cpu.cycle_stepped = true;
+        cpu.registers.x = 0x20;
+        for _ in 0..2 {
+            runner::step_instruction(&mut cpu);
+        }
+
+        let cdl = cpu.bus.cdl.as_ref().unwrap();
+        // The NOP reads the next opcode once and throws it away
+        assert_eq!(cdl.prg[0x01], PRG_CODE);
+        assert_eq!(cdl.prg[0x10], 0);
+        assert_eq!(cdl.prg[0x110], PRG_DATA);
+        assert_eq!(cdl.prg[0x04], 0);
+    }
+
+    #[test]
+    fn saves_and_continues_a_log() {
+        let path = temp_path("nes_cdl_round_trip.cdl");
+        let mut log = CodeDataLog::new(4, 2);
+        log.prg.copy_from_slice(&[PRG_CODE, PRG_DATA | PRG_PCM, 0, PRG_CODE | 0x0C]);
+        log.log_chr(1, CHR_DRAWN);
+        log.save(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [0x01, 0x42, 0x00, 0x0D, 0x00, 0x01]);
+
+        let loaded = CodeDataLog::load(&path, 4, 2).unwrap();
+        assert_eq!(loaded.prg, log.prg);
+        assert_eq!(loaded.chr, log.chr);
+    }
+
+    #[test]
+    fn refuses_a_log_of_another_size() {
+        let path = temp_path("nes_cdl_wrong_size.cdl");
+        CodeDataLog::new(4, 0).save(&path).unwrap();
+        assert!(CodeDataLog::load(&path, 4, 2).is_err());
+        assert!(CodeDataLog::load(&temp_path("nes_cdl_missing.cdl"), 4, 0).is_err());
+
+        let mut bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        assert!(CodeDataLog::start(&mut bus, Some(&path)).is_err());
+        assert!(bus.cdl.is_none());
+    }
+}
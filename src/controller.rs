@@ -1,3 +1,5 @@
+use crate::savestate::{StateReader, StateWriter};
+
 pub mod Button {
     pub const A: u8 = 1 << 7;
     pub const B: u8 = 1 << 6;
@@ -48,7 +50,8 @@ impl Controller {
             self.index += 1;
         }
 
-        value | 0x40
+        // Bits 1-7 are not driven, the bus adds them
+        value
     }
 
     pub fn set_button(&mut self, button_mask: u8, pressed: bool) {
@@ -58,4 +61,17 @@ impl Controller {
             self.buttons &= !button_mask;
         }
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.buttons);
+        writer.write_u8(self.index);
+        writer.write_u8(self.strobe);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.buttons = reader.read_u8()?;
+        self.index = reader.read_u8()?;
+        self.strobe = reader.read_u8()?;
+        Ok(())
+    }
 }
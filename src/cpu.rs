@@ -1,4 +1,7 @@
 use crate::Bus;
+use crate::call_stack::{CallStack, FrameKind};
+use crate::profiler::Profiler;
+use crate::savestate::{StateReader, StateWriter};
 
 pub struct Registers {
     pub a: u8,  // Acc
@@ -14,16 +17,69 @@ pub struct CPU<'lifetime> {
     pub registers: Registers,
     pub bus: &'lifetime mut Bus,
 
+    // Cycle-stepped mode: every bus access (dummy ones included) ticks the PPU
+    // and APU first, so it lands on its real cycle. Otherwise instructions run
+    // atomically and the caller catches the PPU and APU up afterwards.
+    pub cycle_stepped: bool,
+
+    // NMIs taken so far, for the debugger's run-to-NMI
+    pub nmi_count: usize,
+
+    // Shadow call stack, kept only while something asks for it
+    pub call_stack: Option<CallStack>,
+
+    // Cycle profiler, fed by runner::step_instruction while it is running
+    pub profiler: Option<Profiler>,
+
     lookup_table: Vec<Instruction>,
     
     addr_abs: u16,
     addr_rel: u8,
     fetched_data: u8,
+    page_crossed: bool,
+
+    // Interrupt lines as sampled on the latest cycle, and as sampled on the
+    // cycle before it, which is what counts when an instruction ends. NMI is
+    // edge triggered, so the last /NMI level is kept to spot it going active.
+    nmi_line: bool,
+    nmi_detected: bool,
+    irq_line: bool,
+    nmi_pending: bool,
+    irq_pending: bool,
 }
 
 pub struct Instruction {
     pub name: &'static str,
     pub cycles: u8,
+    pub mode: AddrMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddrMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddrMode {
+    // Operand bytes following the opcode
+    pub fn operand_length(&self) -> u16 {
+        match self {
+            AddrMode::Implied | AddrMode::Accumulator => 0,
+            AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => 2,
+            _ => 1,
+        }
+    }
 }
 
 pub enum Flag {
@@ -48,9 +104,20 @@ impl<'lifetime> CPU <'lifetime> {
                 f: 0x24,
             },
             bus, 
+            cycle_stepped: false,
+            nmi_count: 0,
+            call_stack: None,
+            profiler: None,
             addr_abs: 0x0000,
             addr_rel: 0x00,
             fetched_data: 0x00,
+            page_crossed: false,
+
+            nmi_line: false,
+            nmi_detected: false,
+            irq_line: false,
+            nmi_pending: false,
+            irq_pending: false,
 
             lookup_table: Instruction::lookup_table(),
         }
@@ -62,6 +129,10 @@ impl<'lifetime> CPU <'lifetime> {
         self.registers.y = 0;
         self.registers.sp = 0xFD;
         self.registers.f = 0x24;
+
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.clear();
+        }
         
         self.addr_abs = 0xFFFC;
         self.addr_rel = 0x00;
@@ -77,52 +148,160 @@ impl<'lifetime> CPU <'lifetime> {
         self.cycles += 8;
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.cycles as u64);
+        writer.write_u8(self.registers.a);
+        writer.write_u8(self.registers.x);
+        writer.write_u8(self.registers.y);
+        writer.write_u16(self.registers.pc);
+        writer.write_u8(self.registers.sp);
+        writer.write_u8(self.registers.f);
+
+        writer.write_u16(self.addr_abs);
+        writer.write_u8(self.addr_rel);
+        writer.write_u8(self.fetched_data);
+
+        writer.write_bool(self.nmi_line);
+        writer.write_bool(self.nmi_detected);
+        writer.write_bool(self.irq_line);
+        writer.write_bool(self.nmi_pending);
+        writer.write_bool(self.irq_pending);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.cycles = reader.read_u64()? as usize;
+        self.registers.a = reader.read_u8()?;
+        self.registers.x = reader.read_u8()?;
+        self.registers.y = reader.read_u8()?;
+        self.registers.pc = reader.read_u16()?;
+        self.registers.sp = reader.read_u8()?;
+        self.registers.f = reader.read_u8()?;
+
+        self.addr_abs = reader.read_u16()?;
+        self.addr_rel = reader.read_u8()?;
+        self.fetched_data = reader.read_u8()?;
+
+        self.nmi_line = reader.read_bool()?;
+        self.nmi_detected = reader.read_bool()?;
+        self.irq_line = reader.read_bool()?;
+        self.nmi_pending = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+
+        // The shadow call stack is not saved; it starts over from here
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.clear();
+        }
+        Ok(())
+    }
+
+    // Ticks the PPU and APU for one CPU cycle and samples the interrupt lines.
+    // The 6502 acts on what it saw up to the second-to-last cycle of an
+    // instruction, so an NMI raised on the last cycle waits one more instruction.
+    pub fn clock(&mut self) {
+        self.nmi_pending = self.nmi_detected;
+        self.irq_pending = self.irq_line;
+
+        let nmi_line = self.bus.tick();
+        if nmi_line && !self.nmi_line {
+            self.nmi_detected = true;
+        }
+        self.nmi_line = nmi_line;
+        self.irq_line = self.bus.apu.irq();
+    }
+
+    // Runs the interrupt sequence polled during the last instruction, if any.
+    // Returns the cycles it took.
+    pub fn service_interrupts(&mut self) -> u8 {
+        let start = self.cycles;
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.nmi_detected = false;
+            self.nmi();
+        } else if self.irq_pending && self.get_flag(Flag::I) == 0 {
+            self.irq_pending = false;
+            self.irq();
+        }
+
+        (self.cycles - start) as u8
+    }
+
     pub fn irq(&mut self) {
         if self.get_flag(Flag::I) == 0 {
-            self.bus.write(0x0100 + self.registers.sp as u16, (((self.registers.pc as u16) >> 8) & 0x00FF) as u8);
+            let (return_addr, sp_before) = (self.registers.pc, self.registers.sp);
+            self.dummy_read(self.registers.pc);
+            self.dummy_read(self.registers.pc);
+
+            self.write(0x0100 + self.registers.sp as u16, (((self.registers.pc as u16) >> 8) & 0x00FF) as u8);
             self.registers.sp = self.registers.sp.wrapping_sub(1);
-            self.bus.write(0x0100 + self.registers.sp as u16, ((self.registers.pc as u16) & 0x00FF) as u8);
+            self.write(0x0100 + self.registers.sp as u16, ((self.registers.pc as u16) & 0x00FF) as u8);
             self.registers.sp = self.registers.sp.wrapping_sub(1);
 
             self.set_flag(Flag::B, false);
             self.set_flag(Flag::U, true);
             self.set_flag(Flag::I, true);
 
-            self.bus.write(0x0100 + self.registers.sp as u16, self.registers.f);
+            self.write(0x0100 + self.registers.sp as u16, self.registers.f);
             self.registers.sp = self.registers.sp.wrapping_sub(1);
 
             self.addr_abs = 0xFFFE;
-            let low = self.bus.read(self.addr_abs);
-            let high = self.bus.read(self.addr_abs.wrapping_add(1));
+            let low = self.read(self.addr_abs);
+            let high = self.read(self.addr_abs.wrapping_add(1));
             self.registers.pc = ((high as u16) << 8) | low as u16;
 
-            self.cycles += 7;
+            if let Some(stack) = self.call_stack.as_mut() {
+                stack.on_interrupt(FrameKind::Irq, return_addr, sp_before, &self.registers);
+            }
+
         }
     }
 
     pub fn nmi(&mut self) {
-        self.bus.write(0x0100 + self.registers.sp as u16, (((self.registers.pc as u16) >> 8) & 0x00FF) as u8);
+        self.nmi_count += 1;
+        let (return_addr, sp_before) = (self.registers.pc, self.registers.sp);
+
+        self.dummy_read(self.registers.pc);
+        self.dummy_read(self.registers.pc);
+
+        self.write(0x0100 + self.registers.sp as u16, (((self.registers.pc as u16) >> 8) & 0x00FF) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.bus.write(0x0100 + self.registers.sp as u16, ((self.registers.pc as u16) & 0x00FF) as u8);
+        self.write(0x0100 + self.registers.sp as u16, ((self.registers.pc as u16) & 0x00FF) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         self.set_flag(Flag::B, false);
         self.set_flag(Flag::U, true);
         self.set_flag(Flag::I, true);
 
-        self.bus.write(0x0100 + self.registers.sp as u16, self.registers.f);
+        self.write(0x0100 + self.registers.sp as u16, self.registers.f);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         self.addr_abs = 0xFFFA;
-        let low = self.bus.read(self.addr_abs);
-        let high = self.bus.read(self.addr_abs.wrapping_add(1));
+        let low = self.read(self.addr_abs);
+        let high = self.read(self.addr_abs.wrapping_add(1));
         self.registers.pc = ((high as u16) << 8) | low as u16;
 
-        self.cycles += 7;
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.on_interrupt(FrameKind::Nmi, return_addr, sp_before, &self.registers);
+        }
+
     }
 
-    pub fn step(&mut self) -> u8 {  // Return the cicles count
-        let opcode = self.bus.read(self.registers.pc);
+    pub fn step(&mut self) -> usize {  // Return the cicles count
+        let start = self.cycles;
+
+        // Without per-access timing, a pending DMC fetch is served between instructions
+        if !self.cycle_stepped && let Some(addr) = self.bus.apu.dmc_fetch_address() {
+            self.dmc_dma(addr, if self.cycles.is_multiple_of(2) { 3 } else { 4 });
+        }
+
+        let (pc_before, sp_before) = (self.registers.pc, self.registers.sp);
+        if self.bus.cdl.is_some() {
+            let mode = self.lookup_table[self.bus.peek(pc_before) as usize].mode;
+            if let Some(cdl) = self.bus.cdl.as_mut() {
+                cdl.begin_instruction(pc_before, mode);
+            }
+        }
+        let opcode = self.read(self.registers.pc);
         // println!("PC: {:04X}", self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
@@ -218,10 +397,10 @@ impl<'lifetime> CPU <'lifetime> {
             // STA (Store não usa fetch, apenas escreve)
             0x81 => { self.izx(); self.sta(); }
             0x8D => { self.abs(); self.sta(); }
-            0x91 => { self.izy(); self.sta(); }
+            0x91 => { self.izy(); self.indexed_write_cycle(); self.sta(); }
             0x95 => { self.zpx(); self.sta(); }
-            0x99 => { self.aby(); self.sta(); }
-            0x9D => { self.abx(); self.sta(); }
+            0x99 => { self.aby(); self.indexed_write_cycle(); self.sta(); }
+            0x9D => { self.abx(); self.indexed_write_cycle(); self.sta(); }
 
             // STX
             0x8E => { self.abs(); self.stx(); }
@@ -246,13 +425,13 @@ impl<'lifetime> CPU <'lifetime> {
             0xE6 => { self.zp0(); self.fetch(self.addr_abs); self.inc(); }
             0xF6 => { self.zpx(); self.fetch(self.addr_abs); self.inc(); }
             0xEE => { self.abs(); self.fetch(self.addr_abs); self.inc(); }
-            0xFE => { self.abx(); self.fetch(self.addr_abs); self.inc(); }
+            0xFE => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.inc(); }
 
             // Decrement Memory (Requer fetch antes!)
             0xC6 => { self.zp0(); self.fetch(self.addr_abs); self.dec(); }
             0xD6 => { self.zpx(); self.fetch(self.addr_abs); self.dec(); }
             0xCE => { self.abs(); self.fetch(self.addr_abs); self.dec(); }
-            0xDE => { self.abx(); self.fetch(self.addr_abs); self.dec(); }
+            0xDE => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.dec(); }
 
             // Register Inc/Dec
             0xE8 => { self.imp(); self.inx(); }
@@ -269,28 +448,28 @@ impl<'lifetime> CPU <'lifetime> {
             0x06 => { self.zp0(); self.fetch(self.addr_abs); self.asl(false); }
             0x16 => { self.zpx(); self.fetch(self.addr_abs); self.asl(false); }
             0x0E => { self.abs(); self.fetch(self.addr_abs); self.asl(false); }
-            0x1E => { self.abx(); self.fetch(self.addr_abs); self.asl(false); }
+            0x1E => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.asl(false); }
             
             // LSR
             0x4A => { self.imp(); self.lsr(true); }
             0x46 => { self.zp0(); self.fetch(self.addr_abs); self.lsr(false); }
             0x56 => { self.zpx(); self.fetch(self.addr_abs); self.lsr(false); }
             0x4E => { self.abs(); self.fetch(self.addr_abs); self.lsr(false); }
-            0x5E => { self.abx(); self.fetch(self.addr_abs); self.lsr(false); }
+            0x5E => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.lsr(false); }
 
             // ROL
             0x2A => { self.imp(); self.rol(true); }
             0x26 => { self.zp0(); self.fetch(self.addr_abs); self.rol(false); }
             0x36 => { self.zpx(); self.fetch(self.addr_abs); self.rol(false); }
             0x2E => { self.abs(); self.fetch(self.addr_abs); self.rol(false); }
-            0x3E => { self.abx(); self.fetch(self.addr_abs); self.rol(false); }
+            0x3E => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.rol(false); }
 
             // ROR
             0x6A => { self.imp(); self.ror(true); }
             0x66 => { self.zp0(); self.fetch(self.addr_abs); self.ror(false); }
             0x76 => { self.zpx(); self.fetch(self.addr_abs); self.ror(false); }
             0x6E => { self.abs(); self.fetch(self.addr_abs); self.ror(false); }
-            0x7E => { self.abx(); self.fetch(self.addr_abs); self.ror(false); }
+            0x7E => { self.abx(); self.indexed_write_cycle(); self.fetch(self.addr_abs); self.ror(false); }
 
             0x05 => {
                 self.zp0();
@@ -320,7 +499,6 @@ impl<'lifetime> CPU <'lifetime> {
             }
 
             0x20 => {
-                self.abs();
                 self.jsr();
             }
 
@@ -377,6 +555,11 @@ impl<'lifetime> CPU <'lifetime> {
                 self.bvc();
             }
 
+            0x58 => {
+                self.imp();
+                self.cli();
+            }
+
             0x60 => {
                 self.imp();
                 self.rts();
@@ -517,16 +700,125 @@ impl<'lifetime> CPU <'lifetime> {
             }
         }
 
-        self.cycles += self.lookup_table[opcode as usize].cycles as usize;
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.on_instruction(opcode, pc_before, sp_before, &self.registers);
+        }
+
+        if let Some(page) = self.bus.oam_dma.take() {
+            self.oam_dma(page);
+        }
         // println!("OPCODE: {:02X} | NAME: {} | Fetched data: {:02X} | Absolute Addr: {:04X} | Relative Addr: {:02X}", opcode, self.lookup_table[opcode as usize].name, self.fetched_data, self.addr_abs, self.addr_rel);
-        opcode
+        // OAM DMA alone is over 500 cycles
+        self.cycles - start
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if self.cycle_stepped {
+            // The DMC can only halt the CPU on a read cycle
+            if let Some(dmc_addr) = self.bus.apu.dmc_fetch_address() {
+                self.dmc_dma(dmc_addr, if self.cycles.is_multiple_of(2) { 3 } else { 4 });
+            }
+            self.clock();
+        }
+        self.cycles += 1;
+        self.bus.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if self.cycle_stepped {
+            self.clock();
+        }
+        self.cycles += 1;
+        self.bus.write(addr, data);
+    }
+
+    // A cycle the CPU spends halted; the PPU and APU keep running
+    fn idle_cycle(&mut self) {
+        if self.cycle_stepped {
+            self.clock();
+        }
+        self.cycles += 1;
+    }
+
+    // OAM DMA halts the CPU for 513 cycles, 514 when it starts on an odd one:
+    // a halt cycle, an alignment cycle if needed, then 256 read/write pairs.
+    fn oam_dma(&mut self, page: u8) {
+        self.idle_cycle();
+        if !self.cycles.is_multiple_of(2) {
+            self.idle_cycle();
+        }
+
+        for i in 0..256u16 {
+            // A DMC fetch landing in the middle of OAM DMA only costs 2 cycles
+            if let Some(addr) = self.bus.apu.dmc_fetch_address() {
+                self.dmc_dma(addr, 2);
+            }
+
+            self.idle_cycle();
+            let byte = self.bus.read(((page as u16) << 8) | i);
+            self.idle_cycle();
+            let index = self.bus.ppu.oam_addr.wrapping_add(i as u8) as usize;
+            self.bus.ppu.oam_data[index] = byte;
+        }
+    }
+
+    // The DMC steals `stall` cycles to fetch a sample byte, the last one being the fetch
+    fn dmc_dma(&mut self, addr: u16, stall: u8) {
+        for _ in 0..stall {
+            self.idle_cycle();
+        }
+        if let Some(cdl) = self.bus.cdl.as_mut() {
+            cdl.dmc_fetch = true;
+        }
+        let data = self.bus.read(addr);
+        if let Some(cdl) = self.bus.cdl.as_mut() {
+            cdl.dmc_fetch = false;
+        }
+        self.bus.apu.dmc_fill(data);
+    }
+
+    // Accesses the 6502 makes on cycles where it has nothing useful to do.
+    // They only happen in cycle-stepped mode, where their side effects land on
+    // the right cycle; otherwise they just take their cycle.
+    fn dummy_read(&mut self, addr: u16) {
+        if self.cycle_stepped {
+            if let Some(cdl) = self.bus.cdl.as_mut() {
+                cdl.dummy_read = true;
+            }
+            self.read(addr);
+            if let Some(cdl) = self.bus.cdl.as_mut() {
+                cdl.dummy_read = false;
+            }
+        } else {
+            self.cycles += 1;
+        }
+    }
+
+    fn dummy_write(&mut self, addr: u16, data: u8) {
+        if self.cycle_stepped {
+            self.write(addr, data);
+        } else {
+            self.cycles += 1;
+        }
+    }
+
+    // Indexed stores and read-modify-writes always spend the cycle that reads
+    // do only on a page cross
+    fn indexed_write_cycle(&mut self) {
+        if !self.page_crossed {
+            self.dummy_read(self.addr_abs);
+        }
     }
 
     fn fetch(&mut self, addr: u16) -> u8 {
-        self.fetched_data = self.bus.read(addr);
+        self.fetched_data = self.read(addr);
         self.fetched_data
     }
 
+    pub fn instruction(&self, opcode: u8) -> &Instruction {
+        &self.lookup_table[opcode as usize]
+    }
+
     pub fn get_flag(&self, f: Flag) -> u8 {
         if (self.registers.f & (f as u8)) != 0 {
             0x01
@@ -544,6 +836,7 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn imp(&mut self) -> (u16, u8) {
+        self.dummy_read(self.registers.pc);
         (0, 0) 
     }
 
@@ -554,30 +847,34 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn zp0(&mut self) -> (u16, u8) {
-        self.addr_abs = self.bus.read(self.registers.pc) as u16;
+        self.addr_abs = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs &= 0x00FF;
         (self.addr_abs, 0)
     }
 
     fn zpx(&mut self) -> (u16, u8) {
-        self.addr_abs = self.bus.read(self.registers.pc).wrapping_add(self.registers.x) as u16;
+        let base = self.read(self.registers.pc);
+        self.dummy_read(base as u16);
+        self.addr_abs = base.wrapping_add(self.registers.x) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs &= 0x00FF;
         (self.addr_abs, 4)
     }
 
     fn zpy(&mut self) -> (u16, u8) {
-        self.addr_abs = self.bus.read(self.registers.pc).wrapping_add(self.registers.y) as u16;
+        let base = self.read(self.registers.pc);
+        self.dummy_read(base as u16);
+        self.addr_abs = base.wrapping_add(self.registers.y) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs &= 0x00FF;
         (self.addr_abs, 4)
     }
 
     fn abs(&mut self) -> (u16, u8) {
-        let low = self.bus.read(self.registers.pc) as u16;
+        let low = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
-        let high = self.bus.read(self.registers.pc) as u16;
+        let high = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
         self.addr_abs = (high << 8) | low;
@@ -586,13 +883,19 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn abx(&mut self) -> (u16, u8) {
-        let low = self.bus.read(self.registers.pc) as u16;
+        let low = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
-        let high = self.bus.read(self.registers.pc) as u16;
+        let high = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
         self.addr_abs = (high << 8) | low;
         self.addr_abs = self.addr_abs.wrapping_add(self.registers.x as u16) as u16;
+
+        // The high byte is fixed up a cycle late, after a read from the wrong page
+        self.page_crossed = (self.addr_abs & 0xFF00) != (high << 8);
+        if self.page_crossed {
+            self.dummy_read((high << 8) | (self.addr_abs & 0x00FF));
+        }
         
         if (self.addr_abs & 0xFF00) != (high << 8) {
             return (self.addr_abs, 5);
@@ -602,13 +905,18 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn aby(&mut self) -> (u16, u8) {
-        let low = self.bus.read(self.registers.pc) as u16;
+        let low = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
-        let high = self.bus.read(self.registers.pc) as u16;
+        let high = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
         self.addr_abs = (high << 8) | low;
         self.addr_abs = self.addr_abs.wrapping_add(self.registers.y as u16) as u16;
+
+        self.page_crossed = (self.addr_abs & 0xFF00) != (high << 8);
+        if self.page_crossed {
+            self.dummy_read((high << 8) | (self.addr_abs & 0x00FF));
+        }
         
         if (self.addr_abs & 0xFF00) != (high << 8) {
             return (self.addr_abs, 5);
@@ -618,43 +926,49 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn ind(&mut self) -> (u16, u8) {
-        let ptr_low = self.bus.read(self.registers.pc) as u16;
+        let ptr_low = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
-        let ptr_high = self.bus.read(self.registers.pc) as u16;
+        let ptr_high = self.read(self.registers.pc) as u16;
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
         let ptr = (ptr_high << 8) | ptr_low;
 
         if ptr_low == 0x00FF {
-            self.addr_abs = (self.bus.read(ptr & 0xFF00) as u16) << 8 | self.bus.read(ptr) as u16;
+            self.addr_abs = (self.read(ptr & 0xFF00) as u16) << 8 | self.read(ptr) as u16;
         } else {
-            self.addr_abs = ((self.bus.read((ptr + 1) as u16) as u16) << 8 | self.bus.read(ptr) as u16) as u16;
+            self.addr_abs = ((self.read((ptr + 1) as u16) as u16) << 8 | self.read(ptr) as u16) as u16;
         }
 
         (self.addr_abs, 0)
     }
 
     fn izx(&mut self) -> (u16, u8) {
-        let t = self.bus.read(self.registers.pc);
+        let t = self.read(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.dummy_read(t as u16);
 
-        let low = self.bus.read(t.wrapping_add(self.registers.x) as u16) as u16;
-        let high = self.bus.read(t.wrapping_add(self.registers.x).wrapping_add(1) as u16) as u16;
+        let low = self.read(t.wrapping_add(self.registers.x) as u16) as u16;
+        let high = self.read(t.wrapping_add(self.registers.x).wrapping_add(1) as u16) as u16;
 
         self.addr_abs = (high << 8) | low;
         (self.addr_abs, 6)
     }
 
     fn izy(&mut self) -> (u16, u8) {
-        let t = self.bus.read(self.registers.pc);
+        let t = self.read(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
-        let low = self.bus.read(t as u16) as u16;
-        let high = self.bus.read(t.wrapping_add(1) as u16) as u16;
+        let low = self.read(t as u16) as u16;
+        let high = self.read(t.wrapping_add(1) as u16) as u16;
 
         self.addr_abs = (high << 8) | low;
         self.addr_abs = self.addr_abs.wrapping_add(self.registers.y as u16);
 
+        self.page_crossed = (self.addr_abs & 0xFF00) != (high << 8);
+        if self.page_crossed {
+            self.dummy_read((high << 8) | (self.addr_abs & 0x00FF));
+        }
+
         if (self.addr_abs & 0xFF00) != (high << 8) {
             (self.addr_abs, 6)
         } else {
@@ -663,7 +977,7 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn rel(&mut self) -> (u16, u8) {
-        self.addr_rel = self.bus.read(self.registers.pc);
+        self.addr_rel = self.read(self.registers.pc);
 
         self.registers.pc = self.registers.pc.wrapping_add(1);
         (self.addr_rel as u16, 2)
@@ -718,21 +1032,22 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn brk(&mut self) -> u8 {
+        self.dummy_read(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
-        self.bus.write(0x0100 + self.registers.sp as u16, ((self.registers.pc >> 8) & 0xFF) as u8);
+        self.write(0x0100 + self.registers.sp as u16, ((self.registers.pc >> 8) & 0xFF) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.bus.write(0x0100 + self.registers.sp as u16, (self.registers.pc & 0xFF) as u8);
+        self.write(0x0100 + self.registers.sp as u16, (self.registers.pc & 0xFF) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         let status_to_stack = self.registers.f | (Flag::B as u8) | (Flag::U as u8);
-        self.bus.write(0x0100 + self.registers.sp as u16, status_to_stack);
+        self.write(0x0100 + self.registers.sp as u16, status_to_stack);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         self.set_flag(Flag::I, true);
 
-        let low = self.bus.read(0xFFFE) as u16;
-        let high = self.bus.read(0xFFFF) as u16;
+        let low = self.read(0xFFFE) as u16;
+        let high = self.read(0xFFFF) as u16;
         self.registers.pc = (high << 8) | low;
 
         0
@@ -806,19 +1121,19 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn sta(&mut self) -> u8 {
-        self.bus.write(self.addr_abs, self.registers.a);
+        self.write(self.addr_abs, self.registers.a);
 
         return 0
     }
 
     fn stx(&mut self) -> u8 {
-        self.bus.write(self.addr_abs, self.registers.x);
+        self.write(self.addr_abs, self.registers.x);
 
         return 0
     }
 
     fn sty(&mut self) -> u8 {
-        self.bus.write(self.addr_abs, self.registers.y);
+        self.write(self.addr_abs, self.registers.y);
 
         return 0
     }
@@ -909,8 +1224,9 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn rti(&mut self) -> u8 {
+        self.dummy_read(0x0100 + self.registers.sp as u16);
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        let mut flags = self.bus.read(0x0100 + self.registers.sp as u16);
+        let mut flags = self.read(0x0100 + self.registers.sp as u16);
 
         flags |= Flag::U as u8;
         flags &= !(Flag::B as u8);
@@ -918,22 +1234,24 @@ impl<'lifetime> CPU <'lifetime> {
         self.registers.f = flags;
 
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        self.registers.pc = self.bus.read(0x0100 + self.registers.sp as u16) as u16;
+        self.registers.pc = self.read(0x0100 + self.registers.sp as u16) as u16;
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        self.registers.pc |= (self.bus.read(0x0100 + self.registers.sp as u16) as u16) << 8;
+        self.registers.pc |= (self.read(0x0100 + self.registers.sp as u16) as u16) << 8;
 
         return 0
         
     }
 
     fn rts(&mut self) -> u8 {
+        self.dummy_read(0x0100 + self.registers.sp as u16);
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        let low = self.bus.read(0x0100 + self.registers.sp as u16) as u16;
+        let low = self.read(0x0100 + self.registers.sp as u16) as u16;
 
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        let high = self.bus.read(0x0100 + self.registers.sp as u16) as u16;
+        let high = self.read(0x0100 + self.registers.sp as u16) as u16;
 
         self.registers.pc = ((high << 8) | low);
+        self.dummy_read(self.registers.pc);
         self.registers.pc = self.registers.pc + 1;
 
         return 0
@@ -958,7 +1276,8 @@ impl<'lifetime> CPU <'lifetime> {
     fn inc(&mut self) -> u8 {
         let mut value = self.fetched_data;
         value = value.wrapping_add(1);
-        self.bus.write(self.addr_abs, value);
+        self.dummy_write(self.addr_abs, self.fetched_data);
+        self.write(self.addr_abs, value);
     
         self.set_flag(Flag::Z, value == 0);
         self.set_flag(Flag::N, (value & 0x80) != 0);
@@ -985,7 +1304,8 @@ impl<'lifetime> CPU <'lifetime> {
     fn dec(&mut self) -> u8 {
         let mut value = self.fetched_data;
         value = value.wrapping_sub(1);
-        self.bus.write(self.addr_abs, value);
+        self.dummy_write(self.addr_abs, self.fetched_data);
+        self.write(self.addr_abs, value);
     
         self.set_flag(Flag::Z, value == 0);
         self.set_flag(Flag::N, (value & 0x80) != 0);
@@ -1003,7 +1323,8 @@ impl<'lifetime> CPU <'lifetime> {
         if is_mode_acc {
             self.registers.a = value;
         } else {
-            self.bus.write(self.addr_abs, value);
+            self.dummy_write(self.addr_abs, self.fetched_data);
+            self.write(self.addr_abs, value);
         }
 
         self.set_flag(Flag::Z, value == 0);
@@ -1022,7 +1343,8 @@ impl<'lifetime> CPU <'lifetime> {
         if is_mode_acc {
             self.registers.a = value;
         } else {
-            self.bus.write(self.addr_abs, value);
+            self.dummy_write(self.addr_abs, self.fetched_data);
+            self.write(self.addr_abs, value);
         }
 
         self.set_flag(Flag::Z, value == 0);
@@ -1042,7 +1364,8 @@ impl<'lifetime> CPU <'lifetime> {
         if is_mode_acc {
             self.registers.a = value;
         } else {
-            self.bus.write(self.addr_abs, value);
+            self.dummy_write(self.addr_abs, self.fetched_data);
+            self.write(self.addr_abs, value);
         }
 
         self.set_flag(Flag::Z, value == 0);
@@ -1062,7 +1385,8 @@ impl<'lifetime> CPU <'lifetime> {
         if is_mode_acc {
             self.registers.a = value;
         } else {
-            self.bus.write(self.addr_abs, value);
+            self.dummy_write(self.addr_abs, self.fetched_data);
+            self.write(self.addr_abs, value);
         }
 
         self.set_flag(Flag::Z, value == 0);
@@ -1073,35 +1397,35 @@ impl<'lifetime> CPU <'lifetime> {
 
     fn bcs(&mut self) -> u8 {
         if self.get_flag(Flag::C) == 1 {
-            self.registers.pc = self.registers.pc.wrapping_add((self.addr_rel as i8 as i16) as u16);
+            self.branch();
         }
         return 0
     }
 
     fn bcc(&mut self) -> u8 {
         if self.get_flag(Flag::C) == 0 {
-            self.registers.pc = self.registers.pc.wrapping_add((self.addr_rel as i8 as i16) as u16);
+            self.branch();
         }
         return 0
     }
 
     fn bvs(&mut self) -> u8 {
         if self.get_flag(Flag::V) == 1 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
         }
         return 0
     }
 
     fn bvc(&mut self) -> u8 {
         if self.get_flag(Flag::V) == 0 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
         }
         return 0
     }
     
     fn beq(&mut self) -> u8 {
         if self.get_flag(Flag::Z) == 1 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
             return 1
         }
         return 0
@@ -1109,7 +1433,7 @@ impl<'lifetime> CPU <'lifetime> {
 
     fn bne(&mut self) -> u8 {
         if self.get_flag(Flag::Z) != 1 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
             return 1
         }
         return 0
@@ -1117,7 +1441,7 @@ impl<'lifetime> CPU <'lifetime> {
 
     fn bpl(&mut self) -> u8 {
         if self.get_flag(Flag::N) == 0 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
             return 1
         }
         return 0
@@ -1125,20 +1449,39 @@ impl<'lifetime> CPU <'lifetime> {
 
     fn bmi(&mut self) -> u8 {
         if self.get_flag(Flag::N) != 0 {
-            self.registers.pc = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+            self.branch();
             return 1
         }
         return 0
     }
 
+    // Taken branches spend one more cycle, and another if they cross a page
+    fn branch(&mut self) {
+        let target = self.registers.pc.wrapping_add(self.addr_rel as i8 as i16 as u16);
+
+        self.dummy_read(self.registers.pc);
+        if (target & 0xFF00) != (self.registers.pc & 0xFF00) {
+            self.dummy_read((self.registers.pc & 0xFF00) | (target & 0x00FF));
+        }
+
+        self.registers.pc = target;
+    }
+
+    // JSR fetches the high byte of the target last, after pushing the address
+    // of that byte as the return address
     fn jsr(&mut self) -> u8 {
-        self.registers.pc = self.registers.pc.wrapping_sub(1);
+        let low = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
 
-        self.bus.write(0x0100 | self.registers.sp as u16, ((self.registers.pc & 0xFF00) >> 8) as u8);
+        self.dummy_read(0x0100 | self.registers.sp as u16);
+
+        self.write(0x0100 | self.registers.sp as u16, ((self.registers.pc & 0xFF00) >> 8) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.bus.write(0x0100 | self.registers.sp as u16, (self.registers.pc & 0x00FF) as u8);
+        self.write(0x0100 | self.registers.sp as u16, (self.registers.pc & 0x00FF) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
+        let high = self.read(self.registers.pc) as u16;
+        self.addr_abs = (high << 8) | low;
         self.registers.pc = self.addr_abs;
 
         return 0
@@ -1161,22 +1504,23 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn php(&mut self) -> u8 {
-        self.bus.write(0x0100 + self.registers.sp as u16, self.registers.f | (Flag::B as u8) | (Flag::U as u8));
+        self.write(0x0100 + self.registers.sp as u16, self.registers.f | (Flag::B as u8) | (Flag::U as u8));
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         return 0
     }
 
     fn pha(&mut self) -> u8 {
-        self.bus.write(0x0100 + self.registers.sp as u16, self.registers.a);
+        self.write(0x0100 + self.registers.sp as u16, self.registers.a);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
 
         return 0
     }
 
     fn pla(&mut self) -> u8 {
+        self.dummy_read(0x0100 + self.registers.sp as u16);
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        let result = self.bus.read(0x0100 + self.registers.sp as u16);
+        let result = self.read(0x0100 + self.registers.sp as u16);
 
         self.registers.a = result;
 
@@ -1187,8 +1531,9 @@ impl<'lifetime> CPU <'lifetime> {
     }
 
     fn plp(&mut self) -> u8 {
+        self.dummy_read(0x0100 + self.registers.sp as u16);
         self.registers.sp = self.registers.sp.wrapping_add(1);
-        let mut result = self.bus.read(0x0100 + self.registers.sp as u16);
+        let mut result = self.read(0x0100 + self.registers.sp as u16);
 
         result |= Flag::U as u8;
         result &= !(Flag::B as u8);
@@ -1206,1283 +1551,1741 @@ impl Instruction {
 Instruction {
     name: "BRK",
     cycles: 7,
+    mode: AddrMode::Implied,
 },
 // 0x01: ORA Indirect, X
 Instruction {
     name: "ORA",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x02: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x03: XXX Indirect, X (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0x04: XXX Zero Page (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x05: ORA Zero Page
 Instruction {
     name: "ORA",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x06: ASL Zero Page
 Instruction {
     name: "ASL",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x07: XXX Zero Page (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x08: PHP Implied
 Instruction {
     name: "PHP",
     cycles: 3,
+    mode: AddrMode::Implied,
 },
 // 0x09: ORA Immediate
 Instruction {
     name: "ORA",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x0A: ASL Accumulator
 Instruction {
     name: "ASL",
     cycles: 2,
+    mode: AddrMode::Accumulator,
 },
 // 0x0B: XXX Immediate (ANC Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x0C: XXX Absolute (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x0D: ORA Absolute
 Instruction {
     name: "ORA",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x0E: ASL Absolute
 Instruction {
     name: "ASL",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x0F: XXX Absolute (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x10: BPL Relative
 Instruction {
     name: "BPL",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0x11: ORA Indirect, Y
 Instruction {
     name: "ORA",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0x12: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x13: XXX Indirect, Y (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0x14: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x15: ORA Zero Page, X
 Instruction {
     name: "ORA",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x16: ASL Zero Page, X
 Instruction {
     name: "ASL",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x17: XXX Zero Page, X (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x18: CLC Implied
 Instruction {
     name: "CLC",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x19: ORA Absolute, Y
 Instruction {
     name: "ORA",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x1A: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x1B: XXX Absolute, Y (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x1C: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x1D: ORA Absolute, X
 Instruction {
     name: "ORA",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x1E: ASL Absolute, X
 Instruction {
     name: "ASL",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x1F: XXX Absolute, X (SLO Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x20: JSR Absolute
 Instruction {
     name: "JSR",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x21: AND Indirect, X
 Instruction {
     name: "AND",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x22: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x23: XXX Indirect, X (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0x24: BIT Zero Page
 Instruction {
     name: "BIT",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x25: AND Zero Page
 Instruction {
     name: "AND",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x26: ROL Zero Page
 Instruction {
     name: "ROL",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x27: XXX Zero Page (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x28: PLP Implied
 Instruction {
     name: "PLP",
     cycles: 4,
+    mode: AddrMode::Implied,
 },
 // 0x29: AND Immediate
 Instruction {
     name: "AND",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x2A: ROL Accumulator
 Instruction {
     name: "ROL",
     cycles: 2,
+    mode: AddrMode::Accumulator,
 },
 // 0x2B: XXX Immediate (ANC Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x2C: BIT Absolute
 Instruction {
     name: "BIT",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x2D: AND Absolute
 Instruction {
     name: "AND",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x2E: ROL Absolute
 Instruction {
     name: "ROL",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x2F: XXX Absolute (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x30: BMI Relative
 Instruction {
     name: "BMI",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0x31: AND Indirect, Y
 Instruction {
     name: "AND",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0x32: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x33: XXX Indirect, Y (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0x34: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x35: AND Zero Page, X
 Instruction {
     name: "AND",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x36: ROL Zero Page, X
 Instruction {
     name: "ROL",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x37: XXX Zero Page, X (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x38: SEC Implied
 Instruction {
     name: "SEC",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x39: AND Absolute, Y
 Instruction {
     name: "AND",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x3A: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x3B: XXX Absolute, Y (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x3C: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x3D: AND Absolute, X
 Instruction {
     name: "AND",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x3E: ROL Absolute, X
 Instruction {
     name: "ROL",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x3F: XXX Absolute, X (RLA Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x40: RTI Implied
 Instruction {
     name: "RTI",
     cycles: 6,
+    mode: AddrMode::Implied,
 },
 // 0x41: EOR Indirect, X
 Instruction {
     name: "EOR",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x42: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x43: XXX Indirect, X (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0x44: XXX Zero Page (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x45: EOR Zero Page
 Instruction {
     name: "EOR",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x46: LSR Zero Page
 Instruction {
     name: "LSR",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x47: XXX Zero Page (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x48: PHA Implied
 Instruction {
     name: "PHA",
     cycles: 3,
+    mode: AddrMode::Implied,
 },
 // 0x49: EOR Immediate
 Instruction {
     name: "EOR",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x4A: LSR Accumulator
 Instruction {
     name: "LSR",
     cycles: 2,
+    mode: AddrMode::Accumulator,
 },
 // 0x4B: XXX Immediate (ALR Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x4C: JMP Absolute
 Instruction {
     name: "JMP",
     cycles: 3,
+    mode: AddrMode::Absolute,
 },
 // 0x4D: EOR Absolute
 Instruction {
     name: "EOR",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x4E: LSR Absolute
 Instruction {
     name: "LSR",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x4F: XXX Absolute (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x50: BVC Relative
 Instruction {
     name: "BVC",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0x51: EOR Indirect, Y
 Instruction {
     name: "EOR",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0x52: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x53: XXX Indirect, Y (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0x54: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x55: EOR Zero Page, X
 Instruction {
     name: "EOR",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x56: LSR Zero Page, X
 Instruction {
     name: "LSR",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x57: XXX Zero Page, X (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x58: CLI Implied
 Instruction {
     name: "CLI",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x59: EOR Absolute, Y
 Instruction {
     name: "EOR",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x5A: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x5B: XXX Absolute, Y (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x5C: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x5D: EOR Absolute, X
 Instruction {
     name: "EOR",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x5E: LSR Absolute, X
 Instruction {
     name: "LSR",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x5F: XXX Absolute, X (SRE Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x60: RTS Implied
 Instruction {
     name: "RTS",
     cycles: 6,
+    mode: AddrMode::Implied,
 },
 // 0x61: ADC Indirect, X
 Instruction {
     name: "ADC",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x62: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x63: XXX Indirect, X (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0x64: XXX Zero Page (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x65: ADC Zero Page
 Instruction {
     name: "ADC",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x66: ROR Zero Page
 Instruction {
     name: "ROR",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x67: XXX Zero Page (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0x68: PLA Implied
 Instruction {
     name: "PLA",
     cycles: 4,
+    mode: AddrMode::Implied,
 },
 // 0x69: ADC Immediate
 Instruction {
     name: "ADC",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x6A: ROR Accumulator
 Instruction {
     name: "ROR",
     cycles: 2,
+    mode: AddrMode::Accumulator,
 },
 // 0x6B: XXX Immediate (ARR Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x6C: JMP Indirect
 Instruction {
     name: "JMP",
     cycles: 5,
+    mode: AddrMode::Indirect,
 },
 // 0x6D: ADC Absolute
 Instruction {
     name: "ADC",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x6E: ROR Absolute
 Instruction {
     name: "ROR",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x6F: XXX Absolute (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0x70: BVS Relative
 Instruction {
     name: "BVS",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0x71: ADC Indirect, Y
 Instruction {
     name: "ADC",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0x72: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x73: XXX Indirect, Y (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0x74: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x75: ADC Zero Page, X
 Instruction {
     name: "ADC",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x76: ROR Zero Page, X
 Instruction {
     name: "ROR",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x77: XXX Zero Page, X (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x78: SEI Implied
 Instruction {
     name: "SEI",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x79: ADC Absolute, Y
 Instruction {
     name: "ADC",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x7A: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x7B: XXX Absolute, Y (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x7C: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x7D: ADC Absolute, X
 Instruction {
     name: "ADC",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x7E: ROR Absolute, X
 Instruction {
     name: "ROR",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x7F: XXX Absolute, X (RRA Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x80: XXX Immediate (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x81: STA Indirect, X
 Instruction {
     name: "STA",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x82: XXX Immediate (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x83: XXX Indirect, X (SAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0x84: STY Zero Page
 Instruction {
     name: "STY",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x85: STA Zero Page
 Instruction {
     name: "STA",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x86: STX Zero Page
 Instruction {
     name: "STX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x87: XXX Zero Page (SAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0x88: DEY Implied
 Instruction {
     name: "DEY",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x89: XXX Immediate (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x8A: TXA Implied
 Instruction {
     name: "TXA",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x8B: XXX Immediate (XAA Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0x8C: STY Absolute
 Instruction {
     name: "STY",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x8D: STA Absolute
 Instruction {
     name: "STA",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x8E: STX Absolute
 Instruction {
     name: "STX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x8F: XXX Absolute (SAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0x90: BCC Relative
 Instruction {
     name: "BCC",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0x91: STA Indirect, Y
 Instruction {
     name: "STA",
     cycles: 6,
+    mode: AddrMode::IndirectY,
 },
 // 0x92: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x93: XXX Indirect, Y (AHX Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::IndirectY,
 },
 // 0x94: STY Zero Page, X
 Instruction {
     name: "STY",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x95: STA Zero Page, X
 Instruction {
     name: "STA",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0x96: STX Zero Page, Y
 Instruction {
     name: "STX",
     cycles: 4,
+    mode: AddrMode::ZeroPageY,
 },
 // 0x97: XXX Zero Page, Y (SAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageY,
 },
 // 0x98: TYA Implied
 Instruction {
     name: "TYA",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x99: STA Absolute, Y
 Instruction {
     name: "STA",
     cycles: 5,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x9A: TXS Implied
 Instruction {
     name: "TXS",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0x9B: XXX Absolute, Y (TAS Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x9C: XXX Absolute, X (SHY Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x9D: STA Absolute, X
 Instruction {
     name: "STA",
     cycles: 5,
+    mode: AddrMode::AbsoluteX,
 },
 // 0x9E: XXX Absolute, Y (SHX Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::AbsoluteY,
 },
 // 0x9F: XXX Absolute, Y (AHX Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xA0: LDY Immediate
 Instruction {
     name: "LDY",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xA1: LDA Indirect, X
 Instruction {
     name: "LDA",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0xA2: LDX Immediate
 Instruction {
     name: "LDX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xA3: XXX Indirect, X (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0xA4: LDY Zero Page
 Instruction {
     name: "LDY",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xA5: LDA Zero Page
 Instruction {
     name: "LDA",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xA6: LDX Zero Page
 Instruction {
     name: "LDX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xA7: XXX Zero Page (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xA8: TAY Implied
 Instruction {
     name: "TAY",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xA9: LDA Immediate
 Instruction {
     name: "LDA",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xAA: TAX Implied
 Instruction {
     name: "TAX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xAB: XXX Immediate (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xAC: LDY Absolute
 Instruction {
     name: "LDY",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xAD: LDA Absolute
 Instruction {
     name: "LDA",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xAE: LDX Absolute
 Instruction {
     name: "LDX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xAF: XXX Absolute (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xB0: BCS Relative
 Instruction {
     name: "BCS",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0xB1: LDA Indirect, Y
 Instruction {
     name: "LDA",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0xB2: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xB3: XXX Indirect, Y (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0xB4: LDY Zero Page, X
 Instruction {
     name: "LDY",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xB5: LDA Zero Page, X
 Instruction {
     name: "LDA",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xB6: LDX Zero Page, Y
 Instruction {
     name: "LDX",
     cycles: 4,
+    mode: AddrMode::ZeroPageY,
 },
 // 0xB7: XXX Zero Page, Y (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageY,
 },
 // 0xB8: CLV Implied
 Instruction {
     name: "CLV",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xB9: LDA Absolute, Y
 Instruction {
     name: "LDA",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xBA: TSX Implied
 Instruction {
     name: "TSX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xBB: XXX Absolute, Y (LAS Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xBC: LDY Absolute, X
 Instruction {
     name: "LDY",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xBD: LDA Absolute, X
 Instruction {
     name: "LDA",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xBE: LDX Absolute, Y
 Instruction {
     name: "LDX",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xBF: XXX Absolute, Y (LAX Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xC0: CPY Immediate
 Instruction {
     name: "CPY",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xC1: CMP Indirect, X
 Instruction {
     name: "CMP",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0xC2: XXX Immediate (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xC3: XXX Indirect, X (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0xC4: CPY Zero Page
 Instruction {
     name: "CPY",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xC5: CMP Zero Page
 Instruction {
     name: "CMP",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xC6: DEC Zero Page
 Instruction {
     name: "DEC",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0xC7: XXX Zero Page (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0xC8: INY Implied
 Instruction {
     name: "INY",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xC9: CMP Immediate
 Instruction {
     name: "CMP",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xCA: DEX Implied
 Instruction {
     name: "DEX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xCB: XXX Immediate (AXS Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xCC: CPY Absolute
 Instruction {
     name: "CPY",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xCD: CMP Absolute
 Instruction {
     name: "CMP",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xCE: DEC Absolute
 Instruction {
     name: "DEC",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0xCF: XXX Absolute (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0xD0: BNE Relative
 Instruction {
     name: "BNE",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0xD1: CMP Indirect, Y
 Instruction {
     name: "CMP",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0xD2: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xD3: XXX Indirect, Y (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0xD4: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xD5: CMP Zero Page, X
 Instruction {
     name: "CMP",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xD6: DEC Zero Page, X
 Instruction {
     name: "DEC",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xD7: XXX Zero Page, X (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xD8: CLD Implied
 Instruction {
     name: "CLD",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xD9: CMP Absolute, Y
 Instruction {
     name: "CMP",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xDA: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xDB: XXX Absolute, Y (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xDC: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xDD: CMP Absolute, X
 Instruction {
     name: "CMP",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xDE: DEC Absolute, X
 Instruction {
     name: "DEC",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xDF: XXX Absolute, X (DCP Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xE0: CPX Immediate
 Instruction {
     name: "CPX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xE1: SBC Indirect, X
 Instruction {
     name: "SBC",
     cycles: 6,
+    mode: AddrMode::IndirectX,
 },
 // 0xE2: XXX Immediate (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xE3: XXX Indirect, X (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectX,
 },
 // 0xE4: CPX Zero Page
 Instruction {
     name: "CPX",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xE5: SBC Zero Page
 Instruction {
     name: "SBC",
     cycles: 3,
+    mode: AddrMode::ZeroPage,
 },
 // 0xE6: INC Zero Page
 Instruction {
     name: "INC",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0xE7: XXX Zero Page (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 5,
+    mode: AddrMode::ZeroPage,
 },
 // 0xE8: INX Implied
 Instruction {
     name: "INX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xE9: SBC Immediate
 Instruction {
     name: "SBC",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xEA: NOP Implied
 Instruction {
     name: "NOP",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xEB: XXX Immediate (SBC Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Immediate,
 },
 // 0xEC: CPX Absolute
 Instruction {
     name: "CPX",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xED: SBC Absolute
 Instruction {
     name: "SBC",
     cycles: 4,
+    mode: AddrMode::Absolute,
 },
 // 0xEE: INC Absolute
 Instruction {
     name: "INC",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0xEF: XXX Absolute (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::Absolute,
 },
 // 0xF0: BEQ Relative
 Instruction {
     name: "BEQ",
     cycles: 2,
+    mode: AddrMode::Relative,
 },
 // 0xF1: SBC Indirect, Y
 Instruction {
     name: "SBC",
     cycles: 5,
+    mode: AddrMode::IndirectY,
 },
 // 0xF2: XXX Implied (Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xF3: XXX Indirect, Y (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 8,
+    mode: AddrMode::IndirectY,
 },
 // 0xF4: XXX Zero Page, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xF5: SBC Zero Page, X
 Instruction {
     name: "SBC",
     cycles: 4,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xF6: INC Zero Page, X
 Instruction {
     name: "INC",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xF7: XXX Zero Page, X (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 6,
+    mode: AddrMode::ZeroPageX,
 },
 // 0xF8: SED Implied
 Instruction {
     name: "SED",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xF9: SBC Absolute, Y
 Instruction {
     name: "SBC",
     cycles: 4,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xFA: XXX Implied (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 2,
+    mode: AddrMode::Implied,
 },
 // 0xFB: XXX Absolute, Y (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteY,
 },
 // 0xFC: XXX Absolute, X (NOP Illegal)
 Instruction {
     name: "XXX",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xFD: SBC Absolute, X
 Instruction {
     name: "SBC",
     cycles: 4,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xFE: INC Absolute, X
 Instruction {
     name: "INC",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },
 // 0xFF: XXX Absolute, X (ISC Illegal)
 Instruction {
     name: "XXX",
     cycles: 7,
+    mode: AddrMode::AbsoluteX,
 },            
         ]
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines_file::test_rom;
+    use crate::region::Region;
+    use crate::runner;
+
+    fn run_to(cpu: &mut CPU, scanline: i16, dot: i16) {
+        while (cpu.bus.ppu.scanline(), cpu.bus.ppu.dot()) != (scanline, dot) {
+            cpu.bus.ppu.step();
+        }
+    }
+
+    // Runs LDA $2002 with NMI enabled so that the read lands `dot` dots after
+    // the start of the vblank line. Returns the value read and the NMIs taken.
+    fn read_status_near_vblank(dot: i16) -> (u8, usize) {
+        let mut bus = Bus::new(test_rom(&[0xAD, 0x02, 0x20], &[0x40]), Region::Ntsc);
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        cpu.cycle_stepped = true;
+        cpu.bus.write(0x2000, 0x80);
+
+        // The read is on the fourth cycle, 12 dots in
+        let start = dot - 12;
+        if start < 0 {
+            run_to(&mut cpu, 240, 341 + start);
+        } else {
+            run_to(&mut cpu, 241, start);
+        }
+        runner::step_instruction(&mut cpu);
+        let status = cpu.registers.a;
+        for _ in 0..4 {
+            runner::step_instruction(&mut cpu);
+        }
+        (status & 0x80, cpu.nmi_count)
+    }
+
+    // Whether an access writes, and its address
+    type Access = (bool, u16);
+    const R: bool = false;
+    const W: bool = true;
+
+    // Runs one instruction at $8000 with X = $20 and returns its cycle count
+    // and, in cycle-stepped mode, every bus access it made
+    fn trace(program: &[u8], cycle_stepped: bool) -> (usize, Vec<Access>) {
+        let mut bus = Bus::new(test_rom(program, &[0x40]), Region::Ntsc);
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        cpu.cycle_stepped = cycle_stepped;
+        cpu.registers.x = 0x20;
+        cpu.bus.log_accesses = true;
+
+        let cycles = runner::step_instruction(&mut cpu);
+        let accesses = cpu.bus.accesses.iter().map(|access| (access.write, access.addr)).collect();
+        (cycles, accesses)
+    }
+
+    // Cycle by cycle bus activity as documented for the 6502 (64doc)
+    #[test]
+    fn instructions_take_their_documented_cycles_and_dummy_accesses() {
+        let cases: [(&str, &[u8], &[Access]); 9] = [
+            ("NOP", &[0xEA], &[(R, 0x8000), (R, 0x8001)]),
+            ("LDA abs,X", &[0xBD, 0x10, 0x00], &[(R, 0x8000), (R, 0x8001), (R, 0x8002), (R, 0x0030)]),
+            // The high byte is fixed up a cycle late, after a read from the wrong page
+            ("LDA abs,X across a page", &[0xBD, 0xF0, 0x00],
+                &[(R, 0x8000), (R, 0x8001), (R, 0x8002), (R, 0x0010), (R, 0x0110)]),
+            ("STA abs,X", &[0x9D, 0x10, 0x00],
+                &[(R, 0x8000), (R, 0x8001), (R, 0x8002), (R, 0x0030), (W, 0x0030)]),
+            // Read-modify-writes write the old value back first
+            ("INC zp", &[0xE6, 0x10], &[(R, 0x8000), (R, 0x8001), (R, 0x0010), (W, 0x0010), (W, 0x0010)]),
+            ("PHA", &[0x48], &[(R, 0x8000), (R, 0x8001), (W, 0x01FD)]),
+            ("PLA", &[0x68], &[(R, 0x8000), (R, 0x8001), (R, 0x01FD), (R, 0x01FE)]),
+            ("JSR", &[0x20, 0x00, 0x90],
+                &[(R, 0x8000), (R, 0x8001), (R, 0x01FD), (W, 0x01FD), (W, 0x01FC), (R, 0x8002)]),
+            ("BNE across a page", &[0xD0, 0x80], &[(R, 0x8000), (R, 0x8001), (R, 0x8002), (R, 0x8082)]),
+        ];
+
+        for (name, program, accesses) in cases {
+            let (cycles, traced) = trace(program, true);
+            assert_eq!(traced, accesses, "{}", name);
+            assert_eq!(cycles, accesses.len(), "{}", name);
+            // Without cycle stepping the same count comes from the tables
+            assert_eq!(trace(program, false).0, accesses.len(), "{}", name);
+        }
+    }
+
+    // Without page crossings or taken branches, every opcode takes the cycles
+    // listed in the table
+    #[test]
+    fn every_opcode_takes_its_table_cycles() {
+        let table = Instruction::lookup_table();
+        for (opcode, instruction) in table.iter().enumerate() {
+            let opcode = opcode as u8;
+            if instruction.name == "XXX" || instruction.mode == AddrMode::Relative {
+                continue;
+            }
+            for cycle_stepped in [false, true] {
+                let (cycles, _) = trace(&[opcode, 0x10, 0x00], cycle_stepped);
+                assert_eq!(cycles, instruction.cycles as usize, "{:02X} {}", opcode, instruction.name);
+            }
+        }
+    }
+
+    // Machine running `program`, with the cycle counter set to `cycles`
+    fn machine(bus: &mut Bus, cycles: usize, cycle_stepped: bool) -> CPU<'_> {
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.cycles = cycles;
+        cpu.cycle_stepped = cycle_stepped;
+        cpu
+    }
+
+    // Starts a DMC sample of `length` bytes at $C000 at the fastest rate
+    fn start_dmc(bus: &mut Bus, length: u8) {
+        bus.write(0x4010, 0x0F);
+        bus.write(0x4012, 0x00);
+        bus.write(0x4013, length);
+        bus.write(0x4015, 0x10);
+    }
+
+    #[test]
+    fn oam_dma_takes_an_extra_cycle_to_align_on_odd_cycles() {
+        for cycle_stepped in [false, true] {
+            // STA $4014 takes 4 cycles, then the DMA halts the CPU on the 5th
+            for (start, dma) in [(0, 514), (1, 513)] {
+                let mut bus = Bus::new(test_rom(&[0x8D, 0x14, 0x40], &[0x40]), Region::Ntsc);
+                let mut cpu = machine(&mut bus, start, cycle_stepped);
+                assert_eq!(runner::step_instruction(&mut cpu), 4 + dma);
+            }
+        }
+    }
+
+    #[test]
+    fn dmc_fetches_stall_the_cpu_by_cycle_parity() {
+        for cycle_stepped in [false, true] {
+            for (start, stall) in [(0, 3), (1, 4)] {
+                let mut bus = Bus::new(test_rom(&[0xEA], &[0x40]), Region::Ntsc);
+                start_dmc(&mut bus, 0);
+                let mut cpu = machine(&mut bus, start, cycle_stepped);
+                assert_eq!(runner::step_instruction(&mut cpu), 2 + stall);
+                assert_eq!(cpu.bus.apu.dmc_fetch_address(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn dmc_fetches_during_oam_dma_take_two_cycles() {
+        // NOP, which takes the first sample byte; STA $4014
+        let mut bus = Bus::new(test_rom(&[0xEA, 0x8D, 0x14, 0x40], &[0x40]), Region::Ntsc);
+        start_dmc(&mut bus, 0x01);
+        let mut cpu = machine(&mut bus, 0, true);
+        runner::step_instruction(&mut cpu);
+
+        let start = cpu.cycles;
+        cpu.bus.log_accesses = true;
+        let cycles = runner::step_instruction(&mut cpu);
+
+        // Sample bytes are read from $C000 on, OAM bytes from page 0
+        let reads: Vec<u16> = cpu.bus.accesses.iter().filter(|access| !access.write).map(|access| access.addr).collect();
+        let first_oam = reads.iter().position(|addr| *addr == 0x0000).unwrap();
+        let last_oam = reads.iter().rposition(|addr| *addr == 0x00FF).unwrap();
+        let fetches = reads[first_oam..last_oam].iter().filter(|addr| **addr >= 0xC000).count();
+        assert!(fetches > 0);
+        assert_eq!(reads.iter().filter(|addr| **addr >= 0xC000).count(), fetches);
+
+        let align = if (start + 5).is_multiple_of(2) { 0 } else { 1 };
+        assert_eq!(cycles, 4 + 513 + align + 2 * fetches);
+    }
+
+    #[test]
+    fn status_read_racing_vblank_start() {
+        assert_eq!(read_status_near_vblank(-1), (0x00, 1));
+        // The flag never sets
+        assert_eq!(read_status_near_vblank(0), (0x00, 0));
+        // The flag reads set but is cleared before the CPU sees the NMI
+        assert_eq!(read_status_near_vblank(1), (0x80, 0));
+        assert_eq!(read_status_near_vblank(2), (0x80, 0));
+        assert_eq!(read_status_near_vblank(3), (0x80, 1));
+    }
+
+    #[test]
+    fn enabling_nmi_during_vblank_raises_one_each_time() {
+        let program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // Enable NMI
+            0xA9, 0x00, 0x8D, 0x00, 0x20, // Disable it
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // and enable it again
+        ];
+        for cycle_stepped in [false, true] {
+            let mut bus = Bus::new(test_rom(&program, &[0x40]), Region::Ntsc);
+            let mut cpu = CPU::new(&mut bus);
+            cpu.reset();
+            cpu.cycle_stepped = cycle_stepped;
+            run_to(&mut cpu, 242, 0);
+
+            for _ in 0..12 {
+                runner::step_instruction(&mut cpu);
+            }
+            assert_eq!(cpu.nmi_count, 2);
+        }
+    }
+}
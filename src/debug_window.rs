@@ -0,0 +1,100 @@
+use sdl2::event::Event;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debugger::Debugger;
+
+// A debugger panel living in its own window. The front-end routes the events
+// of that window to it and redraws it once per pass of the main loop.
+pub trait DebugView {
+    fn window_id(&self) -> u32;
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, debugger: &mut Debugger);
+
+    fn render(&mut self, cpu: &mut CPU, debugger: &Debugger, font: &mut Font);
+}
+
+// Window plumbing shared by the debug views
+pub struct DebugWindow {
+    pub canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+}
+
+impl DebugWindow {
+    pub fn open(video: &VideoSubsystem, title: &str, width: u32, height: u32) -> Result<Self, String> {
+        let window = video
+            .window(title, width, height)
+            .position_centered()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        canvas.set_blend_mode(BlendMode::Blend);
+        let texture_creator = canvas.texture_creator();
+
+        Ok(DebugWindow {
+            canvas,
+            texture_creator,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Draws a width x height image into `target`, scaling it if needed
+    pub fn draw_pixels(&mut self, pixels: &[Color], width: u32, height: u32, target: Rect) {
+        if let Ok(mut texture) = self.texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, width, height) {
+            texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for (i, color) in pixels.iter().enumerate() {
+                    let offset = (i / width as usize) * pitch + (i % width as usize) * 4;
+                    buffer[offset] = color.b;
+                    buffer[offset + 1] = color.g;
+                    buffer[offset + 2] = color.r;
+                    buffer[offset + 3] = 0xFF;
+                }
+            }).unwrap();
+
+            self.canvas.copy(&texture, None, Some(target)).unwrap();
+        }
+    }
+
+    pub fn draw_text(&mut self, font: &mut Font, text: &str, color: Color, x: i32, y: i32) {
+        if text.is_empty() {
+            return;
+        }
+        let surface = font.render(text).blended(color).unwrap();
+        let texture = self.texture_creator.create_texture_from_surface(&surface).unwrap();
+        let query = texture.query();
+        self.canvas.copy(&texture, None, Some(Rect::new(x, y, query.width, query.height))).unwrap();
+    }
+
+    // Text box next to the mouse, kept inside the window
+    pub fn draw_tooltip(&mut self, font: &mut Font, lines: &[String], mouse: (i32, i32)) {
+        let line_height = font.height();
+        let width = lines.iter()
+            .filter_map(|line| font.size_of(line).ok())
+            .map(|(w, _)| w as i32)
+            .max()
+            .unwrap_or(0) + 8;
+        let height = line_height * lines.len() as i32 + 8;
+
+        let (window_width, window_height) = self.canvas.output_size().unwrap();
+        let x = (mouse.0 + 16).min(window_width as i32 - width).max(0);
+        let y = (mouse.1 + 16).min(window_height as i32 - height).max(0);
+
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xE0));
+        self.canvas.fill_rect(Rect::new(x, y, width as u32, height as u32)).unwrap();
+        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+        self.canvas.draw_rect(Rect::new(x, y, width as u32, height as u32)).unwrap();
+
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_text(font, line, Color::RGB(255, 255, 255), x + 4, y + 4 + i as i32 * line_height);
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use std::ops::RangeInclusive;
+
+use crate::bus::{AddressSpace, MemoryAccess};
+use crate::cdl::CodeDataLog;
+use crate::cpu::CPU;
+use crate::expression::{parse_number, Expr, ExprContext, Variable};
+use crate::runner;
+use crate::symbols::SymbolTable;
+
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+// Execution breakpoint or watchpoint over an address range. Execute only
+// applies to the CPU address space.
+pub struct Breakpoint {
+    pub id: usize,
+    pub space: AddressSpace,
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub condition: Option<(String, Expr)>, // Source text and parsed expression
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut kinds = String::new();
+        if self.read { kinds.push('R'); }
+        if self.write { kinds.push('W'); }
+        if self.execute { kinds.push('X'); }
+
+        let mut text = format!("#{} {} {}${:04X}", self.id, kinds,
+            if self.space == AddressSpace::Ppu { "PPU " } else { "" }, self.start);
+        if self.end != self.start {
+            text.push_str(&format!("-${:04X}", self.end));
+        }
+        if let Some((source, _)) = &self.condition {
+            text.push_str(&format!(" if {}", source));
+        }
+        if !self.enabled {
+            text.push_str(" (disabled)");
+        }
+        text
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RunMode {
+    Run,
+    StepOver { return_addr: u16, sp: u8 },
+    StepOut { sp: u8 },
+    RunToScanline(i16),
+    RunToNmi(usize), // NMI count when it started
+}
+
+// Breakpoints, watchpoints and stepping around the emulation loop. The front-end
+// runs frames through `run_frame`, which stops as soon as something breaks.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    pub symbols: SymbolTable,
+    pub break_on_unknown_opcode: bool,
+
+    pub paused: bool,
+    pub break_reason: Option<String>,
+    mode: RunMode,
+    // Lets the instruction at PC run after resuming from a breakpoint on it
+    skip_breakpoint: bool,
+
+    frame_cycles: usize, // CPU cycles run so far in the current frame
+}
+
+// Values a breakpoint condition can look at
+struct BreakContext<'a, 'b> {
+    cpu: &'a mut CPU<'b>,
+    access: Option<MemoryAccess>,
+}
+
+impl ExprContext for BreakContext<'_, '_> {
+    fn variable(&mut self, variable: Variable) -> i64 {
+        let registers = &self.cpu.registers;
+        match variable {
+            Variable::A => registers.a as i64,
+            Variable::X => registers.x as i64,
+            Variable::Y => registers.y as i64,
+            Variable::Sp => registers.sp as i64,
+            Variable::P => registers.f as i64,
+            Variable::Pc => registers.pc as i64,
+            Variable::Value => self.access.map_or(0, |access| access.value as i64),
+            Variable::Address => self.access.map_or(registers.pc as i64, |access| access.addr as i64),
+            Variable::Scanline => self.cpu.bus.ppu.scanline() as i64,
+            Variable::Dot => self.cpu.bus.ppu.dot() as i64,
+            Variable::Frame => self.cpu.bus.ppu.frame() as i64,
+        }
+    }
+
+    fn memory(&mut self, addr: u16) -> u8 {
+        self.cpu.bus.peek(addr)
+    }
+}
+
+// "$8000", "$8000-$80FF", or symbols such as "main_loop+3"
+fn parse_range(text: &str, symbols: &SymbolTable, cpu: &CPU) -> Result<(u16, u16), String> {
+    let address = |text: &str| match parse_number(text) {
+        Ok(number) => Ok(number),
+        Err(e) if text.starts_with(|c: char| c.is_ascii_digit() || c == '$') => Err(e),
+        Err(_) => symbols.resolve(cpu.bus, text).map(|addr| addr as i64),
+    };
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (address(start)?, address(end)?),
+        None => {
+            let addr = address(text)?;
+            (addr, addr)
+        }
+    };
+
+    if !(0..=0xFFFF).contains(&start) || !(start..=0xFFFF).contains(&end) {
+        return Err(format!("Invalid address range: {}", text));
+    }
+    Ok((start as u16, end as u16))
+}
+
+// Whether the PPU passed `target` moving from line `before` to line `after`.
+// Lines run from -1 (pre-render) to `scanlines` - 2.
+fn passed_scanline(before: i16, after: i16, target: i16, scanlines: i16) -> bool {
+    let distance = |from: i16, to: i16| (to - from).rem_euclid(scanlines);
+    let to_target = distance(before, target);
+    to_target != 0 && to_target <= distance(before, after)
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            symbols: SymbolTable::new(),
+            break_on_unknown_opcode: false,
+
+            paused: false,
+            break_reason: None,
+            mode: RunMode::Run,
+            skip_breakpoint: false,
+
+            frame_cycles: 0,
+        }
+    }
+
+    pub fn add_breakpoint(
+        &mut self,
+        space: AddressSpace,
+        range: RangeInclusive<u16>,
+        read: bool,
+        write: bool,
+        execute: bool,
+        condition: Option<&str>,
+    ) -> Result<usize, String> {
+        if execute && space == AddressSpace::Ppu {
+            return Err("Execution breakpoints only apply to CPU addresses".to_string());
+        }
+
+        let condition = match condition {
+            Some(source) => Some((source.to_string(), Expr::parse(source)?)),
+            None => None,
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            space,
+            start: *range.start(),
+            end: *range.end(),
+            read,
+            write,
+            execute,
+            condition,
+            enabled: true,
+        });
+        Ok(id)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> Result<(), String> {
+        let index = self.breakpoints.iter().position(|bp| bp.id == id)
+            .ok_or(format!("No breakpoint #{}", id))?;
+        self.breakpoints.remove(index);
+        Ok(())
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> Result<(), String> {
+        let breakpoint = self.breakpoints.iter_mut().find(|bp| bp.id == id)
+            .ok_or(format!("No breakpoint #{}", id))?;
+        breakpoint.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn execution_breakpoint_at(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|bp| bp.execute && bp.contains(addr))
+    }
+
+    // Adds a plain execution breakpoint at `addr`, or removes the one there
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        match self.breakpoints.iter().position(|bp| bp.execute && bp.start == addr && bp.end == addr) {
+            Some(index) => {
+                self.breakpoints.remove(index);
+            }
+            None => {
+                let _ = self.add_breakpoint(AddressSpace::Cpu, addr..=addr, false, false, true, None);
+            }
+        }
+    }
+
+    pub fn pause(&mut self, reason: &str) {
+        self.paused = true;
+        self.mode = RunMode::Run;
+        self.break_reason = Some(reason.to_string());
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.break_reason = None;
+        self.skip_breakpoint = true;
+    }
+
+    // The front-end only feeds new input at the start of a frame, so a frame
+    // split by a break still sees one input update
+    pub fn at_frame_start(&self) -> bool {
+        self.frame_cycles == 0
+    }
+
+    pub fn step_into(&mut self, cpu: &mut CPU) {
+        if let Some(reason) = Self::unexecutable(cpu) {
+            self.pause(&reason);
+            return;
+        }
+        self.execute(cpu);
+        cpu.bus.accesses.clear();
+        self.pause("Step");
+    }
+
+    // Runs a JSR until it returns; anything else is a single step
+    pub fn step_over(&mut self, cpu: &mut CPU) {
+        let pc = cpu.registers.pc;
+        if cpu.bus.peek(pc) == JSR {
+            self.resume();
+            self.mode = RunMode::StepOver { return_addr: pc.wrapping_add(3), sp: cpu.registers.sp };
+        } else {
+            self.step_into(cpu);
+        }
+    }
+
+    // Runs until the current subroutine or interrupt handler returns
+    pub fn step_out(&mut self, cpu: &mut CPU) {
+        self.resume();
+        self.mode = RunMode::StepOut { sp: cpu.registers.sp };
+    }
+
+    pub fn run_to_scanline(&mut self, scanline: i16) {
+        self.resume();
+        self.mode = RunMode::RunToScanline(scanline);
+    }
+
+    pub fn run_to_nmi(&mut self, cpu: &mut CPU) {
+        self.resume();
+        self.mode = RunMode::RunToNmi(cpu.nmi_count);
+    }
+
+    // Runs the rest of the current frame, stopping early on a break. Returns
+    // true once the frame is complete.
+    pub fn run_frame(&mut self, cpu: &mut CPU) -> bool {
+        cpu.bus.log_accesses = self.breakpoints.iter().any(|bp| bp.enabled && (bp.read || bp.write));
+
+        while !self.paused {
+            if let Some(reason) = self.check_before(cpu) {
+                self.pause(&reason);
+                break;
+            }
+
+            let opcode = cpu.bus.peek(cpu.registers.pc);
+            let scanline = cpu.bus.ppu.scanline();
+            let frame_done = self.execute(cpu);
+
+            if let Some(reason) = self.check_after(cpu, opcode, scanline) {
+                self.pause(&reason);
+            }
+            if frame_done {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Runs one instruction, rendering the frame when its cycles are used up
+    fn execute(&mut self, cpu: &mut CPU) -> bool {
+        self.frame_cycles += runner::step_instruction(cpu);
+
+        if self.frame_cycles >= cpu.bus.region.cycles_per_frame() {
+            self.frame_cycles = 0;
+            cpu.bus.ppu.render_frame(&cpu.bus.rom, cpu.bus.cdl.as_mut());
+            return true;
+        }
+        false
+    }
+
+    fn condition_met(breakpoint: &Breakpoint, cpu: &mut CPU, access: Option<MemoryAccess>) -> bool {
+        match &breakpoint.condition {
+            Some((_, expr)) => expr.evaluate(&mut BreakContext { cpu, access }) != 0,
+            None => true,
+        }
+    }
+
+    // The CPU has no implementation for unknown opcodes, so they are never run
+    fn unexecutable(cpu: &mut CPU) -> Option<String> {
+        let pc = cpu.registers.pc;
+        let opcode = cpu.bus.peek(pc);
+        (cpu.instruction(opcode).name == "XXX")
+            .then(|| format!("Unknown opcode ${:02X} at ${:04X} cannot be executed", opcode, pc))
+    }
+
+    fn check_before(&mut self, cpu: &mut CPU) -> Option<String> {
+        let pc = cpu.registers.pc;
+
+        if self.skip_breakpoint {
+            self.skip_breakpoint = false;
+            return Self::unexecutable(cpu);
+        }
+
+        if self.break_on_unknown_opcode {
+            let opcode = cpu.bus.peek(pc);
+            if cpu.instruction(opcode).name == "XXX" {
+                return Some(format!("Unknown opcode ${:02X} at ${:04X}", opcode, pc));
+            }
+        }
+
+        for breakpoint in self.breakpoints.iter() {
+            if breakpoint.enabled
+                && breakpoint.execute
+                && breakpoint.contains(pc)
+                && Self::condition_met(breakpoint, cpu, None) {
+                let label = self.symbols.describe(cpu.bus, pc).map(|name| format!(" ({})", name)).unwrap_or_default();
+                return Some(format!("Breakpoint #{} at ${:04X}{}", breakpoint.id, pc, label));
+            }
+        }
+        None
+    }
+
+    fn check_after(&mut self, cpu: &mut CPU, opcode: u8, scanline_before: i16) -> Option<String> {
+        let accesses = std::mem::take(&mut cpu.bus.accesses);
+        for access in accesses {
+            for breakpoint in self.breakpoints.iter() {
+                let kind_matches = if access.write { breakpoint.write } else { breakpoint.read };
+                if breakpoint.enabled
+                    && kind_matches
+                    && breakpoint.space == access.space
+                    && breakpoint.contains(access.addr)
+                    && Self::condition_met(breakpoint, cpu, Some(access)) {
+                    return Some(format!("Watchpoint #{}: {} ${:02X} {} {}${:04X}",
+                        breakpoint.id,
+                        if access.write { "write" } else { "read" },
+                        access.value,
+                        if access.write { "to" } else { "from" },
+                        if access.space == AddressSpace::Ppu { "PPU " } else { "" },
+                        access.addr));
+                }
+            }
+        }
+
+        match self.mode {
+            RunMode::Run => None,
+
+            RunMode::StepOver { return_addr, sp } => {
+                (cpu.registers.pc == return_addr && cpu.registers.sp >= sp).then(|| "Step over".to_string())
+            }
+
+            RunMode::StepOut { sp } => {
+                ((opcode == RTS || opcode == RTI) && cpu.registers.sp > sp).then(|| "Step out".to_string())
+            }
+
+            RunMode::RunToScanline(target) => {
+                let scanline = cpu.bus.ppu.scanline();
+                passed_scanline(scanline_before, scanline, target, cpu.bus.region.scanlines())
+                    .then(|| format!("Reached scanline {}", target))
+            }
+
+            RunMode::RunToNmi(count) => (cpu.nmi_count > count).then(|| "NMI".to_string()),
+        }
+    }
+
+    // Text commands shared by the debugger window and scripts:
+    //   break <range> [if <expr>]              execution breakpoint
+    //   watch [ppu] <r|w|rw> <range> [if <expr>]
+    //   delete|enable|disable <id>, list
+    //   step, over, out, continue, pause
+    //   scanline <n>, nmi, unknown [on|off], symbols <file>
+    //   cdl [start [file]|stop|save <file>]
+    pub fn execute_command(&mut self, cpu: &mut CPU, line: &str) -> Result<String, String> {
+        let (command, condition) = match line.split_once(" if ") {
+            Some((command, condition)) => (command, Some(condition.trim())),
+            None => (line, None),
+        };
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let Some(name) = words.first() else {
+            return Ok(String::new());
+        };
+        let argument = |index: usize| words.get(index).copied().ok_or(format!("Missing argument for '{}'", name));
+
+        match *name {
+            "break" | "b" => {
+                let (start, end) = parse_range(argument(1)?, &self.symbols, cpu)?;
+                let id = self.add_breakpoint(AddressSpace::Cpu, start..=end, false, false, true, condition)?;
+                Ok(format!("Breakpoint #{} added", id))
+            }
+
+            "watch" | "w" => {
+                let (space, first) = if words.get(1) == Some(&"ppu") { (AddressSpace::Ppu, 2) } else { (AddressSpace::Cpu, 1) };
+                let kinds = argument(first)?;
+                let (read, write) = (kinds.contains('r'), kinds.contains('w'));
+                if !read && !write {
+                    return Err(format!("Watch kind must be r, w or rw, not '{}'", kinds));
+                }
+                let (start, end) = parse_range(argument(first + 1)?, &self.symbols, cpu)?;
+                let (start, end) = if space == AddressSpace::Ppu { (start & 0x3FFF, end & 0x3FFF) } else { (start, end) };
+                let id = self.add_breakpoint(space, start..=end, read, write, false, condition)?;
+                Ok(format!("Watchpoint #{} added", id))
+            }
+
+            "delete" | "enable" | "disable" => {
+                let id = parse_number(argument(1)?)? as usize;
+                match *name {
+                    "delete" => self.remove_breakpoint(id)?,
+                    enable => self.set_breakpoint_enabled(id, enable == "enable")?,
+                }
+                Ok(format!("Breakpoint #{} {}d", id, name))
+            }
+
+            "list" => {
+                let lines: Vec<String> = self.breakpoints.iter().map(|bp| bp.describe()).collect();
+                Ok(if lines.is_empty() { "No breakpoints".to_string() } else { lines.join("\n") })
+            }
+
+            "step" | "s" => {
+                self.step_into(cpu);
+                Ok(format!("PC: ${:04X}", cpu.registers.pc))
+            }
+
+            "over" | "o" => {
+                self.step_over(cpu);
+                Ok(format!("PC: ${:04X}", cpu.registers.pc))
+            }
+
+            "out" => {
+                self.step_out(cpu);
+                Ok("Running to the end of the subroutine".to_string())
+            }
+
+            "continue" | "c" => {
+                self.resume();
+                Ok("Running".to_string())
+            }
+
+            "pause" => {
+                self.pause("Paused");
+                Ok(format!("Paused at ${:04X}", cpu.registers.pc))
+            }
+
+            "scanline" => {
+                let scanline = parse_number(argument(1)?)?;
+                if !(-1..cpu.bus.region.scanlines() as i64 - 1).contains(&scanline) {
+                    return Err(format!("Scanline out of range: {}", scanline));
+                }
+                self.run_to_scanline(scanline as i16);
+                Ok(format!("Running to scanline {}", scanline))
+            }
+
+            "nmi" => {
+                self.run_to_nmi(cpu);
+                Ok("Running to the next NMI".to_string())
+            }
+
+            "symbols" => {
+                let path = argument(1)?;
+                let count = self.symbols.load(path)?;
+                Ok(format!("Loaded {} symbols from {}", count, path))
+            }
+
+            "cdl" => match words.get(1).copied() {
+                None => cpu.bus.cdl.as_ref().map(|cdl| cdl.summary()).ok_or("Not logging code and data".to_string()),
+                Some("start") => {
+                    CodeDataLog::start(cpu.bus, words.get(2).copied())?;
+                    Ok("Logging code and data".to_string())
+                }
+                Some("stop") => {
+                    cpu.bus.cdl = None;
+                    Ok("Stopped logging code and data".to_string())
+                }
+                Some("save") => {
+                    let path = argument(2)?;
+                    cpu.bus.cdl.as_ref().ok_or("Not logging code and data".to_string())?.save(path)?;
+                    Ok(format!("Saved code/data log to {}", path))
+                }
+                Some(other) => Err(format!("Unknown cdl command: {}", other)),
+            },
+
+            "unknown" => {
+                self.break_on_unknown_opcode = match words.get(1) {
+                    Some(&"on") => true,
+                    Some(&"off") => false,
+                    _ => !self.break_on_unknown_opcode,
+                };
+                Ok(format!("Break on unknown opcodes {}", if self.break_on_unknown_opcode { "on" } else { "off" }))
+            }
+
+            _ => Err(format!("Unknown command: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::ines_file::test_rom;
+    use crate::region::Region;
+
+    #[test]
+    fn stops_on_unknown_opcodes_without_running_them() {
+        // NOP; then $02, which the CPU does not implement
+        let mut bus = Bus::new(test_rom(&[0xEA, 0x02], &[0x40]), Region::Ntsc);
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        let mut debugger = Debugger::new();
+        debugger.break_on_unknown_opcode = true;
+
+        assert!(!debugger.run_frame(&mut cpu));
+        assert_eq!(debugger.break_reason.as_deref(), Some("Unknown opcode $02 at $8001"));
+
+        // Continuing or stepping refuses to run it instead of breaking on it again
+        debugger.resume();
+        assert!(!debugger.run_frame(&mut cpu));
+        assert!(debugger.break_reason.as_deref().unwrap().contains("cannot be executed"));
+        debugger.step_into(&mut cpu);
+        assert!(debugger.break_reason.as_deref().unwrap().contains("cannot be executed"));
+        assert_eq!(cpu.registers.pc, 0x8001);
+
+        // Moving PC past it goes on as usual
+        cpu.registers.pc = 0x8002;
+        debugger.step_into(&mut cpu);
+        assert_eq!(cpu.registers.pc, 0x8003);
+    }
+}
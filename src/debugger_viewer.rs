@@ -0,0 +1,188 @@
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::disassembler::{disassemble, DisassembledLine};
+use crate::symbols::SymbolTable;
+
+const WINDOW_WIDTH: u32 = 880;
+const WINDOW_HEIGHT: u32 = 600;
+const LINE_HEIGHT: i32 = 18;
+
+const DISASSEMBLY_LINES: usize = 26;
+const INFO_X: i32 = 500;
+const LABEL_WIDTH: usize = 14;
+const SOURCE_LINE_CHARS: usize = 44;
+const OUTPUT_LINES: usize = 4;
+const COMMAND_Y: i32 = WINDOW_HEIGHT as i32 - LINE_HEIGHT - 4;
+
+// Disassembly around PC, registers, breakpoints and a command line taking
+// the commands of Debugger::execute_command
+pub struct DebuggerViewer {
+    window: DebugWindow,
+    view_start: u16,       // First disassembled address; moves when PC leaves the view
+    line_addrs: Vec<u16>,  // Address of each disassembly line last drawn, for clicks
+    command: String,
+    output: Vec<String>,
+}
+
+impl DebuggerViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(DebuggerViewer {
+            window: DebugWindow::open(video, "Debugger", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            view_start: 0,
+            line_addrs: Vec::new(),
+            command: String::new(),
+            output: vec!["F5 run/pause, F11 step, F10 over, Shift+F11 out".to_string()],
+        })
+    }
+
+    fn print(&mut self, text: &str) {
+        self.output.extend(text.lines().map(|line| line.to_string()));
+        let excess = self.output.len().saturating_sub(OUTPUT_LINES);
+        self.output.drain(..excess);
+    }
+
+    fn run_command(&mut self, cpu: &mut CPU, debugger: &mut Debugger) {
+        let command = std::mem::take(&mut self.command);
+        self.print(&format!("> {}", command));
+        match debugger.execute_command(cpu, &command) {
+            Ok(message) => self.print(&message),
+            Err(e) => self.print(&e),
+        }
+    }
+
+    fn disassembly(&mut self, cpu: &mut CPU, symbols: &SymbolTable) -> Vec<DisassembledLine> {
+        let pc = cpu.registers.pc;
+        let mut lines = Vec::with_capacity(DISASSEMBLY_LINES);
+        let mut addr = self.view_start;
+        for _ in 0..DISASSEMBLY_LINES {
+            let line = disassemble(cpu, symbols, addr);
+            addr = line.next_addr();
+            lines.push(line);
+        }
+
+        // Keep the view still while PC stays on one of its lines, leaving
+        // a few lines of context when it has to move
+        if !lines[..DISASSEMBLY_LINES - 4].iter().any(|line| line.addr == pc) {
+            self.view_start = pc;
+            return self.disassembly(cpu, symbols);
+        }
+        lines
+    }
+}
+
+impl DebugView for DebuggerViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                if debugger.paused {
+                    debugger.resume();
+                } else {
+                    debugger.pause("Paused");
+                }
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::F10), .. } => debugger.step_over(cpu),
+
+            Event::KeyDown { keycode: Some(Keycode::F11), keymod, .. } => {
+                if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    debugger.step_out(cpu);
+                } else {
+                    debugger.step_into(cpu);
+                }
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => self.run_command(cpu, debugger),
+
+            Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                self.command.pop();
+            }
+
+            Event::TextInput { text, .. } => self.command.push_str(text),
+
+            // Clicking a disassembly line toggles a breakpoint on it
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if *x < INFO_X => {
+                if let Some(addr) = self.line_addrs.get((*y / LINE_HEIGHT) as usize) {
+                    debugger.toggle_breakpoint(*addr);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, debugger: &Debugger, font: &mut Font) {
+        let white = Color::RGB(255, 255, 255);
+        let grey = Color::RGB(160, 160, 160);
+
+        self.window.canvas.set_draw_color(Color::RGB(24, 24, 24));
+        self.window.canvas.clear();
+
+        let lines = self.disassembly(cpu, &debugger.symbols);
+        self.line_addrs = lines.iter().map(|line| line.addr).collect();
+        for (i, line) in lines.iter().enumerate() {
+            let y = i as i32 * LINE_HEIGHT;
+            if line.addr == cpu.registers.pc {
+                self.window.canvas.set_draw_color(Color::RGB(0, 64, 128));
+                self.window.canvas.fill_rect(Rect::new(0, y, INFO_X as u32 - 8, LINE_HEIGHT as u32)).unwrap();
+            }
+
+            let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let mut label = line.label.as_deref().map(|name| format!("{}:", name)).unwrap_or_default();
+            label.truncate(LABEL_WIDTH);
+            let text = format!("{} ${:04X}  {:<9} {:<width$} {}",
+                if debugger.execution_breakpoint_at(line.addr).is_some() { '*' } else { ' ' },
+                line.addr, bytes.join(" "), label, line.text, width = LABEL_WIDTH);
+            let color = if debugger.execution_breakpoint_at(line.addr).is_some() { Color::RGB(255, 96, 96) } else { white };
+            self.window.draw_text(font, &text, color, 4, y);
+        }
+
+        let registers = &cpu.registers;
+        let flags: String = "NV-BDIZC".chars().enumerate()
+            .map(|(i, name)| if (registers.f & (0x80 >> i)) != 0 { name } else { '.' })
+            .collect();
+        let ppu = &cpu.bus.ppu;
+        let mut info = vec![
+            format!("A:{:02X} X:{:02X} Y:{:02X} SP:{:02X}", registers.a, registers.x, registers.y, registers.sp),
+            format!("PC:{:04X} P:{:02X} {}", registers.pc, registers.f, flags),
+            format!("Scanline {} dot {} frame {}", ppu.scanline(), ppu.dot(), ppu.frame()),
+            format!("Cycles {}", cpu.cycles),
+            debugger.symbols.source_line(cpu.bus, registers.pc)
+                .map(|line| line.chars().take(SOURCE_LINE_CHARS).collect())
+                .unwrap_or_default(),
+            match &debugger.break_reason {
+                Some(reason) => reason.clone(),
+                None if debugger.paused => "Paused".to_string(),
+                None => "Running".to_string(),
+            },
+            format!("Break on unknown opcodes: {}", if debugger.break_on_unknown_opcode { "on" } else { "off" }),
+            String::new(),
+            "Breakpoints:".to_string(),
+        ];
+        info.extend(debugger.breakpoints.iter().map(|bp| bp.describe()));
+
+        for (i, text) in info.iter().enumerate() {
+            self.window.draw_text(font, text, white, INFO_X, i as i32 * LINE_HEIGHT);
+        }
+
+        let output_y = COMMAND_Y - OUTPUT_LINES as i32 * LINE_HEIGHT;
+        for (i, text) in self.output.iter().enumerate() {
+            self.window.draw_text(font, text, grey, 4, output_y + i as i32 * LINE_HEIGHT);
+        }
+        self.window.draw_text(font, &format!("> {}_", self.command), white, 4, COMMAND_Y);
+
+        self.window.canvas.present();
+    }
+}
@@ -0,0 +1,76 @@
+use crate::cpu::{AddrMode, CPU};
+use crate::symbols::SymbolTable;
+
+pub struct DisassembledLine {
+    pub addr: u16,
+    pub label: Option<String>, // Label placed exactly at addr
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl DisassembledLine {
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+// Decodes the instruction at `addr` without side effects on the bus. Operand
+// addresses are shown as labels where the symbol table has one, and PRG bytes
+// the code/data log only saw read as data are shown as .byte.
+pub fn disassemble(cpu: &mut CPU, symbols: &SymbolTable, addr: u16) -> DisassembledLine {
+    let label = symbols.label_at(cpu.bus, addr).map(|name| name.to_string());
+
+    if let (Some(cdl), Some(offset)) = (cpu.bus.cdl.as_ref(), cpu.bus.prg_rom_offset(addr))
+        && cdl.is_data(offset) {
+        let byte = cpu.bus.peek(addr);
+        return DisassembledLine {
+            addr,
+            label,
+            bytes: vec![byte],
+            text: format!(".byte ${:02X}", byte),
+        };
+    }
+
+    let opcode = cpu.bus.peek(addr);
+    let instruction = cpu.instruction(opcode);
+    let (name, mode) = (instruction.name, instruction.mode);
+
+    let mut bytes = vec![opcode];
+    for i in 0..mode.operand_length() {
+        bytes.push(cpu.bus.peek(addr.wrapping_add(1 + i)));
+    }
+
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = ((bytes.get(2).copied().unwrap_or(0) as u16) << 8) | byte as u16;
+
+    let zero_page = symbols.describe(cpu.bus, byte as u16).unwrap_or(format!("${:02X}", byte));
+    let absolute = symbols.describe(cpu.bus, word).unwrap_or(format!("${:04X}", word));
+
+    let operand = match mode {
+        AddrMode::Implied => String::new(),
+        AddrMode::Accumulator => "A".to_string(),
+        AddrMode::Immediate => format!("#${:02X}", byte),
+        AddrMode::ZeroPage => zero_page,
+        AddrMode::ZeroPageX => format!("{},X", zero_page),
+        AddrMode::ZeroPageY => format!("{},Y", zero_page),
+        AddrMode::Absolute => absolute,
+        AddrMode::AbsoluteX => format!("{},X", absolute),
+        AddrMode::AbsoluteY => format!("{},Y", absolute),
+        AddrMode::Indirect => format!("({})", absolute),
+        AddrMode::IndirectX => format!("({},X)", zero_page),
+        AddrMode::IndirectY => format!("({}),Y", zero_page),
+        AddrMode::Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            symbols.describe(cpu.bus, target).unwrap_or(format!("${:04X}", target))
+        }
+    };
+
+    let text = if operand.is_empty() { name.to_string() } else { format!("{} {}", name, operand) };
+
+    DisassembledLine {
+        addr,
+        label,
+        bytes,
+        text,
+    }
+}
@@ -0,0 +1,103 @@
+// CPU accesses to the PPU registers, OAM DMA and mapper registers, stamped
+// with the PPU position, for the event viewer
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventKind {
+    PpuCtrl,
+    PpuMask,
+    PpuStatus,
+    OamAddr,
+    OamData,
+    PpuScroll,
+    PpuAddr,
+    PpuData,
+    OamDma,
+    Mapper,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 10] = [
+        EventKind::PpuCtrl,
+        EventKind::PpuMask,
+        EventKind::PpuStatus,
+        EventKind::OamAddr,
+        EventKind::OamData,
+        EventKind::PpuScroll,
+        EventKind::PpuAddr,
+        EventKind::PpuData,
+        EventKind::OamDma,
+        EventKind::Mapper,
+    ];
+
+    // Which kind of event a CPU access is, if it is one
+    pub fn of(addr: u16, write: bool) -> Option<EventKind> {
+        match addr {
+            0x2000..=0x3FFF => Some(Self::ALL[(addr & 0x0007) as usize]),
+            0x4014 if write => Some(EventKind::OamDma),
+            0x4020..=0x5FFF | 0x8000..=0xFFFF if write => Some(EventKind::Mapper),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::PpuCtrl => "$2000 PPUCTRL",
+            EventKind::PpuMask => "$2001 PPUMASK",
+            EventKind::PpuStatus => "$2002 PPUSTATUS",
+            EventKind::OamAddr => "$2003 OAMADDR",
+            EventKind::OamData => "$2004 OAMDATA",
+            EventKind::PpuScroll => "$2005 PPUSCROLL",
+            EventKind::PpuAddr => "$2006 PPUADDR",
+            EventKind::PpuData => "$2007 PPUDATA",
+            EventKind::OamDma => "$4014 OAMDMA",
+            EventKind::Mapper => "Mapper",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PpuEvent {
+    pub kind: EventKind,
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+    pub scanline: i16,
+    pub dot: i16,
+}
+
+// Events of the frame in progress and of the last complete one
+pub struct EventLog {
+    frame: u32,
+    current: Vec<PpuEvent>,
+    previous: Vec<PpuEvent>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            frame: 0,
+            current: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u32, event: PpuEvent) {
+        if frame != self.frame {
+            self.frame = frame;
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.push(event);
+    }
+
+    // Events of the previous frame and of the given frame so far, allowing
+    // for frames in which nothing was recorded
+    pub fn frames(&self, frame: u32) -> (&[PpuEvent], &[PpuEvent]) {
+        if frame == self.frame {
+            (&self.previous, &self.current)
+        } else if frame == self.frame.wrapping_add(1) {
+            (&self.current, &[])
+        } else {
+            (&[], &[])
+        }
+    }
+}
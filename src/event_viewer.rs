@@ -0,0 +1,197 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::event_log::{EventKind, PpuEvent};
+
+const DOTS: i32 = 341;
+const SCALE: i32 = 2;
+const DIAGRAM_WIDTH: i32 = DOTS * SCALE;
+const LEGEND_X: i32 = DIAGRAM_WIDTH + 12;
+const LINE_HEIGHT: i32 = 20;
+
+const WINDOW_WIDTH: u32 = 920;
+const WINDOW_HEIGHT: u32 = 312 * SCALE as u32; // Tall enough for PAL and Dendy
+
+// Events closer than this to the mouse, in pixels, get a tooltip
+const HOVER_DISTANCE: i32 = 4;
+
+fn kind_color(kind: EventKind) -> Color {
+    match kind {
+        EventKind::PpuCtrl => Color::RGB(255, 80, 80),
+        EventKind::PpuMask => Color::RGB(255, 160, 64),
+        EventKind::PpuStatus => Color::RGB(255, 255, 80),
+        EventKind::OamAddr => Color::RGB(160, 255, 96),
+        EventKind::OamData => Color::RGB(64, 200, 120),
+        EventKind::PpuScroll => Color::RGB(80, 224, 255),
+        EventKind::PpuAddr => Color::RGB(96, 128, 255),
+        EventKind::PpuData => Color::RGB(200, 112, 255),
+        EventKind::OamDma => Color::RGB(255, 112, 200),
+        EventKind::Mapper => Color::RGB(220, 220, 220),
+    }
+}
+
+// Scanline -1 (pre-render) is the top row, as the PPU starts a frame there
+fn event_position(event: &PpuEvent) -> (i32, i32) {
+    (event.dot as i32 * SCALE, (event.scanline as i32 + 1) * SCALE)
+}
+
+// Register accesses of one frame plotted by the dot and scanline they
+// happened on. Keys 1-9 and 0, or a click on the legend, hide a register.
+pub struct EventViewer {
+    window: DebugWindow,
+    shown: [bool; EventKind::ALL.len()],
+    mouse: Option<(i32, i32)>,
+}
+
+impl EventViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(EventViewer {
+            window: DebugWindow::open(video, "Event viewer", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            shown: [true; EventKind::ALL.len()],
+            mouse: None,
+        })
+    }
+
+    fn is_shown(&self, kind: EventKind) -> bool {
+        EventKind::ALL.iter().position(|k| *k == kind).is_some_and(|index| self.shown[index])
+    }
+
+    // The frame so far, completed with the end of the previous frame
+    fn visible_events(&self, cpu: &CPU) -> Vec<PpuEvent> {
+        let Some(log) = &cpu.bus.events else {
+            return Vec::new();
+        };
+        let (previous, current) = log.frames(cpu.bus.ppu.frame());
+        let now = (cpu.bus.ppu.scanline(), cpu.bus.ppu.dot());
+        previous.iter()
+            .filter(|event| (event.scanline, event.dot) > now)
+            .chain(current.iter())
+            .filter(|event| self.is_shown(event.kind))
+            .copied()
+            .collect()
+    }
+
+    fn draw_diagram(&mut self, cpu: &CPU, events: &[PpuEvent]) {
+        let scanlines = cpu.bus.region.scanlines() as i32;
+        let canvas = &mut self.window.canvas;
+
+        canvas.set_draw_color(Color::RGB(40, 40, 40));
+        canvas.fill_rect(Rect::new(0, 0, DIAGRAM_WIDTH as u32, (scanlines * SCALE) as u32)).unwrap();
+        canvas.set_draw_color(Color::RGB(64, 64, 72));
+        canvas.fill_rect(Rect::new(SCALE, SCALE, (256 * SCALE) as u32, (240 * SCALE) as u32)).unwrap();
+
+        // Where the PPU is now
+        let (scanline, dot) = (cpu.bus.ppu.scanline() as i32, cpu.bus.ppu.dot() as i32);
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, 0x60));
+        canvas.fill_rect(Rect::new(0, (scanline + 1) * SCALE, DIAGRAM_WIDTH as u32, SCALE as u32)).unwrap();
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        canvas.fill_rect(Rect::new(dot * SCALE - 2, (scanline + 1) * SCALE - 2, 4 + SCALE as u32, 4 + SCALE as u32)).unwrap();
+
+        for event in events {
+            let (x, y) = event_position(event);
+            canvas.set_draw_color(kind_color(event.kind));
+            canvas.fill_rect(Rect::new(x - 1, y - 1, 2 + SCALE as u32, 2 + SCALE as u32)).unwrap();
+        }
+    }
+
+    fn draw_legend(&mut self, font: &mut Font, events: &[PpuEvent]) {
+        for (i, kind) in EventKind::ALL.iter().enumerate() {
+            let y = 4 + i as i32 * LINE_HEIGHT;
+            let count = events.iter().filter(|event| event.kind == *kind).count();
+            let (color, text_color) = if self.shown[i] {
+                (kind_color(*kind), Color::RGB(255, 255, 255))
+            } else {
+                (Color::RGB(64, 64, 64), Color::RGB(110, 110, 110))
+            };
+
+            self.window.canvas.set_draw_color(color);
+            self.window.canvas.fill_rect(Rect::new(LEGEND_X, y + 3, 12, 12)).unwrap();
+            let text = format!("{} {:<15} {}", (i + 1) % 10, kind.name(), count);
+            self.window.draw_text(font, &text, text_color, LEGEND_X + 18, y);
+        }
+    }
+
+    fn draw_hover(&mut self, font: &mut Font, events: &[PpuEvent]) {
+        let Some((mouse_x, mouse_y)) = self.mouse else {
+            return;
+        };
+        let nearest = events.iter()
+            .map(|event| {
+                let (x, y) = event_position(event);
+                ((x - mouse_x).abs().max((y - mouse_y).abs()), event)
+            })
+            .filter(|(distance, _)| *distance <= HOVER_DISTANCE)
+            .min_by_key(|(distance, _)| *distance);
+
+        if let Some((_, event)) = nearest {
+            let lines = [
+                event.kind.name().to_string(),
+                if event.write {
+                    format!("Write ${:02X} to ${:04X}", event.value, event.addr)
+                } else {
+                    format!("Read ${:02X} from ${:04X}", event.value, event.addr)
+                },
+                format!("Scanline {}, dot {}", event.scanline, event.dot),
+            ];
+            self.window.draw_tooltip(font, &lines, (mouse_x, mouse_y));
+        }
+    }
+
+    fn toggle(&mut self, index: usize) {
+        if let Some(shown) = self.shown.get_mut(index) {
+            *shown = !*shown;
+        }
+    }
+}
+
+impl DebugView for EventViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, _cpu: &mut CPU, _debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                let digit = key.into_i32() - Keycode::Num0.into_i32();
+                if (0..=9).contains(&digit) {
+                    // 1-9 are the first nine registers, 0 the tenth
+                    self.toggle((digit as usize + 9) % 10);
+                }
+            }
+
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if *x >= LEGEND_X && *y >= 4 => {
+                self.toggle(((*y - 4) / LINE_HEIGHT) as usize);
+            }
+
+            Event::MouseMotion { x, y, .. } => {
+                self.mouse = Some((*x, *y));
+            }
+
+            Event::Window { win_event: WindowEvent::Leave, .. } => {
+                self.mouse = None;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, _debugger: &Debugger, font: &mut Font) {
+        self.window.canvas.set_draw_color(Color::RGB(24, 24, 24));
+        self.window.canvas.clear();
+
+        let events = self.visible_events(cpu);
+        self.draw_diagram(cpu, &events);
+        self.draw_legend(font, &events);
+        self.draw_hover(font, &events);
+
+        self.window.canvas.present();
+    }
+}
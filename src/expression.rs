@@ -0,0 +1,313 @@
+// Conditions for the debugger's breakpoints, e.g. `a == $10 && [$0300] != 0`.
+//
+// Numbers are decimal, `$hex` or `0xhex`. `[addr]` reads a CPU byte without
+// side effects. Comparisons and logic operators give 1 or 0.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variable {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+    Value,    // Byte read or written by the access that hit a watchpoint
+    Address,  // Address of that access
+    Scanline,
+    Dot,
+    Frame,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// What an expression can look at while it is evaluated
+pub trait ExprContext {
+    fn variable(&mut self, variable: Variable) -> i64;
+    fn memory(&mut self, addr: u16) -> u8;
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected '{}' in expression", token)),
+        }
+    }
+
+    pub fn evaluate(&self, context: &mut dyn ExprContext) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Variable(variable) => context.variable(*variable),
+            Expr::Memory(addr) => {
+                let addr = addr.evaluate(context);
+                context.memory(addr as u16) as i64
+            }
+            Expr::Not(expr) => (expr.evaluate(context) == 0) as i64,
+            Expr::Negate(expr) => expr.evaluate(context).wrapping_neg(),
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(context);
+                // && and || only look at the right side when they need to
+                match op {
+                    BinaryOp::Or if left != 0 => return 1,
+                    BinaryOp::And if left == 0 => return 0,
+                    _ => {}
+                }
+                let right = right.evaluate(context);
+
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                    BinaryOp::Equal => (left == right) as i64,
+                    BinaryOp::NotEqual => (left != right) as i64,
+                    BinaryOp::Less => (left < right) as i64,
+                    BinaryOp::LessEqual => (left <= right) as i64,
+                    BinaryOp::Greater => (left > right) as i64,
+                    BinaryOp::GreaterEqual => (left >= right) as i64,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
+
+// Parses "$1234", "0x1234" or "1234"
+pub fn parse_number(text: &str) -> Result<i64, String> {
+    let result = if let Some(hex) = text.strip_prefix('$') {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else {
+        text.parse::<i64>()
+    };
+    result.map_err(|_| format!("Invalid number: {}", text))
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '$' || c == '_' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["==", "!=", "<=", ">=", "&&", "||"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                i += 2;
+            } else if "()[]<>!&|^+-".contains(c) {
+                tokens.push(c.to_string());
+                i += 1;
+            } else {
+                return Err(format!("Unexpected '{}' in expression", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Binary operators from the loosest to the tightest binding
+const PRECEDENCE: [&[(&str, BinaryOp)]; 7] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual),
+        ("<", BinaryOp::Less), ("<=", BinaryOp::LessEqual),
+        (">", BinaryOp::Greater), (">=", BinaryOp::GreaterEqual),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("Expected '{}', found '{}'", expected, token)),
+            None => Err(format!("Expected '{}' at the end of the expression", expected)),
+        }
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(token) = self.tokens.get(self.pos)
+            && let Some((_, op)) = PRECEDENCE[level].iter().find(|(name, _)| name == token) {
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(*op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let token = self.next().ok_or("Unexpected end of expression")?;
+        match token.as_str() {
+            "!" => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            "-" => Ok(Expr::Negate(Box::new(self.parse_unary()?))),
+            "(" => {
+                let expr = self.parse_binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            "[" => {
+                let expr = self.parse_binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            _ => {
+                let variable = match token.to_lowercase().as_str() {
+                    "a" => Some(Variable::A),
+                    "x" => Some(Variable::X),
+                    "y" => Some(Variable::Y),
+                    "sp" => Some(Variable::Sp),
+                    "p" => Some(Variable::P),
+                    "pc" => Some(Variable::Pc),
+                    "value" => Some(Variable::Value),
+                    "addr" => Some(Variable::Address),
+                    "scanline" => Some(Variable::Scanline),
+                    "dot" => Some(Variable::Dot),
+                    "frame" => Some(Variable::Frame),
+                    _ => None,
+                };
+                match variable {
+                    Some(variable) => Ok(Expr::Variable(variable)),
+                    None => parse_number(&token).map(Expr::Number),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext {
+        memory: Vec<u8>,
+        reads: Vec<u16>,
+    }
+
+    impl ExprContext for TestContext {
+        fn variable(&mut self, variable: Variable) -> i64 {
+            match variable {
+                Variable::A => 0x10,
+                Variable::X => 2,
+                Variable::Pc => 0x8000,
+                _ => 0,
+            }
+        }
+
+        fn memory(&mut self, addr: u16) -> u8 {
+            self.reads.push(addr);
+            self.memory[addr as usize]
+        }
+    }
+
+    fn evaluate(text: &str) -> (i64, Vec<u16>) {
+        let mut memory = vec![0x00; 0x10000];
+        memory[0x0300] = 5;
+        memory[0x8001] = 0x42;
+        let mut context = TestContext { memory, reads: Vec::new() };
+        let value = Expr::parse(text).unwrap().evaluate(&mut context);
+        (value, context.reads)
+    }
+
+    #[test]
+    fn parses_numbers_in_every_base() {
+        assert_eq!(parse_number("$1F"), Ok(0x1F));
+        assert_eq!(parse_number("0x1F"), Ok(0x1F));
+        assert_eq!(parse_number("0X1f"), Ok(0x1F));
+        assert_eq!(parse_number("31"), Ok(31));
+        assert!(parse_number("$").is_err());
+        assert!(parse_number("1F").is_err());
+    }
+
+    #[test]
+    fn evaluates_with_precedence_and_left_associativity() {
+        assert_eq!(evaluate("5 - 2 - 1").0, 2);
+        assert_eq!(evaluate("$10 + 0x10 + 16").0, 48);
+        assert_eq!(evaluate("-1 + 2").0, 1);
+        // Comparisons bind looser than the bitwise operators
+        assert_eq!(evaluate("4 & 6 == 4").0, 1);
+        assert_eq!(evaluate("1 | 2 ^ 3 & 2").0, 1);
+        assert_eq!(evaluate("1 == 1 && 2 < 1 || 3 >= 3").0, 1);
+        assert_eq!(evaluate("!(1 != 2)").0, 0);
+    }
+
+    #[test]
+    fn reads_variables_and_memory() {
+        assert_eq!(evaluate("a == $10 && [$0300] != 0"), (1, vec![0x0300]));
+        assert_eq!(evaluate("X + A").0, 0x12);
+        assert_eq!(evaluate("[pc + 1]"), (0x42, vec![0x8001]));
+    }
+
+    #[test]
+    fn logic_operators_skip_the_right_side_when_they_can() {
+        assert_eq!(evaluate("0 && [$0300]"), (0, vec![]));
+        assert_eq!(evaluate("1 || [$0300]"), (1, vec![]));
+        assert_eq!(evaluate("1 && [$0300]"), (1, vec![0x0300]));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for text in ["", "1 +", "(1", "[1", "1 2", "a @ 1", "zz", "1 = 1"] {
+            assert!(Expr::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::bus::AddressSpace;
+use crate::cpu::CPU;
+use crate::debugger::Debugger;
+
+// Registers in the order of the g packet: a, x, y, p, sp (one byte each),
+// then pc as two little-endian bytes
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nes.6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8" regnum="1"/>
+    <reg name="y" bitsize="8" type="uint8" regnum="2"/>
+    <reg name="p" bitsize="8" type="uint8" regnum="3"/>
+    <reg name="sp" bitsize="8" type="uint8" regnum="4"/>
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="5"/>
+  </feature>
+</target>
+"#;
+
+const MAX_MEMORY_READ: usize = 0x800;
+
+// Stop replies
+const SIGINT: &str = "S02";
+const SIGTRAP: &str = "S05";
+
+enum Reply {
+    Packet(String),
+    Detach,
+    Kill,
+}
+
+// One GDB client over TCP, speaking the $<data>#<checksum> framing
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> Result<Option<u8>, String> {
+        if self.position == self.buffer.len() {
+            let mut chunk = [0u8; 1024];
+            let count = self.stream.read(&mut chunk).map_err(|e| e.to_string())?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.buffer = chunk[..count].to_vec();
+            self.position = 0;
+        }
+        self.position += 1;
+        Ok(Some(self.buffer[self.position - 1]))
+    }
+
+    // Next packet, acknowledged. A Ctrl-C outside a packet comes back as "\x03";
+    // None when the client hung up.
+    fn read_packet(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some("\x03".to_string())),
+                Some(b'$') => {}
+                Some(_) => continue, // Acks and line noise
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.send_raw(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+            }
+            self.send_raw(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> Result<(), String> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.send_raw(packet.as_bytes())
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream.write_all(bytes).map_err(|e| e.to_string())
+    }
+
+    // Polls for a Ctrl-C while the target runs, without blocking
+    fn interrupted(&mut self) -> Result<bool, String> {
+        if self.position < self.buffer.len() {
+            let pending = self.buffer[self.position..].contains(&0x03);
+            self.position = self.buffer.len();
+            return Ok(pending);
+        }
+
+        self.stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let mut chunk = [0u8; 64];
+        let result = self.stream.read(&mut chunk);
+        self.stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+
+        match result {
+            Ok(count) => Ok(chunk[..count].contains(&0x03)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+struct Session {
+    debugger: Debugger,
+    // GDB names breakpoints by (type, address, kind); the debugger by id
+    breakpoints: Vec<((u8, u16, u16), usize)>,
+}
+
+impl Session {
+    fn registers(cpu: &CPU) -> Vec<u8> {
+        let registers = &cpu.registers;
+        let pc = registers.pc.to_le_bytes();
+        vec![registers.a, registers.x, registers.y, registers.f, registers.sp, pc[0], pc[1]]
+    }
+
+    fn set_register(cpu: &mut CPU, number: usize, bytes: &[u8]) -> Option<()> {
+        let registers = &mut cpu.registers;
+        match number {
+            0 => registers.a = *bytes.first()?,
+            1 => registers.x = *bytes.first()?,
+            2 => registers.y = *bytes.first()?,
+            3 => registers.f = *bytes.first()?,
+            4 => registers.sp = *bytes.first()?,
+            5 => registers.pc = u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_memory(cpu: &mut CPU, args: &str) -> Option<String> {
+        let (addr, length) = args.split_once(',')?;
+        let addr = parse_hex(addr)?;
+        let length = parse_hex(length)?.min(MAX_MEMORY_READ);
+        let bytes: Vec<u8> = (0..length).map(|i| cpu.bus.peek((addr + i) as u16)).collect();
+        Some(to_hex(&bytes))
+    }
+
+    fn write_memory(cpu: &mut CPU, args: &str) -> Option<()> {
+        let (location, data) = args.split_once(':')?;
+        let (addr, length) = location.split_once(',')?;
+        let addr = parse_hex(addr)?;
+        let bytes = hex_bytes(data)?;
+        if bytes.len() != parse_hex(length)? {
+            return None;
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.bus.poke((addr + i) as u16, *byte);
+        }
+        Some(())
+    }
+
+    // Z/z packets: 0 and 1 are execution breakpoints, 2 write, 3 read and
+    // 4 access watchpoints whose kind is the watched length
+    fn breakpoint_key(args: &str) -> Option<(u8, u16, u16)> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?.parse().ok()?;
+        let addr = parse_hex(fields.next()?)? as u16;
+        let length = parse_hex(fields.next()?)? as u16;
+        Some((kind, addr, length))
+    }
+
+    fn insert_breakpoint(&mut self, args: &str) -> Option<()> {
+        let key = Self::breakpoint_key(args)?;
+        if self.breakpoints.iter().any(|(existing, _)| *existing == key) {
+            return Some(());
+        }
+
+        let (kind, addr, length) = key;
+        let (range, read, write, execute) = match kind {
+            0 | 1 => (addr..=addr, false, false, true),
+            2..=4 => {
+                let end = addr.wrapping_add(length.max(1) - 1).max(addr);
+                (addr..=end, kind != 2, kind != 3, false)
+            }
+            _ => return None,
+        };
+        let id = self.debugger.add_breakpoint(AddressSpace::Cpu, range, read, write, execute, None).ok()?;
+        self.breakpoints.push((key, id));
+        Some(())
+    }
+
+    fn remove_breakpoint(&mut self, args: &str) -> Option<()> {
+        let key = Self::breakpoint_key(args)?;
+        let index = self.breakpoints.iter().position(|(existing, _)| *existing == key)?;
+        let (_, id) = self.breakpoints.remove(index);
+        self.debugger.remove_breakpoint(id).ok()
+    }
+
+    // Runs until a breakpoint or a Ctrl-C from the client
+    fn run(&mut self, cpu: &mut CPU, connection: &mut Connection) -> Result<&'static str, String> {
+        self.debugger.resume();
+        loop {
+            self.debugger.run_frame(cpu);
+            if self.debugger.paused {
+                return Ok(SIGTRAP);
+            }
+            if connection.interrupted()? {
+                self.debugger.pause("Interrupted");
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    fn target_xml(args: &str) -> Option<String> {
+        let (offset, length) = args.strip_prefix("target.xml:")?.split_once(',')?;
+        let offset = parse_hex(offset)?.min(TARGET_XML.len());
+        let end = (offset + parse_hex(length)?).min(TARGET_XML.len());
+        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+        Some(format!("{}{}", marker, &TARGET_XML[offset..end]))
+    }
+
+    fn handle(&mut self, cpu: &mut CPU, connection: &mut Connection, packet: &str) -> Result<Reply, String> {
+        let error = || "E01".to_string();
+        let ok = |result: Option<()>| result.map(|_| "OK".to_string()).unwrap_or_else(error);
+
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "\x03" => {
+                self.debugger.pause("Interrupted");
+                SIGINT.to_string()
+            }
+            "?" => SIGTRAP.to_string(),
+
+            "g" => to_hex(&Self::registers(cpu)),
+            "G" => {
+                let result = hex_bytes(args).filter(|bytes| bytes.len() == 7).map(|bytes| {
+                    // Each register starts at its own number, pc taking the last two bytes
+                    for number in 0..6 {
+                        Self::set_register(cpu, number, &bytes[number..]);
+                    }
+                });
+                ok(result)
+            }
+            "p" => match parse_hex(args) {
+                Some(5) => to_hex(&cpu.registers.pc.to_le_bytes()),
+                Some(number) if number < 5 => to_hex(&Self::registers(cpu)[number..number + 1]),
+                _ => error(),
+            },
+            "P" => {
+                let result = args.split_once('=')
+                    .and_then(|(number, value)| Self::set_register(cpu, parse_hex(number)?, &hex_bytes(value)?));
+                ok(result)
+            }
+
+            "m" => Self::read_memory(cpu, args).unwrap_or_else(error),
+            "M" => ok(Self::write_memory(cpu, args)),
+
+            "c" | "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.registers.pc = addr as u16;
+                }
+                if command == "s" {
+                    self.debugger.step_into(cpu);
+                    SIGTRAP.to_string()
+                } else {
+                    self.run(cpu, connection)?.to_string()
+                }
+            }
+
+            "Z" => ok(self.insert_breakpoint(args)),
+            "z" => ok(self.remove_breakpoint(args)),
+
+            "H" | "T" => "OK".to_string(),
+            "D" => return Ok(Reply::Detach),
+            "k" => return Ok(Reply::Kill),
+
+            "q" => {
+                if args.starts_with("Supported") {
+                    "PacketSize=4000;qXfer:features:read+".to_string()
+                } else if let Some(xfer) = args.strip_prefix("Xfer:features:read:") {
+                    Self::target_xml(xfer).unwrap_or_else(error)
+                } else {
+                    match args {
+                        "Attached" => "1".to_string(),
+                        "C" => "QC1".to_string(),
+                        "fThreadInfo" => "m1".to_string(),
+                        "sThreadInfo" => "l".to_string(),
+                        _ => String::new(),
+                    }
+                }
+            }
+
+            // Anything else is unsupported, which an empty reply tells the client
+            _ => String::new(),
+        };
+        Ok(Reply::Packet(reply))
+    }
+}
+
+// Waits for one GDB client on localhost and serves it until it detaches or
+// kills the target. The CPU is halted while the client is attached and not
+// continuing, and runs unthrottled without video or sound otherwise.
+pub fn serve(cpu: &mut CPU, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    println!("Waiting for GDB on 127.0.0.1:{}", port);
+    let (stream, client) = listener.accept().map_err(|e| e.to_string())?;
+    println!("GDB attached from {}", client);
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+
+    let mut connection = Connection {
+        stream,
+        buffer: Vec::new(),
+        position: 0,
+    };
+    let mut session = Session {
+        debugger: Debugger::new(),
+        breakpoints: Vec::new(),
+    };
+    session.debugger.pause("Attached");
+
+    while let Some(packet) = connection.read_packet()? {
+        match session.handle(cpu, &mut connection, &packet)? {
+            Reply::Packet(reply) => connection.send_packet(&reply)?,
+            Reply::Detach => {
+                connection.send_packet("OK")?;
+                break;
+            }
+            Reply::Kill => break,
+        }
+    }
+
+    println!("GDB detached");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A connection to a local client socket the test writes to
+    fn connect() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (Connection { stream, buffer: Vec::new(), position: 0 }, client)
+    }
+
+    fn received(client: &mut TcpStream, count: usize) -> String {
+        let mut bytes = vec![0u8; count];
+        client.read_exact(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn checksums_are_the_byte_sum_modulo_256() {
+        assert_eq!(checksum_of(b""), 0x00);
+        assert_eq!(checksum_of(b"OK"), 0x9A);
+        assert_eq!(checksum_of(b"qSupported"), 0x37);
+        assert_eq!(checksum_of(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn reads_and_acknowledges_packets() {
+        let (mut connection, mut client) = connect();
+        client.write_all(b"+$g#67$m8000,2#ff$m8000,2#93").unwrap();
+
+        assert_eq!(connection.read_packet().unwrap().as_deref(), Some("g"));
+        assert_eq!(received(&mut client, 1), "+");
+
+        // A bad checksum is refused and the resent packet taken
+        assert_eq!(connection.read_packet().unwrap().as_deref(), Some("m8000,2"));
+        assert_eq!(received(&mut client, 2), "-+");
+    }
+
+    #[test]
+    fn reads_interrupts_and_hang_ups() {
+        let (mut connection, client) = connect();
+        (&client).write_all(b"\x03$c#6").unwrap();
+        assert_eq!(connection.read_packet().unwrap().as_deref(), Some("\x03"));
+
+        // The client leaves in the middle of a packet
+        drop(client);
+        assert_eq!(connection.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn frames_sent_packets() {
+        let (mut connection, mut client) = connect();
+        connection.send_packet("OK").unwrap();
+        connection.send_packet("").unwrap();
+        assert_eq!(received(&mut client, 10), "$OK#9a$#00");
+    }
+
+    #[test]
+    fn converts_hex_payloads() {
+        assert_eq!(hex_bytes("00a9FF"), Some(vec![0x00, 0xA9, 0xFF]));
+        assert_eq!(hex_bytes("abc"), None);
+        assert_eq!(hex_bytes("zz"), None);
+        assert_eq!(to_hex(&[0x00, 0xA9, 0xFF]), "00a9ff");
+        assert_eq!(parse_hex("8000"), Some(0x8000));
+    }
+
+    #[test]
+    fn serves_the_target_description_in_chunks() {
+        let first = Session::target_xml("target.xml:0,10").unwrap();
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+
+        let rest = Session::target_xml(&format!("target.xml:10,{:x}", TARGET_XML.len())).unwrap();
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x10..]));
+        assert_eq!(Session::target_xml("other.xml:0,10"), None);
+    }
+}
@@ -1,5 +1,8 @@
 use std::fs;
 
+use crate::mapper::{new_mapper, Mapper, Mirroring};
+use crate::region::Region;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 
 struct Header {
@@ -8,6 +11,15 @@ struct Header {
     chr_rom_size: u8,
     flags6: u8,
     flags7: u8,
+    prg_ram_size: u8, // iNES byte 8, in 8KB units
+    flags9: u8,
+    timing: u8, // NES 2.0 byte 12
+}
+
+impl Header {
+    fn mapper_number(&self) -> u8 {
+        (self.flags7 & 0xF0) | (self.flags6 >> 4)
+    }
 }
 
 pub struct Rom {
@@ -15,18 +27,29 @@ pub struct Rom {
     pub trainer: Vec<u8>,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub screen_mirroring: bool, // 0: Vertical & 1: Horizontal
+    pub chr_ram: bool, // Boards without CHR ROM have 8KB of RAM instead
+    pub mirroring: Mirroring, // As wired on the board, mappers may override it
+    pub mapper: Box<dyn Mapper>,
+
+    // Nametable pages 2 and 3 of four-screen boards
+    pub extra_vram: Vec<u8>,
+
+    // Work RAM at $6000-$7FFF, on boards with a battery or a PRG RAM size in the header
+    pub prg_ram: Vec<u8>,
 }
 
 impl Rom {
     pub fn new(file_path: String) -> Self {
-        
+        Self::from_bytes(&fs::read(file_path).unwrap())
+    }
+
+    pub fn from_bytes(file_data: &[u8]) -> Self {
         const PRG_ROM_UNIT: usize = 16 * 1024;
         const CHR_ROM_UNIT: usize = 8 * 1024;
+        const PRG_RAM_UNIT: usize = 8 * 1024;
         const HEADER_SIZE: usize = 16;
         const TRAINER_SIZE: usize = 512;
 
-        let file_data: Vec<u8> = fs::read(file_path).unwrap();
         if &file_data[0..4] == &NES_TAG {
             println!("The file is a valid ines format file!");
         } else {
@@ -39,9 +62,19 @@ impl Rom {
             chr_rom_size: file_data[5],
             flags6: file_data[6],
             flags7: file_data[7],
+            prg_ram_size: file_data[8],
+            flags9: file_data[9],
+            timing: file_data[12],
         };
 
-        let screen_mirroring = (header.flags6 & 0x01) != 0;
+        let four_screen = (header.flags6 & 0x08) != 0;
+        let mirroring = if four_screen {
+            Mirroring::FourScreen
+        } else if (header.flags6 & 0x01) != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
 
         let trainer_present = header.flags6 & (1 << 2) != 0;
         let trainer_len = if trainer_present {TRAINER_SIZE} else {0};
@@ -58,15 +91,90 @@ impl Rom {
         let chr_rom_start = prg_rom_end;
         let chr_rom_end = chr_rom_start + chr_rom_len;
         
-        let chr_rom = file_data[chr_rom_start..chr_rom_end].to_vec();
+        let chr_ram = chr_rom_len == 0;
+        let chr_rom = if chr_ram {
+            vec![0x00; CHR_ROM_UNIT]
+        } else {
+            file_data[chr_rom_start..chr_rom_end].to_vec()
+        };
+
+        let battery = (header.flags6 & 0x02) != 0;
+        let prg_ram_len = if battery || header.prg_ram_size > 0 { PRG_RAM_UNIT } else { 0 };
+
+        let mapper = new_mapper(header.mapper_number(), header.prg_rom_size);
 
         Rom {
             header: header,
             trainer: trainer,
             prg_rom: prg_rom,
             chr_rom: chr_rom,
-            screen_mirroring: screen_mirroring,
+            chr_ram,
+            mirroring,
+            mapper,
+
+            extra_vram: vec![0x00; if four_screen { 2 * 1024 } else { 0 }],
+            prg_ram: vec![0x00; prg_ram_len],
 
         }
     }
+
+    // Puts the board back as it powers up: fresh mapper registers, blank RAM
+    pub fn power_on(&mut self) {
+        self.mapper = new_mapper(self.header.mapper_number(), self.header.prg_rom_size);
+        if self.chr_ram {
+            self.chr_rom.fill(0x00);
+        }
+        self.extra_vram.fill(0x00);
+        self.prg_ram.fill(0x00);
+    }
+
+    pub fn current_mirroring(&self) -> Mirroring {
+        self.mapper.mirroring().unwrap_or(self.mirroring)
+    }
+
+    // Nametable page (0-3) backing logical nametable `table` (0-3)
+    pub fn nametable_page(&self, table: u16) -> usize {
+        match self.current_mirroring() {
+            Mirroring::Horizontal => (table / 2) as usize,
+            Mirroring::Vertical => (table % 2) as usize,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table as usize,
+        }
+    }
+
+    // Region requested by the header. NES 2.0 has a timing field; plain iNES
+    // only has the rarely set TV system bit in flags 9.
+    pub fn region(&self) -> Region {
+        let nes2 = (self.header.flags7 & 0x0C) == 0x08;
+
+        if nes2 {
+            match self.header.timing & 0x03 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc, // 2 is multi-region, those games run fine as NTSC
+            }
+        } else if (self.header.flags9 & 0x01) != 0 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+}
+
+// NROM image with `program` at $8000 and `nmi_handler` at $9000. IRQs land on
+// an RTI at $FFF0. CHR ROM is blank.
+#[cfg(test)]
+pub fn test_rom(program: &[u8], nmi_handler: &[u8]) -> Rom {
+    let mut prg = vec![0xEA; 16 * 1024]; // NOP
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x1000..0x1000 + nmi_handler.len()].copy_from_slice(nmi_handler);
+    prg[0x3FF0] = 0x40; // RTI
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0xF0, 0xFF]);
+
+    let mut data = NES_TAG.to_vec();
+    data.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(&prg);
+    data.extend_from_slice(&[0x00; 8 * 1024]);
+    Rom::from_bytes(&data)
 }
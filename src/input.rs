@@ -0,0 +1,461 @@
+use std::fs;
+
+use crate::controller::{Button, Controller};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Button(usize, u8), // (port, button mask)
+    Turbo(usize, u8),  // (port, button mask), toggled every `turbo_rate` frames
+    Reset,
+    Pause,
+    SaveState,
+    LoadState,
+    Screenshot,
+    Step,
+    Nmi,
+    FastForward, // Held
+    SlowMotion,  // Toggled
+    NametableViewer,
+    PatternViewer,
+    OamViewer,
+    DebuggerViewer,
+    MemoryViewer,
+    CallStackViewer,
+    ProfilerViewer,
+    EventViewer,
+}
+
+impl Action {
+    // Moves a controller action to another port, used for gamepads.
+    pub fn with_port(self, port: usize) -> Action {
+        match self {
+            Action::Button(_, mask) => Action::Button(port, mask),
+            Action::Turbo(_, mask) => Action::Turbo(port, mask),
+            other => other,
+        }
+    }
+}
+
+// Bindings are kept as key and button names so this module does not depend on SDL.
+pub struct InputConfig {
+    pub keys: Vec<(String, Action)>,
+    pub turbo_rate: u32,
+
+    // Gamepad bindings use port 0; the front-end moves them to the pad's port.
+    pub pad_buttons: Vec<(String, Action)>,
+    pub pad_analog: bool,
+    pub pad_dead_zone: i16,
+    pub pad_mapping_db: Option<String>,
+}
+
+const PLAYER_BUTTONS: [(&str, u8); 8] = [
+    ("up", Button::UP),
+    ("down", Button::DOWN),
+    ("left", Button::LEFT),
+    ("right", Button::RIGHT),
+    ("a", Button::A),
+    ("b", Button::B),
+    ("select", Button::SELECT),
+    ("start", Button::START),
+];
+
+const DEFAULT_CONFIG: &str = "
+[player1]
+up = Up
+down = Down
+left = Left
+right = Right
+a = Z
+b = X
+select = Right Shift
+start = Return
+turbo_a = A
+turbo_b = S
+
+[player2]
+up = I
+down = K
+left = J
+right = L
+a = H
+b = G
+select = T
+start = Y
+
+[hotkeys]
+reset = R
+pause = P
+save_state = F5
+load_state = F7
+screenshot = F12
+step = Space
+nmi = N
+fast_forward = Tab
+slow_motion = Backspace
+nametable_viewer = F1
+pattern_viewer = F2
+oam_viewer = F3
+debugger = F4
+memory_viewer = F6
+call_stack = F8
+profiler = F9
+event_viewer = F10
+
+[turbo]
+rate = 2
+
+[gamepad]
+up = dpup
+down = dpdown
+left = dpleft
+right = dpright
+a = b
+b = a
+select = back
+start = start
+turbo_a = y
+turbo_b = x
+analog = true
+dead_zone = 8000
+";
+
+impl InputConfig {
+    pub fn default_config() -> Self {
+        Self::parse(DEFAULT_CONFIG).unwrap()
+    }
+
+    // Loads an INI key map. Missing files fall back to the default bindings.
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        match fs::read_to_string(file_path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Ok(Self::default_config()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = InputConfig {
+            keys: Vec::new(),
+            turbo_rate: 2,
+            pad_buttons: Vec::new(),
+            pad_analog: true,
+            pad_dead_zone: 8000,
+            pad_mapping_db: None,
+        };
+        let mut section = String::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_lowercase();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or(format!("Expected 'name = value' at line {}", line_number + 1))?;
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+
+            if section == "turbo" {
+                if key == "rate" {
+                    config.turbo_rate = value.parse::<u32>()
+                        .map_err(|_| format!("Invalid turbo rate at line {}", line_number + 1))?
+                        .max(1);
+                }
+                continue;
+            }
+
+            if section == "gamepad" {
+                match key.as_str() {
+                    "analog" => {
+                        config.pad_analog = value == "true" || value == "1";
+                        continue;
+                    }
+                    "dead_zone" => {
+                        // A distance from the stick centre, 0-32767
+                        config.pad_dead_zone = value.parse::<i16>().ok()
+                            .filter(|dead_zone| *dead_zone >= 0)
+                            .ok_or_else(|| format!("Invalid dead zone at line {}", line_number + 1))?;
+                        continue;
+                    }
+                    "mapping_db" => {
+                        config.pad_mapping_db = Some(value.to_string());
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            let action = parse_action(&section, &key)
+                .ok_or(format!("Unknown binding '{}' in [{}] at line {}", key, section, line_number + 1))?;
+
+            let bindings = if section == "gamepad" { &mut config.pad_buttons } else { &mut config.keys };
+
+            // Several keys can be bound to the same action: "a = Z, Space"
+            for key_name in value.split(',').map(|k| k.trim()).filter(|k| !k.is_empty()) {
+                bindings.push((key_name.to_string(), action));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_hotkey(key: &str) -> Option<Action> {
+    match key {
+        "reset" => Some(Action::Reset),
+        "pause" => Some(Action::Pause),
+        "save_state" => Some(Action::SaveState),
+        "load_state" => Some(Action::LoadState),
+        "screenshot" => Some(Action::Screenshot),
+        "step" => Some(Action::Step),
+        "nmi" => Some(Action::Nmi),
+        "fast_forward" => Some(Action::FastForward),
+        "slow_motion" => Some(Action::SlowMotion),
+        "nametable_viewer" => Some(Action::NametableViewer),
+        "pattern_viewer" => Some(Action::PatternViewer),
+        "oam_viewer" => Some(Action::OamViewer),
+        "debugger" => Some(Action::DebuggerViewer),
+        "memory_viewer" => Some(Action::MemoryViewer),
+        "call_stack" => Some(Action::CallStackViewer),
+        "profiler" => Some(Action::ProfilerViewer),
+        "event_viewer" => Some(Action::EventViewer),
+        _ => None,
+    }
+}
+
+fn parse_action(section: &str, key: &str) -> Option<Action> {
+    let port = match section {
+        "player1" | "gamepad" => 0,
+        "player2" => 1,
+        "hotkeys" => return parse_hotkey(key),
+        _ => return None,
+    };
+
+    if section == "gamepad" && let Some(action) = parse_hotkey(key) {
+        return Some(action);
+    }
+
+    if let Some(button) = key.strip_prefix("turbo_") {
+        return PLAYER_BUTTONS.iter()
+            .find(|(name, _)| *name == button)
+            .map(|(_, mask)| Action::Turbo(port, *mask));
+    }
+
+    PLAYER_BUTTONS.iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, mask)| Action::Button(port, *mask))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    Keyboard,
+    Gamepad,
+}
+
+// Buttons held on one kind of device, per port
+#[derive(Clone, Copy, Default)]
+struct HeldButtons {
+    held: [u8; 2],
+    turbo_held: [u8; 2],
+}
+
+// Button state of both ports, gathered from every input device. Keyboard and
+// gamepads are tracked apart so unplugging a pad leaves the keys alone.
+pub struct InputState {
+    keyboard: HeldButtons,
+    gamepad: HeldButtons,
+    analog: [u8; 2],
+    turbo_rate: u32,
+    frame: u32,
+}
+
+impl InputState {
+    pub fn new(turbo_rate: u32) -> Self {
+        InputState {
+            keyboard: HeldButtons::default(),
+            gamepad: HeldButtons::default(),
+            analog: [0, 0],
+            turbo_rate: turbo_rate.max(1),
+            frame: 0,
+        }
+    }
+
+    // Converts one analog stick axis into the matching pair of D-pad directions.
+    pub fn set_axis(&mut self, port: usize, vertical: bool, value: i16, dead_zone: i16) {
+        let (negative, positive) = if vertical {
+            (Button::UP, Button::DOWN)
+        } else {
+            (Button::LEFT, Button::RIGHT)
+        };
+
+        self.analog[port] &= !(negative | positive);
+        if value < -dead_zone {
+            self.analog[port] |= negative;
+        } else if value > dead_zone {
+            self.analog[port] |= positive;
+        }
+    }
+
+    // Drops what the gamepad of a port holds, when it is unplugged.
+    pub fn release_pad(&mut self, port: usize) {
+        self.gamepad.held[port] = 0;
+        self.gamepad.turbo_held[port] = 0;
+        self.analog[port] = 0;
+    }
+
+    // Returns true when the action is a controller button and was handled here.
+    pub fn set_action(&mut self, action: Action, pressed: bool, device: Device) -> bool {
+        let buttons = match device {
+            Device::Keyboard => &mut self.keyboard,
+            Device::Gamepad => &mut self.gamepad,
+        };
+        let (state, port, mask) = match action {
+            Action::Button(port, mask) => (&mut buttons.held, port, mask),
+            Action::Turbo(port, mask) => (&mut buttons.turbo_held, port, mask),
+            _ => return false,
+        };
+
+        if pressed {
+            state[port] |= mask;
+        } else {
+            state[port] &= !mask;
+        }
+        true
+    }
+
+    // Latches the current state into the controllers, once per frame.
+    pub fn apply(&mut self, controllers: &mut [Controller; 2]) {
+        let turbo_on = (self.frame / self.turbo_rate).is_multiple_of(2);
+        self.frame = self.frame.wrapping_add(1);
+
+        for (port, controller) in controllers.iter_mut().enumerate() {
+            let turbo_held = self.keyboard.turbo_held[port] | self.gamepad.turbo_held[port];
+            let turbo = if turbo_on { turbo_held } else { 0 };
+            let buttons = self.keyboard.held[port] | self.gamepad.held[port] | self.analog[port] | turbo;
+
+            for bit in 0..8 {
+                controller.set_button(1 << bit, buttons & (1 << bit) != 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(bindings: &[(String, Action)], name: &str) -> Option<Action> {
+        bindings.iter().find(|(key, _)| key == name).map(|(_, action)| *action)
+    }
+
+    #[test]
+    fn default_config_binds_both_players_and_hotkeys() {
+        let config = InputConfig::default_config();
+
+        assert_eq!(binding(&config.keys, "Z"), Some(Action::Button(0, Button::A)));
+        assert_eq!(binding(&config.keys, "Right Shift"), Some(Action::Button(0, Button::SELECT)));
+        assert_eq!(binding(&config.keys, "H"), Some(Action::Button(1, Button::A)));
+        assert_eq!(binding(&config.keys, "F5"), Some(Action::SaveState));
+        assert_eq!(binding(&config.pad_buttons, "dpup"), Some(Action::Button(0, Button::UP)));
+        assert_eq!(binding(&config.pad_buttons, "y"), Some(Action::Turbo(0, Button::A)));
+        assert_eq!(config.turbo_rate, 2);
+    }
+
+    #[test]
+    fn parses_sections_lists_and_comments() {
+        let config = InputConfig::parse("
+            ; player one
+            [Player1]
+            A = Z, Space   # two keys
+            turbo_b = X
+
+            [player2]
+            start = Return
+
+            [hotkeys]
+            pause = P
+
+            [turbo]
+            rate = 0
+
+            [gamepad]
+            a = b
+            reset = guide
+            analog = false
+            dead_zone = 1000
+            mapping_db = pads.txt
+        ").unwrap();
+
+        assert_eq!(binding(&config.keys, "Z"), Some(Action::Button(0, Button::A)));
+        assert_eq!(binding(&config.keys, "Space"), Some(Action::Button(0, Button::A)));
+        assert_eq!(binding(&config.keys, "X"), Some(Action::Turbo(0, Button::B)));
+        assert_eq!(binding(&config.keys, "Return"), Some(Action::Button(1, Button::START)));
+        assert_eq!(binding(&config.keys, "P"), Some(Action::Pause));
+        assert_eq!(config.turbo_rate, 1);
+        assert_eq!(binding(&config.pad_buttons, "b"), Some(Action::Button(0, Button::A)));
+        assert_eq!(binding(&config.pad_buttons, "guide"), Some(Action::Reset));
+        assert!(!config.pad_analog);
+        assert_eq!(config.pad_dead_zone, 1000);
+        assert_eq!(config.pad_mapping_db.as_deref(), Some("pads.txt"));
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_entries() {
+        let error = InputConfig::parse("[player1]\na = Z\njump = Space\n").err().unwrap();
+        assert!(error.contains("'jump'") && error.contains("line 3"), "{}", error);
+
+        let error = InputConfig::parse("[hotkeys]\nreset\n").err().unwrap();
+        assert!(error.contains("line 2"), "{}", error);
+
+        assert!(InputConfig::parse("[turbo]\nrate = fast\n").is_err());
+        assert!(InputConfig::parse("[gamepad]\ndead_zone = 40000\n").is_err());
+        assert!(InputConfig::parse("[gamepad]\ndead_zone = -100\n").is_err());
+        assert!(InputConfig::parse("[player3]\na = Z\n").is_err());
+    }
+
+    fn latch(input: &mut InputState) -> [u8; 2] {
+        let mut controllers = [Controller::new(), Controller::new()];
+        input.apply(&mut controllers);
+        [controllers[0].buttons, controllers[1].buttons]
+    }
+
+    #[test]
+    fn merges_keyboard_gamepad_and_stick() {
+        let mut input = InputState::new(2);
+        input.set_action(Action::Button(0, Button::A), true, Device::Keyboard);
+        input.set_action(Action::Button(0, Button::B), true, Device::Gamepad);
+        input.set_axis(1, false, -20000, 8000);
+
+        assert_eq!(latch(&mut input), [Button::A | Button::B, Button::LEFT]);
+        assert!(!input.set_action(Action::Pause, true, Device::Keyboard));
+    }
+
+    #[test]
+    fn unplugging_a_pad_keeps_the_keyboard_buttons() {
+        let mut input = InputState::new(2);
+        input.set_action(Action::Button(0, Button::A), true, Device::Keyboard);
+        input.set_action(Action::Button(0, Button::A), true, Device::Gamepad);
+        input.set_action(Action::Button(0, Button::START), true, Device::Gamepad);
+        input.set_axis(0, true, 20000, 8000);
+
+        input.release_pad(0);
+        assert_eq!(latch(&mut input), [Button::A, 0]);
+
+        // Releasing the key the pad also held still lets go of the button
+        input.set_action(Action::Button(0, Button::A), false, Device::Keyboard);
+        assert_eq!(latch(&mut input), [0, 0]);
+    }
+
+    #[test]
+    fn turbo_toggles_every_rate_frames() {
+        let mut input = InputState::new(2);
+        input.set_action(Action::Turbo(1, Button::B), true, Device::Gamepad);
+
+        let pressed: Vec<bool> = (0..6).map(|_| latch(&mut input)[1] == Button::B).collect();
+        assert_eq!(pressed, [true, true, false, false, true, true]);
+    }
+}
@@ -1,29 +1,141 @@
 mod bus;
 mod cpu;
 mod ppu;
+mod apu;
+mod audio;
 mod sdl_ui;
 mod ines_file;
+mod mapper;
 mod controller;
+mod savestate;
+mod movie;
+mod runner;
+mod png;
+mod screenshot;
+mod input;
+mod pacing;
+mod region;
+mod debug_window;
+mod nametable_viewer;
+mod pattern_viewer;
+mod oam_viewer;
+mod expression;
+mod disassembler;
+mod debugger;
+mod debugger_viewer;
+mod memory_viewer;
+mod gdb_stub;
+mod symbols;
+mod call_stack;
+mod call_stack_viewer;
+mod cdl;
+mod profiler;
+mod profiler_viewer;
+mod event_log;
+mod event_viewer;
 
 use bus::Bus;
+use call_stack::CallStack;
+use cdl::CodeDataLog;
+use profiler::Profiler;
+use symbols::SymbolTable;
 use cpu::CPU;
 use ines_file::Rom;
-use ppu::PPU;
-use controller::Controller;
+use movie::MovieSession;
+use screenshot::ScreenshotOptions;
+use pacing::SyncMode;
+use region::Region;
+
+pub struct Options {
+    pub rom_path: String,
+    pub record_movie: Option<String>,
+    pub record_from_state: Option<String>, // Save state the recording starts from
+    pub play_movie: Option<String>,
+    pub headless_frames: Option<usize>,
+    pub golden_frame: Option<String>,
+    pub screenshot_path: Option<String>,
+    pub dump_frame_path: Option<String>,
+    pub screenshot: ScreenshotOptions,
+    pub keys_path: String,
+    pub audio: bool,
+    pub sample_rate: u32,
+    pub sync_mode: SyncMode,
+    pub fast_forward_speed: f64,
+    pub slow_motion_speed: f64,
+    pub region: Option<Region>, // None: taken from the ROM header
+    pub cycle_stepped: bool,
+    pub gdb_port: Option<u16>,
+    pub symbol_files: Vec<String>,
+    pub cdl_path: Option<String>,
+    pub profile_path: Option<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        rom_path: "iceclimber.nes".to_string(),
+        record_movie: None,
+        record_from_state: None,
+        play_movie: None,
+        headless_frames: None,
+        golden_frame: None,
+        screenshot_path: None,
+        dump_frame_path: None,
+        screenshot: ScreenshotOptions::new(),
+        keys_path: "keys.ini".to_string(),
+        audio: true,
+        sample_rate: 48000,
+        sync_mode: SyncMode::Timer,
+        fast_forward_speed: 0.0,
+        slow_motion_speed: 0.5,
+        region: None,
+        cycle_stepped: false,
+        gdb_port: None,
+        symbol_files: Vec::new(),
+        cdl_path: None,
+        profile_path: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => options.record_movie = args.next(),
+            "--record-from-state" => options.record_from_state = args.next(),
+            "--play" => options.play_movie = args.next(),
+            "--headless" => options.headless_frames = args.next().and_then(|n| n.parse().ok()),
+            "--golden" => options.golden_frame = args.next(),
+            "--screenshot" => options.screenshot_path = args.next(),
+            "--dump-frame" => options.dump_frame_path = args.next(),
+            "--scale" => options.screenshot.scale = args.next().and_then(|n| n.parse().ok()).unwrap_or(1),
+            "--crop-overscan" => options.screenshot.crop_overscan = true,
+            "--no-audio" => options.audio = false,
+            "--sample-rate" => options.sample_rate = args.next().and_then(|n| n.parse().ok()).unwrap_or(48000),
+            "--sync" => options.sync_mode = args.next().and_then(|m| SyncMode::from_name(&m)).unwrap_or(SyncMode::Timer),
+            "--fast-forward" => options.fast_forward_speed = args.next().and_then(|n| n.parse().ok()).unwrap_or(0.0),
+            "--slow-motion" => options.slow_motion_speed = args.next().and_then(|n| n.parse().ok()).unwrap_or(0.5),
+            "--region" => options.region = args.next().and_then(|r| Region::from_name(&r)),
+            "--cycle-stepped" => options.cycle_stepped = true,
+            "--gdb" => options.gdb_port = args.next().and_then(|n| n.parse().ok()),
+            "--symbols" => options.symbol_files.extend(args.next()),
+            "--cdl" => options.cdl_path = args.next(),
+            "--profile" => options.profile_path = args.next(),
+            "--keys" => options.keys_path = args.next().unwrap_or(options.keys_path),
+            _ => options.rom_path = arg,
+        }
+    }
+    options
+}
 
 fn main() {
+    let options = parse_args();
     
-    let rom = Rom::new("iceclimber.nes".to_string());
-    let ppu: PPU = PPU::new();
-
-    let mut bus = Bus {
-        ram: [0x00; 2 * 1024],
-        rom: rom,
-        ppu: ppu,
-        controller: [Controller::new(), Controller::new()],
-    };
+    let rom = Rom::new(options.rom_path.clone());
+    let region = options.region.unwrap_or(rom.region());
+    println!("Region: {:?}", region);
+
+    let mut bus = Bus::new(rom, region);
 
     let mut cpu = CPU::new(&mut bus);
+    cpu.cycle_stepped = options.cycle_stepped;
     
     /*
     cpu.bus.write(0x8000, 0xA9); // LDA
@@ -41,8 +153,58 @@ fn main() {
     cpu.reset();
 
     println!("PC: ${:04X}", cpu.registers.pc);
+
+    if let Some(path) = &options.cdl_path
+        && let Err(e) = CodeDataLog::start(cpu.bus, Some(path)) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    if options.profile_path.is_some() {
+        cpu.call_stack = Some(CallStack::new());
+        cpu.profiler = Some(Profiler::new());
+    }
+
+    let mut movie = None;
+    if let Some(path) = options.play_movie.clone() {
+        let mut session = MovieSession::play(path).unwrap();
+        session.start(&mut cpu).unwrap();
+        movie = Some(session);
+    } else if let Some(path) = options.record_movie.clone() {
+        let rom_filename = std::path::Path::new(&options.rom_path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let start_state = options.record_from_state.as_ref().map(|state_path| {
+            let data = std::fs::read(state_path).map_err(|e| format!("{}: {}", state_path, e))?;
+            savestate::load(&mut cpu, &data)?;
+            Ok::<_, String>(data)
+        });
+        match start_state.transpose() {
+            Ok(start_state) => movie = Some(MovieSession::record(path, rom_filename, start_state)),
+            Err(e) => {
+                println!("Failed to load the movie's start state: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(port) = options.gdb_port {
+        if let Err(e) = gdb_stub::serve(&mut cpu, port) {
+            println!("GDB server: {}", e);
+            std::process::exit(1);
+        }
+        save_cdl(cpu.bus, &options);
+        save_profile(&cpu, &load_symbols(&options, cpu.bus), &options);
+        return;
+    }
+
+    if let Some(frames) = options.headless_frames {
+        run_headless(&mut cpu, frames, movie, &options);
+        return;
+    }
     
-    sdl_ui::start_ui(cpu);
+    sdl_ui::start_ui(cpu, movie, &options);
 
     println!("Exit with success!");
 
@@ -54,3 +216,73 @@ fn main() {
     }
 */
 }
+
+// Symbol files next to the ROM and those given with --symbols
+pub fn load_symbols(options: &Options, bus: &Bus) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    symbols.load_for_rom(&options.rom_path, bus.rom.prg_rom.len() / 0x4000);
+    for path in &options.symbol_files {
+        match symbols.load(path) {
+            Ok(count) => println!("Loaded {} symbols from {}", count, path),
+            Err(e) => println!("{}", e),
+        }
+    }
+    symbols
+}
+
+// Prints the profile and writes its folded stacks to the --profile file
+pub fn save_profile(cpu: &CPU, symbols: &SymbolTable, options: &Options) {
+    if let (Some(path), Some(profiler)) = (&options.profile_path, &cpu.profiler) {
+        for line in profiler.report(symbols, cpu.bus) {
+            println!("{}", line);
+        }
+        match profiler.save_folded(symbols, cpu.bus, path) {
+            Ok(()) => println!("Saved folded stacks to {}", path),
+            Err(e) => println!("Failed to save profile: {}", e),
+        }
+    }
+}
+
+// Writes the code/data log to the --cdl file, if one is being recorded
+pub fn save_cdl(bus: &Bus, options: &Options) {
+    if let (Some(path), Some(cdl)) = (&options.cdl_path, &bus.cdl) {
+        match cdl.save(path) {
+            Ok(()) => println!("Saved code/data log to {}", path),
+            Err(e) => println!("Failed to save code/data log: {}", e),
+        }
+    }
+}
+
+fn run_headless(cpu: &mut CPU, frames: usize, mut movie: Option<MovieSession>, options: &Options) {
+    match movie.as_mut() {
+        Some(session) => {
+            for _ in 0..frames {
+                session.update(cpu);
+                runner::run_frame(cpu);
+            }
+        }
+        None => {
+            runner::run_frames(cpu, frames, &[]);
+        }
+    }
+
+    println!("Frame {} hash: {:016X}", frames, runner::frame_hash(&cpu.bus.ppu.frame_buffer));
+    save_cdl(cpu.bus, options);
+    if options.profile_path.is_some() {
+        save_profile(cpu, &load_symbols(options, cpu.bus), options);
+    }
+
+    if let Some(path) = &options.screenshot_path {
+        screenshot::save_png(&cpu.bus.ppu.frame_buffer, path, &options.screenshot).unwrap();
+    }
+
+    if let Some(path) = &options.dump_frame_path {
+        screenshot::save_indexed(&cpu.bus.ppu.frame_buffer, path).unwrap();
+    }
+
+    if let Some(path) = &options.golden_frame
+        && let Err(e) = runner::compare_golden(&cpu.bus.ppu.frame_buffer, path) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,40 +1,127 @@
+use crate::savestate::{StateReader, StateWriter};
 
+// How the four logical nametables map onto nametable RAM. The console has 2KB
+// (two pages); four-screen boards add two more on the cartridge.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mirroring {
+    Horizontal,        // $2000=$2400, $2800=$2C00
+    Vertical,          // $2000=$2800, $2400=$2C00
+    SingleScreenLower, // All four use page 0
+    SingleScreenUpper, // All four use page 1
+    FourScreen,
+}
+
+// Cartridge hardware between the console and the ROM chips. Mappers translate
+// CPU and PPU addresses into PRG/CHR offsets and may add registers or RAM.
 pub trait Mapper {
-    fn cpu_read_mapper_addr(addr: u16) -> u16;
-    
-    fn cpu_write_mapper_addr(addr: u16) -> u16;
-    
-    fn ppu_read_mapper_addr(addr: u16) -> u16;
-    
-    fn ppu_write_mapper_addr(addr: u16) -> u16;
+    // Offset into PRG ROM for a CPU access to $8000-$FFFF
+    fn cpu_read_mapper_addr(&self, addr: u16) -> usize;
+
+    // Writes to $8000-$FFFF, where bank switching registers usually live
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+    // Offset into CHR for a PPU access to $0000-$1FFF
+    fn ppu_read_mapper_addr(&self, addr: u16) -> usize;
+
+    // Mirroring selected by the mapper at runtime; None keeps the header's
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // The expansion area ($4020-$5FFF) is open bus unless the mapper claims it
+    fn expansion_read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn expansion_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
 }
 
+pub fn new_mapper(number: u8, nprg_banks: u8) -> Box<dyn Mapper> {
+    match number {
+        0 => Box::new(Mapper000::new(nprg_banks)),
+        7 => Box::new(Mapper007::new()),
+        _ => {
+            println!("Mapper {} is not supported, using NROM", number);
+            Box::new(Mapper000::new(nprg_banks))
+        }
+    }
+}
+
+// NROM: 16 or 32KB of PRG ROM (16KB is mirrored), 8KB of CHR, no registers
 pub struct Mapper000 {
-    pub npgr_banks: u8,
-    pub nchr_banks: u8,
+    pub nprg_banks: u8,
 }
 
 impl Mapper000 {
-    pub fn new(npgr_banks: u8, nchr_banks: u8) -> Self {
+    pub fn new(nprg_banks: u8) -> Self {
         Mapper000 {
-            npgr_banks: npgr_banks,
-            nchr_banks: nchr_banks,
+            nprg_banks,
         }
     }
 }
 
-impl MapperTrait for Mapper000 {
-    fn cpu_read_mapper_addr(&self, addr: u16) -> u16 {
+impl Mapper for Mapper000 {
+    fn cpu_read_mapper_addr(&self, addr: u16) -> usize {
         let offset = addr & 0x7FFF;
 
-        if self.npgr_banks == 1 {
-            offset & 0x3FFF
+        if self.nprg_banks == 1 {
+            (offset & 0x3FFF) as usize
         } else {
-            offset
+            offset as usize
         }
     }
 
-    fn cpu_write_mapper_addr(&self, addr:u16) -> u16 {
-        self.cpu_read_mapper_addr(addr)
+    fn ppu_read_mapper_addr(&self, addr: u16) -> usize {
+        (addr & 0x1FFF) as usize
+    }
+}
+
+// AxROM: 32KB PRG banks and a register that picks the single-screen nametable page
+pub struct Mapper007 {
+    register: u8,
+}
+
+impl Mapper007 {
+    pub fn new() -> Self {
+        Mapper007 {
+            register: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper007 {
+    fn cpu_read_mapper_addr(&self, addr: u16) -> usize {
+        ((self.register & 0x07) as usize) * 0x8000 + (addr & 0x7FFF) as usize
+    }
+
+    fn cpu_write(&mut self, _addr: u16, data: u8) {
+        self.register = data;
+    }
+
+    fn ppu_read_mapper_addr(&self, addr: u16) -> usize {
+        (addr & 0x1FFF) as usize
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if (self.register & 0x10) != 0 {
+            Some(Mirroring::SingleScreenUpper)
+        } else {
+            Some(Mirroring::SingleScreenLower)
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.register = reader.read_u8()?;
+        Ok(())
     }
 }
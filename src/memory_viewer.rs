@@ -0,0 +1,359 @@
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::bus::{Bus, MemoryRegion};
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::expression::parse_number;
+
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 560;
+const LINE_HEIGHT: i32 = 18;
+
+const ROWS: usize = 26;
+const BYTES_PER_ROW: usize = 16;
+const ROWS_Y: i32 = 30;
+const STATUS_Y: i32 = ROWS_Y + ROWS as i32 * LINE_HEIGHT + 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Search,
+    GoTo,
+}
+
+// Hex editor over the memories in MemoryRegion. Bytes that changed during the
+// last frame are shown in yellow.
+pub struct MemoryViewer {
+    window: DebugWindow,
+    region: MemoryRegion,
+    top_row: usize,
+    cursor: usize,
+    high_nibble: Option<u8>, // First digit typed over the byte under the cursor
+    prompt: Option<(Prompt, String)>,
+    message: String,
+
+    tab_rects: Vec<Rect>,
+    char_width: i32,
+
+    // Region contents at the start of the current frame, and what the last one changed
+    snapshot: Vec<u8>,
+    changed: Vec<bool>,
+    snapshot_frame: u32,
+}
+
+fn read_region(bus: &Bus, region: MemoryRegion) -> Vec<u8> {
+    (0..bus.memory_len(region)).map(|offset| bus.peek_memory(region, offset)).collect()
+}
+
+// "A9 10" or "A910"
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Expected hex bytes, got '{}'", text));
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("Invalid hex byte: {}", &digits[i..i + 2])))
+        .collect()
+}
+
+// Offset of the first match starting after `cursor`, wrapping around
+fn find_after(data: &[u8], cursor: usize, pattern: &[u8]) -> Option<usize> {
+    (1..=data.len())
+        .map(|i| (cursor + i) % data.len())
+        .find(|start| data[*start..].starts_with(pattern))
+}
+
+impl MemoryViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(MemoryViewer {
+            window: DebugWindow::open(video, "Memory", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            region: MemoryRegion::CpuRam,
+            top_row: 0,
+            cursor: 0,
+            high_nibble: None,
+            prompt: None,
+            message: String::new(),
+
+            tab_rects: Vec::new(),
+            char_width: 8,
+
+            snapshot: Vec::new(),
+            changed: Vec::new(),
+            snapshot_frame: 0,
+        })
+    }
+
+    fn select_region(&mut self, region: MemoryRegion) {
+        self.region = region;
+        self.top_row = 0;
+        self.cursor = 0;
+        self.high_nibble = None;
+        self.snapshot.clear();
+    }
+
+    // Moves the cursor, scrolling to keep it visible
+    fn move_cursor(&mut self, bus: &Bus, delta: isize) {
+        let len = bus.memory_len(self.region);
+        if len == 0 {
+            return;
+        }
+        self.cursor = self.cursor.saturating_add_signed(delta).min(len - 1);
+        self.high_nibble = None;
+
+        let row = self.cursor / BYTES_PER_ROW;
+        if row < self.top_row {
+            self.top_row = row;
+        } else if row >= self.top_row + ROWS {
+            self.top_row = row + 1 - ROWS;
+        }
+    }
+
+    fn scroll(&mut self, bus: &Bus, rows: isize) {
+        let last_row = bus.memory_len(self.region).div_ceil(BYTES_PER_ROW).saturating_sub(ROWS);
+        self.top_row = self.top_row.saturating_add_signed(rows).min(last_row);
+    }
+
+    fn type_digit(&mut self, bus: &mut Bus, digit: u8) {
+        if bus.memory_len(self.region) == 0 {
+            return;
+        }
+        match self.high_nibble.take() {
+            None => self.high_nibble = Some(digit),
+            Some(high) => {
+                bus.poke_memory(self.region, self.cursor, (high << 4) | digit);
+                self.move_cursor(bus, 1);
+            }
+        }
+    }
+
+    fn run_prompt(&mut self, bus: &Bus, prompt: Prompt, text: &str) -> Result<String, String> {
+        let len = bus.memory_len(self.region);
+        match prompt {
+            // Next match after the cursor, wrapping around
+            Prompt::Search => {
+                let pattern = parse_hex_bytes(text)?;
+                if len == 0 {
+                    return Err(format!("{} is empty", self.region.name()));
+                }
+                let data = read_region(bus, self.region);
+                let found = find_after(&data, self.cursor, &pattern).ok_or(format!("{} not found", text))?;
+                self.move_cursor(bus, found as isize - self.cursor as isize);
+                Ok(format!("Found at ${:04X}", self.region.base_address() + found))
+            }
+
+            // Takes an address in the region's address space, or an offset
+            Prompt::GoTo => {
+                let value = parse_number(text)? as usize;
+                let offset = value.checked_sub(self.region.base_address()).filter(|offset| *offset < len)
+                    .or(Some(value).filter(|offset| *offset < len))
+                    .ok_or(format!("${:04X} is outside {}", value, self.region.name()))?;
+                self.move_cursor(bus, offset as isize - self.cursor as isize);
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn track_changes(&mut self, bus: &Bus) {
+        let frame = bus.ppu.frame();
+        let current = read_region(bus, self.region);
+
+        if self.snapshot.len() != current.len() {
+            self.changed = vec![false; current.len()];
+            self.snapshot = current;
+            self.snapshot_frame = frame;
+        } else if frame != self.snapshot_frame {
+            self.changed = current.iter().zip(self.snapshot.iter()).map(|(new, old)| new != old).collect();
+            self.snapshot = current;
+            self.snapshot_frame = frame;
+        }
+    }
+
+    fn draw_tabs(&mut self, font: &mut Font) {
+        self.tab_rects.clear();
+        let mut x = 4;
+        for region in MemoryRegion::ALL {
+            let width = font.size_of(region.name()).map(|(w, _)| w as i32).unwrap_or(0) + 12;
+            let rect = Rect::new(x, 4, width as u32, LINE_HEIGHT as u32 + 2);
+            if region == self.region {
+                self.window.canvas.set_draw_color(Color::RGB(0, 64, 128));
+                self.window.canvas.fill_rect(rect).unwrap();
+            }
+            self.window.canvas.set_draw_color(Color::RGB(128, 128, 128));
+            self.window.canvas.draw_rect(rect).unwrap();
+            self.window.draw_text(font, region.name(), Color::RGB(255, 255, 255), x + 6, 5);
+
+            self.tab_rects.push(rect);
+            x += width + 4;
+        }
+    }
+}
+
+impl DebugView for MemoryViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, _debugger: &mut Debugger) {
+        let bus = &mut *cpu.bus;
+
+        // While a prompt is open, typing goes to it
+        if let Some((prompt, text)) = self.prompt.as_mut() {
+            match event {
+                Event::TextInput { text: input, .. } => text.push_str(input),
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                    text.pop();
+                }
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => self.prompt = None,
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+                    let (prompt, text) = (*prompt, text.clone());
+                    self.prompt = None;
+                    self.message = self.run_prompt(bus, prompt, &text).unwrap_or_else(|e| e);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::TextInput { text, .. } => {
+                for c in text.chars() {
+                    match c {
+                        '/' => self.prompt = Some((Prompt::Search, String::new())),
+                        'g' | 'G' => self.prompt = Some((Prompt::GoTo, String::new())),
+                        _ => {
+                            if let Some(digit) = c.to_digit(16) {
+                                self.type_digit(bus, digit as u8);
+                            }
+                        }
+                    }
+                }
+            }
+
+            Event::KeyDown { keycode: Some(key), keymod, .. } => {
+                match *key {
+                    Keycode::Left => self.move_cursor(bus, -1),
+                    Keycode::Right => self.move_cursor(bus, 1),
+                    Keycode::Up => self.move_cursor(bus, -(BYTES_PER_ROW as isize)),
+                    Keycode::Down => self.move_cursor(bus, BYTES_PER_ROW as isize),
+                    Keycode::PageUp => self.move_cursor(bus, -((ROWS * BYTES_PER_ROW) as isize)),
+                    Keycode::PageDown => self.move_cursor(bus, (ROWS * BYTES_PER_ROW) as isize),
+                    Keycode::Escape => self.high_nibble = None,
+                    Keycode::Tab => {
+                        let index = MemoryRegion::ALL.iter().position(|r| *r == self.region).unwrap_or(0);
+                        let count = MemoryRegion::ALL.len();
+                        let next = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) { index + count - 1 } else { index + 1 };
+                        self.select_region(MemoryRegion::ALL[next % count]);
+                    }
+                    _ => {}
+                }
+            }
+
+            Event::MouseWheel { y, .. } => self.scroll(bus, -(*y as isize) * 3),
+
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                if let Some(index) = self.tab_rects.iter().position(|rect| rect.contains_point((*x, *y))) {
+                    self.select_region(MemoryRegion::ALL[index]);
+                } else if *y >= ROWS_Y && *y < ROWS_Y + ROWS as i32 * LINE_HEIGHT {
+                    // Bytes start after the 7 character address column, 3 characters each
+                    let column = ((x - 4) / self.char_width - 7).div_euclid(3);
+                    if (0..BYTES_PER_ROW as i32).contains(&column) {
+                        let row = self.top_row + ((y - ROWS_Y) / LINE_HEIGHT) as usize;
+                        let offset = row * BYTES_PER_ROW + column as usize;
+                        self.move_cursor(bus, offset as isize - self.cursor as isize);
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, _debugger: &Debugger, font: &mut Font) {
+        let bus = &*cpu.bus;
+        let white = Color::RGB(255, 255, 255);
+        let grey = Color::RGB(140, 140, 140);
+
+        self.char_width = font.size_of("0").map(|(w, _)| w as i32).unwrap_or(8);
+        self.track_changes(bus);
+
+        self.window.canvas.set_draw_color(Color::RGB(24, 24, 24));
+        self.window.canvas.clear();
+        self.draw_tabs(font);
+
+        let len = bus.memory_len(self.region);
+        let base = self.region.base_address();
+        if len == 0 {
+            self.window.draw_text(font, "Not present on this cartridge", grey, 4, ROWS_Y);
+        }
+
+        for row in 0..ROWS {
+            let start = (self.top_row + row) * BYTES_PER_ROW;
+            if start >= len {
+                break;
+            }
+            let y = ROWS_Y + row as i32 * LINE_HEIGHT;
+            self.window.draw_text(font, &format!("{:06X}", base + start), grey, 4, y);
+
+            for column in 0..BYTES_PER_ROW.min(len - start) {
+                let offset = start + column;
+                let x = 4 + (7 + column as i32 * 3) * self.char_width;
+
+                if offset == self.cursor {
+                    self.window.canvas.set_draw_color(Color::RGB(0, 64, 128));
+                    self.window.canvas.fill_rect(Rect::new(x - 2, y, 2 * self.char_width as u32 + 4, LINE_HEIGHT as u32)).unwrap();
+                }
+
+                let text = match self.high_nibble {
+                    Some(high) if offset == self.cursor => format!("{:X}_", high),
+                    _ => format!("{:02X}", bus.peek_memory(self.region, offset)),
+                };
+                let color = if self.changed.get(offset).copied().unwrap_or(false) { Color::RGB(255, 220, 0) } else { white };
+                self.window.draw_text(font, &text, color, x, y);
+            }
+        }
+
+        if len > 0 {
+            let status = format!("{} ${:04X}/{:X} | 0-F: edit  /: search  g: go to  Tab: region",
+                self.region.name(), base + self.cursor, len);
+            self.window.draw_text(font, &status, grey, 4, STATUS_Y);
+        }
+
+        let prompt_line = match &self.prompt {
+            Some((Prompt::Search, text)) => format!("Search hex bytes: {}_", text),
+            Some((Prompt::GoTo, text)) => format!("Go to address: {}_", text),
+            None => self.message.clone(),
+        };
+        self.window.draw_text(font, &prompt_line, white, 4, STATUS_Y + LINE_HEIGHT);
+
+        self.window.canvas.present();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_bytes_with_or_without_spaces() {
+        assert_eq!(parse_hex_bytes("A9 10"), Ok(vec![0xA9, 0x10]));
+        assert_eq!(parse_hex_bytes("a910"), Ok(vec![0xA9, 0x10]));
+        for text in ["", "A9 1", "G0", "a\u{20AC}", "\u{20AC}\u{20AC}"] {
+            assert!(parse_hex_bytes(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn searches_after_the_cursor_and_wraps_around() {
+        let data = [0xA9, 0x10, 0x00, 0xA9, 0x10];
+        assert_eq!(find_after(&data, 0, &[0xA9, 0x10]), Some(3));
+        assert_eq!(find_after(&data, 3, &[0xA9, 0x10]), Some(0));
+        assert_eq!(find_after(&data, 0, &[0x10, 0xA9]), None);
+        assert_eq!(find_after(&[], 0, &[0xA9]), None);
+    }
+}
@@ -0,0 +1,464 @@
+use std::fs;
+
+use crate::cpu::CPU;
+use crate::savestate;
+
+// FM2 button columns, in file order. Column i maps to bit i of `Controller::buttons`.
+const FM2_BUTTONS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+pub mod command {
+    pub const SOFT_RESET: u8 = 1 << 0;
+    pub const HARD_RESET: u8 = 1 << 1;
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub buttons: [u8; 2],
+}
+
+pub struct Movie {
+    pub rom_filename: String,
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    pub start_state: Option<Vec<u8>>, // None: movie starts from power-on
+    pub frames: Vec<MovieFrame>,
+}
+
+#[derive(PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    Finished,
+}
+
+pub struct MovieSession {
+    pub movie: Movie,
+    pub mode: MovieMode,
+    pub path: String,
+    pub frame: usize,
+
+    pending_commands: u8,
+}
+
+impl Movie {
+    pub fn new(rom_filename: String) -> Self {
+        Movie {
+            rom_filename,
+            rerecord_count: 0,
+            comments: Vec::new(),
+            start_state: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+        Self::from_fm2(&text)
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), String> {
+        fs::write(file_path, self.to_fm2()).map_err(|e| e.to_string())
+    }
+
+    pub fn from_fm2(text: &str) -> Result<Self, String> {
+        let mut movie = Movie::new(String::new());
+        let mut ports = [1u8, 1u8];
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line, &ports)
+                    .ok_or(format!("Invalid input log at line {}", line_number + 1))?);
+                continue;
+            }
+
+            let (key, value) = match line.split_once(' ') {
+                Some((key, value)) => (key, value),
+                None => (line, ""),
+            };
+
+            match key {
+                "version" if value != "3" => {
+                    return Err(format!("Unsupported FM2 version: {}", value));
+                }
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "rerecordCount" => movie.rerecord_count = value.parse().unwrap_or(0),
+                "comment" => movie.comments.push(value.to_string()),
+                "port0" => ports[0] = value.parse().unwrap_or(0),
+                "port1" => ports[1] = value.parse().unwrap_or(0),
+                "fourscore" if value == "1" => {
+                    return Err("Four Score movies are not supported".to_string());
+                }
+                "binary" if value == "1" => {
+                    return Err("Binary FM2 input logs are not supported".to_string());
+                }
+                "savestate" => {
+                    let encoded = value.strip_prefix("base64:")
+                        .ok_or("Only base64 savestates are supported".to_string())?;
+                    movie.start_state = Some(base64_decode(encoded)?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("version 3\n");
+        text.push_str("emuVersion 22020\n");
+        text.push_str(&format!("rerecordCount {}\n", self.rerecord_count));
+        text.push_str("palFlag 0\n");
+        text.push_str(&format!("romFilename {}\n", self.rom_filename));
+        text.push_str("guid 00000000-0000-0000-0000-000000000000\n");
+        text.push_str("fourscore 0\n");
+        text.push_str("microphone 0\n");
+        text.push_str("port0 1\n");
+        text.push_str("port1 1\n");
+        text.push_str("port2 0\n");
+        text.push_str("FDS 0\n");
+        text.push_str("NewPPU 0\n");
+
+        for comment in self.comments.iter() {
+            text.push_str(&format!("comment {}\n", comment));
+        }
+
+        if let Some(state) = &self.start_state {
+            text.push_str(&format!("savestate base64:{}\n", base64_encode(state)));
+        }
+
+        for frame in self.frames.iter() {
+            text.push_str(&format!("|{}|{}|{}||\n",
+                frame.commands,
+                format_buttons(frame.buttons[0]),
+                format_buttons(frame.buttons[1]),
+            ));
+        }
+
+        text
+    }
+}
+
+impl MovieSession {
+    // A recording either starts from power-on or from an embedded save state.
+    pub fn record(path: String, rom_filename: String, start_state: Option<Vec<u8>>) -> Self {
+        let mut movie = Movie::new(rom_filename);
+        movie.start_state = start_state;
+
+        MovieSession {
+            movie,
+            mode: MovieMode::Recording,
+            path,
+            frame: 0,
+            pending_commands: 0,
+        }
+    }
+
+    pub fn play(path: String) -> Result<Self, String> {
+        let movie = Movie::load(&path)?;
+        Ok(MovieSession {
+            movie,
+            mode: MovieMode::Playing,
+            path,
+            frame: 0,
+            pending_commands: 0,
+        })
+    }
+
+    // Must be called once before the first emulated frame.
+    pub fn start(&mut self, cpu: &mut CPU) -> Result<(), String> {
+        match self.mode {
+            MovieMode::Playing => {
+                if let Some(state) = &self.movie.start_state {
+                    savestate::load(cpu, state)?;
+                }
+            }
+            MovieMode::Recording | MovieMode::Finished => {}
+        }
+        Ok(())
+    }
+
+    // Called at the start of every frame, after the live input has been polled.
+    pub fn update(&mut self, cpu: &mut CPU) {
+        match self.mode {
+            MovieMode::Playing => {
+                if self.frame >= self.movie.frames.len() {
+                    println!("Movie finished after {} frames", self.frame);
+                    self.mode = MovieMode::Finished;
+                    return;
+                }
+
+                let frame = self.movie.frames[self.frame];
+                apply_commands(cpu, frame.commands);
+                cpu.bus.controller[0].buttons = frame.buttons[0];
+                cpu.bus.controller[1].buttons = frame.buttons[1];
+                self.frame += 1;
+            }
+            MovieMode::Recording => {
+                self.movie.frames.push(MovieFrame {
+                    commands: self.pending_commands,
+                    buttons: [cpu.bus.controller[0].buttons, cpu.bus.controller[1].buttons],
+                });
+                self.pending_commands = 0;
+                self.frame += 1;
+            }
+            MovieMode::Finished => {}
+        }
+    }
+
+    // Goes back to a frame the emulator returned to by loading a save state.
+    // A recording drops its input past that frame and counts a rerecord;
+    // playback carries on from there.
+    pub fn seek(&mut self, frame: usize) {
+        self.frame = frame.min(self.movie.frames.len());
+        self.pending_commands = 0;
+        match self.mode {
+            MovieMode::Recording => {
+                self.movie.frames.truncate(self.frame);
+                self.movie.rerecord_count += 1;
+            }
+            MovieMode::Playing | MovieMode::Finished => self.mode = MovieMode::Playing,
+        }
+    }
+
+    // Records a reset so playback reproduces it at the start of the next frame.
+    pub fn record_command(&mut self, command: u8) {
+        if self.mode == MovieMode::Recording {
+            self.pending_commands |= command;
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        if self.mode == MovieMode::Recording {
+            self.movie.save(&self.path)?;
+            println!("Movie saved to {} ({} frames)", self.path, self.movie.frames.len());
+        }
+        self.mode = MovieMode::Finished;
+        Ok(())
+    }
+}
+
+fn apply_commands(cpu: &mut CPU, commands: u8) {
+    if commands & command::HARD_RESET != 0 {
+        cpu.bus.power_on();
+        cpu.reset();
+    } else if commands & command::SOFT_RESET != 0 {
+        cpu.reset();
+    }
+}
+
+fn parse_frame(line: &str, ports: &[u8; 2]) -> Option<MovieFrame> {
+    let mut fields = line.split('|').skip(1);
+    let mut frame = MovieFrame {
+        commands: fields.next()?.trim().parse().ok()?,
+        buttons: [0, 0],
+    };
+
+    // Every port has a field, left empty for ports with nothing plugged in
+    for (port, kind) in ports.iter().enumerate() {
+        let field = fields.next()?;
+        if *kind == 1 {
+            frame.buttons[port] = parse_buttons(field)?;
+        }
+    }
+
+    Some(frame)
+}
+
+fn parse_buttons(field: &str) -> Option<u8> {
+    if field.chars().count() != FM2_BUTTONS.len() {
+        return None;
+    }
+
+    let mut buttons = 0;
+    for (i, c) in field.chars().enumerate() {
+        if c != '.' && c != ' ' {
+            buttons |= 1 << i;
+        }
+    }
+    Some(buttons)
+}
+
+fn format_buttons(buttons: u8) -> String {
+    FM2_BUTTONS.iter()
+        .enumerate()
+        .map(|(i, c)| if buttons & (1 << i) != 0 { *c } else { '.' })
+        .collect()
+}
+
+const BASE64_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_TABLE[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let mut n: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.trim().bytes() {
+        if c == b'=' {
+            break;
+        }
+        let value = BASE64_TABLE.iter().position(|&x| x == c)
+            .ok_or(format!("Invalid base64 character: {}", c as char))?;
+
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((n >> bits) as u8);
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version 3\nemuVersion 22020\nrerecordCount 7\nromFilename smb\n";
+
+    #[test]
+    fn parses_gamepads_on_both_ports() {
+        let text = format!("{}port0 1\nport1 1\n|0|R......A|...U....||\n|1|........|.L.....B||\n", HEADER);
+        let movie = Movie::from_fm2(&text).unwrap();
+
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.rerecord_count, 7);
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.frames[0].buttons, [0x81, 0x08]);
+        assert_eq!(movie.frames[1].commands, command::SOFT_RESET);
+        assert_eq!(movie.frames[1].buttons, [0x00, 0x82]);
+    }
+
+    #[test]
+    fn skips_the_empty_field_of_an_unplugged_port() {
+        let text = format!("{}port0 0\nport1 1\n|0||...U...A||\n", HEADER);
+        let movie = Movie::from_fm2(&text).unwrap();
+        assert_eq!(movie.frames[0].buttons, [0x00, 0x88]);
+
+        let text = format!("{}port0 1\nport1 0\n|0|R.......|||\n", HEADER);
+        let movie = Movie::from_fm2(&text).unwrap();
+        assert_eq!(movie.frames[0].buttons, [0x01, 0x00]);
+    }
+
+    #[test]
+    fn rejects_bad_input_logs() {
+        assert!(Movie::from_fm2("version 2\n").is_err());
+        assert!(Movie::from_fm2("version 3\nbinary 1\n").is_err());
+        assert!(Movie::from_fm2("version 3\n|0|RLDU|........||\n").is_err());
+        assert!(Movie::from_fm2("version 3\n|x|........|........||\n").is_err());
+    }
+
+    #[test]
+    fn round_trips_through_fm2() {
+        let mut movie = Movie::new("zelda".to_string());
+        movie.rerecord_count = 3;
+        movie.comments.push("author someone".to_string());
+        movie.start_state = Some(vec![0x4E, 0x45, 0x53, 0x53, 0x00, 0xFF]);
+        movie.frames.push(MovieFrame { commands: command::HARD_RESET, buttons: [0xFF, 0x00] });
+        movie.frames.push(MovieFrame { commands: 0, buttons: [0x10, 0x24] });
+
+        let text = movie.to_fm2();
+        assert!(text.contains("|2|RLDUTSBA|........||\n"));
+
+        let parsed = Movie::from_fm2(&text).unwrap();
+        assert_eq!(parsed.rom_filename, "zelda");
+        assert_eq!(parsed.rerecord_count, 3);
+        assert_eq!(parsed.comments, movie.comments);
+        assert_eq!(parsed.start_state, movie.start_state);
+        assert_eq!(parsed.frames.len(), 2);
+        assert_eq!(parsed.frames[0].commands, command::HARD_RESET);
+        assert_eq!(parsed.frames[0].buttons, [0xFF, 0x00]);
+        assert_eq!(parsed.frames[1].buttons, [0x10, 0x24]);
+    }
+
+    #[test]
+    fn hard_reset_powers_the_whole_console_back_on() {
+        use crate::bus::Bus;
+        use crate::ines_file::test_rom;
+        use crate::mapper::{Mapper007, Mirroring};
+        use crate::region::Region;
+
+        let mut rom = test_rom(&[], &[]);
+        rom.mapper = Box::new(Mapper007::new());
+        rom.mapper.cpu_write(0x8000, 0x10);
+        rom.chr_ram = true;
+        rom.chr_rom.fill(0x33);
+        rom.prg_ram = vec![0x55; 8 * 1024];
+        let mut bus = Bus::new(rom, Region::Ntsc);
+        bus.ram[0x0123] = 0x77;
+        let mut cpu = CPU::new(&mut bus);
+
+        apply_commands(&mut cpu, command::HARD_RESET);
+
+        // The mapper is rebuilt from the header, NROM here
+        assert_eq!(cpu.bus.rom.current_mirroring(), Mirroring::Horizontal);
+        assert!(cpu.bus.rom.chr_rom.iter().all(|&b| b == 0x00));
+        assert_eq!(cpu.bus.rom.prg_ram, vec![0x00; 8 * 1024]);
+        assert_eq!(cpu.bus.ram[0x0123], 0x00);
+        assert_eq!(cpu.registers.pc, 0x8000);
+    }
+
+    fn session(mode: MovieMode, frames: usize) -> MovieSession {
+        let mut movie = Movie::new("test".to_string());
+        movie.frames = (0..frames).map(|n| MovieFrame { commands: 0, buttons: [n as u8, 0] }).collect();
+        MovieSession { movie, mode, path: String::new(), frame: frames, pending_commands: command::SOFT_RESET }
+    }
+
+    #[test]
+    fn seeking_a_recording_truncates_it_and_counts_a_rerecord() {
+        let mut recording = session(MovieMode::Recording, 10);
+        recording.seek(4);
+
+        assert_eq!(recording.frame, 4);
+        assert_eq!(recording.movie.frames.len(), 4);
+        assert_eq!(recording.movie.rerecord_count, 1);
+        assert_eq!(recording.pending_commands, 0);
+        assert!(recording.mode == MovieMode::Recording);
+    }
+
+    #[test]
+    fn seeking_a_playback_resumes_it() {
+        let mut playback = session(MovieMode::Finished, 10);
+        playback.seek(4);
+
+        assert_eq!(playback.frame, 4);
+        assert_eq!(playback.movie.frames.len(), 10);
+        assert_eq!(playback.movie.rerecord_count, 0);
+        assert!(playback.mode == MovieMode::Playing);
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648() {
+        let cases: [(&[u8], &str); 4] = [(b"", ""), (b"M", "TQ=="), (b"Ma", "TWE="), (b"Man", "TWFu")];
+        for (data, text) in cases {
+            assert_eq!(base64_encode(data), text);
+            assert_eq!(base64_decode(text).unwrap(), data);
+        }
+
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&data)).unwrap(), data);
+        assert!(base64_decode("TW*u").is_err());
+    }
+}
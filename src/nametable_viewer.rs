@@ -0,0 +1,121 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::ppu::get_color_from_palette;
+
+const MAP_WIDTH: u32 = 512;
+const MAP_HEIGHT: u32 = 480;
+
+// The four logical nametables laid out as the PPU addresses them:
+// $2000 $2400 on top, $2800 $2C00 below
+pub struct NametableViewer {
+    window: DebugWindow,
+    show_attributes: bool,
+    mouse: Option<(i32, i32)>,
+}
+
+impl NametableViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(NametableViewer {
+            window: DebugWindow::open(video, "Nametables (G: attribute grid)", MAP_WIDTH, MAP_HEIGHT)?,
+            show_attributes: false,
+            mouse: None,
+        })
+    }
+
+    fn draw_attribute_grid(&mut self) {
+        let canvas = &mut self.window.canvas;
+
+        // 16x16 areas share a palette, 32x32 areas share an attribute byte
+        for x in (16..MAP_WIDTH as i32).step_by(16) {
+            let alpha = if x % 32 == 0 { 0xA0 } else { 0x50 };
+            canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha));
+            canvas.draw_line((x, 0), (x, MAP_HEIGHT as i32 - 1)).unwrap();
+        }
+        for table_y in [0, 240] {
+            for y in (table_y..table_y + 240).step_by(16) {
+                let alpha = if (y - table_y) % 32 == 0 { 0xA0 } else { 0x50 };
+                canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha));
+                canvas.draw_line((0, y), (MAP_WIDTH as i32 - 1, y)).unwrap();
+            }
+        }
+    }
+
+    // The visible screen wraps around the edges of the map
+    fn draw_viewport(&mut self, origin: (usize, usize)) {
+        let spans = |start: usize, length: usize, total: usize| {
+            let start = start % total;
+            if start + length <= total {
+                vec![(start, length)]
+            } else {
+                vec![(start, total - start), (0, start + length - total)]
+            }
+        };
+
+        self.window.canvas.set_draw_color(Color::RGB(255, 0, 0));
+        for (x, width) in spans(origin.0, 256, MAP_WIDTH as usize) {
+            for (y, height) in spans(origin.1, 240, MAP_HEIGHT as usize) {
+                let rect = Rect::new(x as i32, y as i32, width as u32, height as u32);
+                self.window.canvas.draw_rect(rect).unwrap();
+            }
+        }
+    }
+}
+
+impl DebugView for NametableViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, _cpu: &mut CPU, _debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::G), repeat: false, .. } => {
+                self.show_attributes = !self.show_attributes;
+            }
+
+            Event::MouseMotion { x, y, .. } => {
+                self.mouse = Some((*x, *y));
+            }
+
+            Event::Window { win_event: WindowEvent::Leave, .. } => {
+                self.mouse = None;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, _debugger: &Debugger, font: &mut Font) {
+        let ppu = &cpu.bus.ppu;
+        let rom = &cpu.bus.rom;
+
+        let pixels: Vec<Color> = ppu.render_nametables(rom).into_iter().map(get_color_from_palette).collect();
+        self.window.draw_pixels(&pixels, MAP_WIDTH, MAP_HEIGHT, Rect::new(0, 0, MAP_WIDTH, MAP_HEIGHT));
+
+        if self.show_attributes {
+            self.draw_attribute_grid();
+        }
+        self.draw_viewport(ppu.scroll_origin());
+
+        if let Some((x, y)) = self.mouse
+            && (0..MAP_WIDTH as i32).contains(&x) && (0..MAP_HEIGHT as i32).contains(&y) {
+            let entry = ppu.nametable_tile(rom, x as usize, y as usize);
+            let lines = [
+                format!("PPU addr: ${:04X}", entry.ppu_addr),
+                format!("Tile: ${:02X}", entry.tile),
+                format!("Attr: ${:02X} @ ${:04X}", entry.attr_byte, entry.attr_addr),
+                format!("Palette: {}", entry.palette),
+            ];
+            self.window.draw_tooltip(font, &lines, (x, y));
+        }
+
+        self.window.canvas.present();
+    }
+}
@@ -0,0 +1,149 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+
+const COLUMNS: usize = 4;
+const CELL_WIDTH: u32 = 160;
+const CELL_HEIGHT: u32 = 40;
+const HEADER_HEIGHT: i32 = 44;
+
+const WINDOW_WIDTH: u32 = COLUMNS as u32 * CELL_WIDTH;
+const WINDOW_HEIGHT: u32 = HEADER_HEIGHT as u32 + (64 / COLUMNS) as u32 * CELL_HEIGHT;
+
+// Sprites drawn on the line after this one are highlighted in green, the ones
+// past the 8 sprite limit in red
+const MAX_SPRITES_PER_LINE: usize = 8;
+
+// All 64 OAM entries with a preview. The selected entry can be edited in place.
+pub struct OamViewer {
+    window: DebugWindow,
+    scanline: i16,
+    selected: usize,
+}
+
+impl OamViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(OamViewer {
+            window: DebugWindow::open(video, "OAM", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            scanline: 0,
+            selected: 0,
+        })
+    }
+
+    fn cell_rect(index: usize) -> Rect {
+        Rect::new(
+            (index % COLUMNS) as i32 * CELL_WIDTH as i32,
+            HEADER_HEIGHT + (index / COLUMNS) as i32 * CELL_HEIGHT as i32,
+            CELL_WIDTH,
+            CELL_HEIGHT,
+        )
+    }
+
+    // Edits the selected entry; OAM bytes are Y, tile, attributes, X
+    fn edit(&self, cpu: &mut CPU, key: Keycode) {
+        let entry = &mut cpu.bus.ppu.oam_data[self.selected * 4..self.selected * 4 + 4];
+        match key {
+            Keycode::Up => entry[0] = entry[0].wrapping_sub(1),
+            Keycode::Down => entry[0] = entry[0].wrapping_add(1),
+            Keycode::Minus => entry[1] = entry[1].wrapping_sub(1),
+            Keycode::Equals => entry[1] = entry[1].wrapping_add(1),
+            Keycode::P => entry[2] = (entry[2] & !0x03) | (entry[2].wrapping_add(1) & 0x03),
+            Keycode::B => entry[2] ^= 0x20,
+            Keycode::H => entry[2] ^= 0x40,
+            Keycode::V => entry[2] ^= 0x80,
+            Keycode::Left => entry[3] = entry[3].wrapping_sub(1),
+            Keycode::Right => entry[3] = entry[3].wrapping_add(1),
+            _ => {}
+        }
+    }
+}
+
+impl DebugView for OamViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, _debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::LeftBracket), .. } => {
+                self.scanline = (self.scanline - 1).max(0);
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::RightBracket), .. } => {
+                self.scanline = (self.scanline + 1).min(239);
+            }
+
+            Event::KeyDown { keycode: Some(key), .. } => self.edit(cpu, *key),
+
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. }
+                if (HEADER_HEIGHT..WINDOW_HEIGHT as i32).contains(y) && (0..WINDOW_WIDTH as i32).contains(x) => {
+                self.selected = ((y - HEADER_HEIGHT) / CELL_HEIGHT as i32) as usize * COLUMNS + (x / CELL_WIDTH as i32) as usize;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, _debugger: &Debugger, font: &mut Font) {
+        let ppu = &cpu.bus.ppu;
+        let height = ppu.sprite_height();
+        let in_range = ppu.sprites_on_scanline(self.scanline);
+        let white = Color::RGB(255, 255, 255);
+
+        self.window.canvas.set_draw_color(Color::RGB(32, 32, 32));
+        self.window.canvas.clear();
+
+        let dropped = in_range.len().saturating_sub(MAX_SPRITES_PER_LINE);
+        let status = format!("Scanline {} ([ ]): {} in range, {} dropped | 8x{} sprites",
+            self.scanline, in_range.len(), dropped, height);
+        self.window.draw_text(font, &status, white, 4, 2);
+        self.window.draw_text(font, "Arrows move, +/- tile, P palette, H/V flip, B priority", white, 4, 2 + font.height());
+
+        for index in 0..64 {
+            let rect = Self::cell_rect(index);
+            let entry = &ppu.oam_data[index * 4..index * 4 + 4];
+            let (y, tile, attr, x) = (entry[0], entry[1], entry[2], entry[3]);
+
+            match in_range.iter().position(|i| *i == index) {
+                Some(slot) if slot < MAX_SPRITES_PER_LINE => {
+                    self.window.canvas.set_draw_color(Color::RGB(0, 96, 0));
+                    self.window.canvas.fill_rect(rect).unwrap();
+                }
+                Some(_) => {
+                    self.window.canvas.set_draw_color(Color::RGB(128, 0, 0));
+                    self.window.canvas.fill_rect(rect).unwrap();
+                }
+                None => {}
+            }
+
+            let preview = ppu.sprite_preview(&cpu.bus.rom, index);
+            let preview_rect = Rect::new(rect.x() + 4, rect.y() + 4, 16, height as u32 * 2);
+            self.window.draw_pixels(&preview, 8, height as u32, preview_rect);
+
+            let line_1 = format!("{:02} X:{:02X} Y:{:02X}", index, x, y);
+            let line_2 = format!("T:{:02X} P{} {}{} {}",
+                tile,
+                attr & 0x03,
+                if (attr & 0x40) != 0 { 'H' } else { '-' },
+                if (attr & 0x80) != 0 { 'V' } else { '-' },
+                if (attr & 0x20) != 0 { "back" } else { "front" });
+            self.window.draw_text(font, &line_1, white, rect.x() + 26, rect.y() + 2);
+            self.window.draw_text(font, &line_2, white, rect.x() + 26, rect.y() + 2 + font.height());
+
+            if index == self.selected {
+                self.window.canvas.set_draw_color(Color::RGB(255, 255, 0));
+                self.window.canvas.draw_rect(rect).unwrap();
+            }
+        }
+
+        self.window.canvas.present();
+    }
+}
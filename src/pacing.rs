@@ -0,0 +1,161 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Frames the emulator may fall behind before the pacer gives up catching up
+const MAX_LAG_FRAMES: u32 = 3;
+// The last part of the wait is spent spinning, sleep() is too coarse for it
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+// Emulated frames per presented frame when running uncapped with vsync on
+const UNCAPPED_VSYNC_FRAMES: f64 = 8.0;
+// Refresh rate assumed when the display does not report one
+const DEFAULT_DISPLAY_RATE: f64 = 60.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    Timer, // Sleep until the next frame is due
+    Vsync, // The display refresh blocks on present
+    Audio, // Wait for the audio queue to drain to its target level
+}
+
+impl SyncMode {
+    pub fn from_name(name: &str) -> Option<SyncMode> {
+        match name {
+            "timer" => Some(SyncMode::Timer),
+            "vsync" => Some(SyncMode::Vsync),
+            "audio" => Some(SyncMode::Audio),
+            _ => None,
+        }
+    }
+}
+
+pub struct FramePacer {
+    pub mode: SyncMode,
+    pub frame_rate: f64,
+    pub speed: f64, // 1.0 is real time, 0.0 runs uncapped
+    pub display_rate: f64, // Presents per second with vsync
+
+    next_frame: Instant,
+    credit: f64,
+}
+
+impl FramePacer {
+    // Audio pacing waits on the audio queue, so without an open audio device
+    // it falls back to the timer
+    pub fn new(mode: SyncMode, frame_rate: f64, has_audio: bool) -> Self {
+        FramePacer {
+            mode: if mode == SyncMode::Audio && !has_audio { SyncMode::Timer } else { mode },
+            frame_rate,
+            speed: 1.0,
+            display_rate: DEFAULT_DISPLAY_RATE,
+            next_frame: Instant::now(),
+            credit: 0.0,
+        }
+    }
+
+    // Audio pacing only holds at normal speed; fast-forward and slow motion
+    // fall back to the timer since the audio queue cannot follow them.
+    pub fn audio_paced(&self) -> bool {
+        self.mode == SyncMode::Audio && self.speed == 1.0
+    }
+
+    // Number of emulated frames to run before the next present. Only vsync
+    // presents at a fixed rate, so it is the only mode that batches frames,
+    // or skips them when the display refreshes faster than the console.
+    pub fn frames_to_run(&mut self) -> u32 {
+        if self.mode != SyncMode::Vsync {
+            return 1;
+        }
+
+        self.credit += if self.speed <= 0.0 {
+            UNCAPPED_VSYNC_FRAMES
+        } else {
+            self.frame_rate * self.speed / self.display_rate
+        };
+        let frames = self.credit.floor();
+        self.credit -= frames;
+        frames as u32
+    }
+
+    // Blocks until the next frame is due in timer mode.
+    pub fn wait(&mut self) {
+        if self.mode == SyncMode::Vsync || self.audio_paced() {
+            return;
+        }
+
+        if self.speed <= 0.0 {
+            self.next_frame = Instant::now();
+            return;
+        }
+
+        let frame_duration = Duration::from_secs_f64(1.0 / (self.frame_rate * self.speed));
+        self.next_frame += frame_duration;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            let remaining = self.next_frame - now;
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep(remaining - SPIN_THRESHOLD);
+            }
+            while Instant::now() < self.next_frame {
+                std::hint::spin_loop();
+            }
+        } else if now - self.next_frame > frame_duration * MAX_LAG_FRAMES {
+            // Too far behind (e.g. the window was dragged): resync instead of rushing
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC: f64 = 60.0988;
+
+    // Emulated frames run over one second of presents
+    fn frames_per_second(frame_rate: f64, display_rate: f64, speed: f64) -> u32 {
+        let mut pacer = FramePacer::new(SyncMode::Vsync, frame_rate, true);
+        pacer.display_rate = display_rate;
+        pacer.speed = speed;
+        (0..display_rate as u32).map(|_| pacer.frames_to_run()).sum()
+    }
+
+    #[test]
+    fn vsync_runs_the_console_frame_rate_on_any_display() {
+        assert_eq!(frames_per_second(NTSC, 60.0, 1.0), 60);
+        assert_eq!(frames_per_second(NTSC, 144.0, 1.0), 60);
+        assert_eq!(frames_per_second(NTSC, 75.0, 1.0), 60);
+        assert_eq!(frames_per_second(NTSC, 50.0, 1.0), 60);
+        assert_eq!(frames_per_second(50.007, 60.0, 1.0), 50);
+    }
+
+    #[test]
+    fn vsync_scales_with_speed() {
+        assert_eq!(frames_per_second(NTSC, 144.0, 0.5), 30);
+        assert_eq!(frames_per_second(NTSC, 60.0, 2.0), 120);
+        assert_eq!(frames_per_second(NTSC, 60.0, 0.0), 480);
+    }
+
+    #[test]
+    fn other_modes_run_one_frame_per_present() {
+        let mut pacer = FramePacer::new(SyncMode::Timer, NTSC, true);
+        pacer.display_rate = 144.0;
+        assert_eq!(pacer.frames_to_run(), 1);
+    }
+
+    #[test]
+    fn audio_sync_without_audio_falls_back_to_the_timer() {
+        assert!(FramePacer::new(SyncMode::Audio, NTSC, true).audio_paced());
+
+        let mut pacer = FramePacer::new(SyncMode::Audio, NTSC, false);
+        assert!(pacer.mode == SyncMode::Timer);
+        assert!(!pacer.audio_paced());
+
+        // Still capped at the console frame rate
+        let start = Instant::now();
+        for _ in 0..3 {
+            pacer.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_secs_f64(2.5 / NTSC));
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::ppu::{PPU, get_color_from_palette};
+use crate::ines_file::Rom;
+use crate::png;
+use crate::screenshot;
+
+const TABLE_SCALE: u32 = 2;
+const TABLES_HEIGHT: u32 = 128 * TABLE_SCALE;
+const SWATCH_SIZE: u32 = 32;
+const PALETTE_Y: i32 = TABLES_HEIGHT as i32 + 8;
+const HINT_Y: i32 = PALETTE_Y + 2 * SWATCH_SIZE as i32 + 8;
+
+const WINDOW_WIDTH: u32 = 2 * 128 * TABLE_SCALE;
+const WINDOW_HEIGHT: u32 = HINT_Y as u32 + 28;
+
+// Both pattern tables ($0000 left, $1000 right) in one of the eight palettes,
+// above the palette RAM: background palettes on the first row, sprites below
+pub struct PatternViewer {
+    window: DebugWindow,
+    palette: u8,
+}
+
+// Both pattern tables side by side, 256x128
+fn chr_sheet(ppu: &PPU, rom: &Rom, palette: u8) -> Vec<Color> {
+    let left = ppu.get_pattern_table(rom, 0, palette);
+    let right = ppu.get_pattern_table(rom, 1, palette);
+
+    let mut sheet = Vec::with_capacity(256 * 128);
+    for y in 0..128 {
+        sheet.extend_from_slice(&left[y * 128..(y + 1) * 128]);
+        sheet.extend_from_slice(&right[y * 128..(y + 1) * 128]);
+    }
+    sheet
+}
+
+impl PatternViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(PatternViewer {
+            window: DebugWindow::open(video, "Pattern tables", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            palette: 0,
+        })
+    }
+
+    fn export_png(&self, ppu: &PPU, rom: &Rom) -> Result<String, String> {
+        let rgb: Vec<u8> = chr_sheet(ppu, rom, self.palette)
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect();
+
+        let path = screenshot::next_screenshot_path("chr");
+        fs::write(&path, png::encode_rgb(256, 128, &rgb)).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    fn draw_palettes(&mut self, font: &mut Font, palette_ram: &[u8; 32]) {
+        for (i, entry) in palette_ram.iter().enumerate() {
+            let x = (i % 16) as i32 * SWATCH_SIZE as i32;
+            let y = PALETTE_Y + (i / 16) as i32 * SWATCH_SIZE as i32;
+            let color = get_color_from_palette(*entry);
+
+            self.window.canvas.set_draw_color(color);
+            self.window.canvas.fill_rect(Rect::new(x, y, SWATCH_SIZE, SWATCH_SIZE)).unwrap();
+
+            // Dark text on light colours
+            let brightness = color.r as u32 * 3 + color.g as u32 * 6 + color.b as u32;
+            let text_color = if brightness > 1280 { Color::RGB(0, 0, 0) } else { Color::RGB(255, 255, 255) };
+            self.window.draw_text(font, &format!("{:02X}", entry), text_color, x + 2, y + 4);
+        }
+
+        let selected = self.palette as i32;
+        let rect = Rect::new(
+            (selected % 4) * 4 * SWATCH_SIZE as i32,
+            PALETTE_Y + (selected / 4) * SWATCH_SIZE as i32,
+            4 * SWATCH_SIZE,
+            SWATCH_SIZE,
+        );
+        self.window.canvas.set_draw_color(Color::RGB(255, 0, 0));
+        self.window.canvas.draw_rect(rect).unwrap();
+    }
+}
+
+impl DebugView for PatternViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, _debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                let number = key.into_i32() - Keycode::Num1.into_i32();
+                if (0..8).contains(&number) {
+                    self.palette = number as u8;
+                } else if *key == Keycode::S {
+                    match self.export_png(&cpu.bus.ppu, &cpu.bus.rom) {
+                        Ok(path) => println!("Pattern tables saved to {}", path),
+                        Err(e) => println!("Failed to save pattern tables: {}", e),
+                    }
+                }
+            }
+
+            // Clicking a palette in the grid selects it
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                let row = (y - PALETTE_Y) / SWATCH_SIZE as i32;
+                if *y >= PALETTE_Y && row < 2 && (0..WINDOW_WIDTH as i32).contains(x) {
+                    self.palette = (row * 4 + x / (4 * SWATCH_SIZE as i32)) as u8;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, _debugger: &Debugger, font: &mut Font) {
+        let sheet = chr_sheet(&cpu.bus.ppu, &cpu.bus.rom, self.palette);
+        let palette_ram = cpu.bus.ppu.palette_ram(&cpu.bus.rom);
+
+        self.window.canvas.set_draw_color(Color::RGB(32, 32, 32));
+        self.window.canvas.clear();
+
+        self.window.draw_pixels(&sheet, 256, 128, Rect::new(0, 0, WINDOW_WIDTH, TABLES_HEIGHT));
+        self.draw_palettes(font, &palette_ram);
+
+        let hint = format!("Palette {} | 1-8: select | S: save PNG", self.palette);
+        self.window.draw_text(font, &hint, Color::RGB(255, 255, 255), 4, HINT_Y);
+
+        self.window.canvas.present();
+    }
+}
@@ -0,0 +1,201 @@
+// Minimal PNG encoder: 8-bit RGB, no interlacing, zlib stream made of stored
+// (uncompressed) deflate blocks. Files are larger than necessary but need no
+// external compression library.
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "RGB buffer does not match image size");
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&PNG_SIGNATURE);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.push(8); // Bit depth
+    ihdr.push(2); // Colour type: truecolour
+    ihdr.push(0); // Compression: deflate
+    ihdr.push(0); // Filter method
+    ihdr.push(0); // No interlace
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    // Every scanline starts with filter type 0 (none)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Reads back a PNG written by `encode_rgb`, for reporting how much a golden
+// frame differs. Compressed files from other encoders give None.
+pub fn decode_rgb(png: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    if png.get(..8)? != PNG_SIGNATURE {
+        return None;
+    }
+
+    let mut size = None;
+    let mut zlib = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().ok()?) as usize;
+        let kind = &png[pos + 4..pos + 8];
+        let data = png.get(pos + 8..pos + 8 + len)?;
+        match kind {
+            b"IHDR" if data.get(8..13)? == [8, 2, 0, 0, 0] => {
+                let width = u32::from_be_bytes(data[0..4].try_into().ok()?) as usize;
+                let height = u32::from_be_bytes(data[4..8].try_into().ok()?) as usize;
+                size = Some((width, height));
+            }
+            b"IHDR" => return None,
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += len + 12;
+    }
+
+    let (width, height) = size?;
+    let raw = zlib_unstored(&zlib)?;
+    if raw.len() != height * (width * 3 + 1) {
+        return None;
+    }
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for row in raw.chunks(width * 3 + 1) {
+        if row[0] != 0 {
+            return None; // Filtered scanline
+        }
+        rgb.extend_from_slice(&row[1..]);
+    }
+    Some((width, height, rgb))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_STORED_BLOCK * 5 + 11);
+    out.push(0x78); // CMF: deflate, 32K window
+    out.push(0x01); // FLG: no dictionary, fastest
+
+    let blocks: Vec<&[u8]> = data.chunks(MAX_STORED_BLOCK).collect();
+    if blocks.is_empty() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    for (i, block) in blocks.iter().enumerate() {
+        let last = i == blocks.len() - 1;
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn zlib_unstored(zlib: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut pos = 2;
+    loop {
+        let header = *zlib.get(pos)?;
+        if header & 0x06 != 0 {
+            return None; // Compressed block
+        }
+        let len = u16::from_le_bytes(zlib.get(pos + 1..pos + 3)?.try_into().ok()?) as usize;
+        data.extend_from_slice(zlib.get(pos + 5..pos + 5 + len)?);
+        pos += 5 + len;
+        if header & 0x01 != 0 {
+            return Some(data);
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn encodes_a_valid_png() {
+        let png = encode_rgb(2, 1, &[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        assert_eq!(png[png.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        // Every chunk's CRC covers its type and data
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(&png[pos + 4..pos + 8 + len]), crc);
+            pos += len + 12;
+        }
+        assert_eq!(pos, png.len());
+    }
+
+    #[test]
+    fn splits_large_images_into_stored_blocks() {
+        let rgb: Vec<u8> = (0..256 * 240 * 3).map(|i| (i * 7) as u8).collect();
+        let png = encode_rgb(256, 240, &rgb);
+        assert_eq!(decode_rgb(&png), Some((256, 240, rgb)));
+    }
+
+    #[test]
+    fn refuses_images_it_did_not_write() {
+        assert_eq!(decode_rgb(b"GIF89a"), None);
+
+        let mut png = encode_rgb(1, 1, &[1, 2, 3]);
+        let idat = png.windows(4).position(|kind| kind == b"IDAT").unwrap();
+        png[idat + 6] = 0x02; // Fixed Huffman block
+        assert_eq!(decode_rgb(&png), None);
+    }
+}
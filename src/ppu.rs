@@ -1,4 +1,7 @@
 use crate::ines_file::Rom;
+use crate::cdl::{CodeDataLog, CHR_DRAWN};
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
 use sdl2::pixels::Color;
 
 
@@ -22,11 +25,44 @@ const NES_PALETTE: [Color; 64] = [
 ];
 
 
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// Frames before an open bus bit that has not been refreshed decays to 0 (about 600ms)
+const OPEN_BUS_DECAY_FRAMES: u32 = 36;
+// Level kept by the colour channels that are not emphasized
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
 pub fn get_color_from_palette(palette_indx: u8) -> Color {
     NES_PALETTE[(palette_indx & 0x03F) as usize]
 }
 
+// Colour of a frame buffer pixel, with the emphasis bits applied
+pub fn pixel_color(pixel: u16) -> Color {
+    let color = get_color_from_palette(pixel as u8);
+    let emphasis = (pixel >> 6) & 0x07;
+    if emphasis == 0 {
+        return color;
+    }
+
+    let channel = |value: u8, bit: u16| {
+        if emphasis & bit != 0 { value } else { (value as f32 * EMPHASIS_ATTENUATION) as u8 }
+    };
+    Color::RGB(channel(color.r, 0x01), channel(color.g, 0x02), channel(color.b, 0x04))
+}
+
+// One nametable entry as seen by the debug viewer
+pub struct NametableTile {
+    pub ppu_addr: u16,
+    pub tile: u8,
+    pub attr_addr: u16,
+    pub attr_byte: u8,
+    pub palette: u8,
+}
+
 pub struct PPU {
+    region: Region,
+
     tbl_name: [[u8; 1024]; 2],
     tbl_palette:[u8; 32],
 
@@ -40,19 +76,27 @@ pub struct PPU {
     pub status: u8,
 
     data_buffer: u8,
+    io_latch: u8,                // PPU open bus
+    io_latch_refresh: [u32; 8],  // Frame each latch bit was last driven
 
     scanline: i16,
+    frame: u32,
     cycle: i16,
-
-    pub emitted_nmi: bool,
+    odd_frame: bool,
+    suppress_vblank: bool, // $2002 was read one dot before vblank starts
 
     pub oam_addr: u8,
     pub oam_data: [u8; 256],
+
+    // Bits 0-5: NES colour index, bits 6-8: emphasis bits of PPUMASK
+    pub frame_buffer: Vec<u16>,
 }
 
 impl PPU {
-    pub fn new() -> Self {
+    pub fn new(region: Region) -> Self {
         PPU {
+            region,
+
             tbl_name: [[0x00; 1024]; 2],
             tbl_palette: [0x00; 32],
 
@@ -66,43 +110,58 @@ impl PPU {
             status: 0,
 
             data_buffer: 0,
+            io_latch: 0,
+            io_latch_refresh: [0; 8],
 
             scanline: 0,
+            frame: 0,
             cycle: 0,
-
-            emitted_nmi: false,
+            odd_frame: false,
+            suppress_vblank: false,
 
             oam_addr: 0,
             oam_data: [0x00; 256],
+
+            frame_buffer: vec![0x0000; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
     pub fn step(&mut self) {
+        let show_background = (self.mask & 0x08) != 0;
+        let show_sprites = (self.mask & 0x10) != 0;
+
+        // NTSC skips the last dot of the pre-render line on odd frames while rendering
+        if self.scanline == -1 && self.cycle == 339 && self.odd_frame
+            && (show_background || show_sprites) && self.region == Region::Ntsc {
+            self.cycle = 340;
+        }
+
         self.cycle += 1;
 
         if self.cycle >= 341 {
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline >= 261 {
+            // The pre-render line is numbered -1
+            if self.scanline >= self.region.scanlines() - 1 {
                 self.scanline = -1;
-
-                self.status &= !(1 << 7);
-                self.status &= !(1 << 6);
+                self.odd_frame = !self.odd_frame;
+                self.frame = self.frame.wrapping_add(1);
             }
         }
 
-        if self.scanline == 241 && self.cycle == 1 {
-            self.status |=  1 << 7;
+        // Vblank, sprite 0 hit and sprite overflow clear on dot 1 of the pre-render line
+        if self.scanline == -1 && self.cycle == 1 {
+            self.status &= !0xE0;
+        }
 
-            if (self.control & 0x80) != 0 {
-                self.emitted_nmi = true;
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
+            if !self.suppress_vblank {
+                self.status |=  1 << 7;
             }
+            self.suppress_vblank = false;
         }
 
-        let show_background = (self.mask & 0x08) != 0;
-        let show_sprites = (self.mask & 0x10) != 0;
-
         if show_background && show_sprites {
             let sprite_0_y = self.oam_data[0] as i16;
 
@@ -114,32 +173,139 @@ impl PPU {
         }
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for table in self.tbl_name.iter() {
+            writer.write_bytes(table);
+        }
+        writer.write_bytes(&self.tbl_palette);
+
+        writer.write_u16(self.vram_addr);
+        writer.write_u16(self.temp_addr);
+        writer.write_u8(self.fine_x);
+        writer.write_bool(self.write_toggle);
+
+        writer.write_u8(self.control);
+        writer.write_u8(self.mask);
+        writer.write_u8(self.status);
+        writer.write_u8(self.data_buffer);
+        writer.write_u8(self.io_latch);
+
+        writer.write_u16(self.scanline as u16);
+        writer.write_u16(self.cycle as u16);
+        writer.write_bool(self.odd_frame);
+        writer.write_bool(self.suppress_vblank);
+
+        writer.write_u8(self.oam_addr);
+        writer.write_bytes(&self.oam_data);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        for table in self.tbl_name.iter_mut() {
+            reader.read_bytes(table)?;
+        }
+        reader.read_bytes(&mut self.tbl_palette)?;
+
+        self.vram_addr = reader.read_u16()?;
+        self.temp_addr = reader.read_u16()?;
+        self.fine_x = reader.read_u8()?;
+        self.write_toggle = reader.read_bool()?;
+
+        self.control = reader.read_u8()?;
+        self.mask = reader.read_u8()?;
+        self.status = reader.read_u8()?;
+        self.data_buffer = reader.read_u8()?;
+        self.io_latch = reader.read_u8()?;
+        self.io_latch_refresh = [self.frame; 8];
+
+        self.scanline = reader.read_u16()? as i16;
+        self.cycle = reader.read_u16()? as i16;
+        self.odd_frame = reader.read_bool()?;
+        self.suppress_vblank = reader.read_bool()?;
+
+        self.oam_addr = reader.read_u8()?;
+        reader.read_bytes(&mut self.oam_data)?;
+        Ok(())
+    }
+
+    // The data lines between the CPU and the PPU hold their charge for a while,
+    // so undriven bits read back whatever was last put on them
+    fn refresh_latch(&mut self, data: u8, bits: u8) {
+        self.io_latch = (self.io_latch & !bits) | (data & bits);
+        for (bit, refresh) in self.io_latch_refresh.iter_mut().enumerate() {
+            if bits & (1 << bit) != 0 {
+                *refresh = self.frame;
+            }
+        }
+    }
+
+    fn open_bus(&mut self) -> u8 {
+        for (bit, refresh) in self.io_latch_refresh.iter().enumerate() {
+            if self.frame.wrapping_sub(*refresh) > OPEN_BUS_DECAY_FRAMES {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+        self.io_latch
+    }
+
     pub fn cpu_read(&mut self, addr: u16, readonly: bool, rom: &mut Rom) -> u8 {
-        let mut data: u8 = 0x00;
+        // Write-only registers read back the open bus
+        let mut data: u8 = self.open_bus();
 
         match addr {
             0x0000 => {}, // Control (Write Only)
             0x0001 => {}, // Mask (Write Only)
             0x0002 => {   // Status
-                data = (self.status & 0xE0) | (self.data_buffer & 0x1F);
+                data = (self.status & 0xE0) | (data & 0x1F);
                 if !readonly {
                     self.status &= !(1 << 7);
                     self.write_toggle = false;
+                    self.refresh_latch(data, 0xE0);
+
+                    // Race with vblank start: a read one dot early reads it clear and
+                    // the flag never sets. A read on the dot itself or the next one
+                    // clears it before the CPU samples the NMI line, see nmi_line().
+                    if self.scanline == self.region.vblank_scanline() && self.cycle == 0 {
+                        self.suppress_vblank = true;
+                    }
                 }
             },
             0x0003 => {}, // OAM Addr
             0x0004 => { // OAM Data
-               data = self.oam_data[self.oam_addr as usize];
+                data = self.oam_data[self.oam_addr as usize];
+
+                // Bits 2-4 of the sprite attribute byte do not exist
+                if (self.oam_addr & 0x03) == 0x02 {
+                    data &= 0xE3;
+                }
+
+                if !readonly {
+                    self.refresh_latch(data, 0xFF);
+                }
             },
 
             0x0005 => {}, // Scroll
             0x0006 => {}, // PPU Addr
             0x0007 => {   // PPU Data
-                data = self.data_buffer;
-                self.data_buffer = self.ppu_read(self.vram_addr, rom);
-                
-                if self.vram_addr >= 0x3F00 {
+                if readonly {
+                    return self.data_buffer;
+                }
+
+                if (self.vram_addr & 0x3FFF) >= 0x3F00 {
+                    // Palette reads skip the buffer, which gets the nametable byte
+                    // underneath instead. Palette entries are only 6 bits wide.
+                    let mut color = self.ppu_read(self.vram_addr, rom);
+                    if (self.mask & 0x01) != 0 {
+                        color &= 0x30;
+                    }
+                    data = (data & 0xC0) | (color & 0x3F);
+                    self.refresh_latch(data, 0x3F);
+
+                    self.data_buffer = self.ppu_read(self.vram_addr.wrapping_sub(0x1000), rom);
+                } else {
                     data = self.data_buffer;
+                    self.refresh_latch(data, 0xFF);
+
+                    self.data_buffer = self.ppu_read(self.vram_addr, rom);
                 }
 
                 self.vram_addr = self.vram_addr.wrapping_add(if (self.control & 0x04) == 0 {1} else {32});
@@ -150,17 +316,12 @@ impl PPU {
     }
 
     pub fn cpu_write(&mut self, addr: u16, data: u8, rom: &mut Rom) {
+        self.refresh_latch(data, 0xFF);
+
         match addr {
             0x0000 => {
-                let old_nmi = (self.control & 0x80) != 0;
                 self.control = data;
-                let new_nmi = (self.control & 0x80) != 0;
-
                 self.temp_addr = (self.temp_addr & 0xF3FF) | ((data as u16 & 0x03) << 10);
-
-                if (self.status & 0x80) != 0 && !old_nmi && new_nmi {
-                    self.emitted_nmi = true;
-                }
             },
             0x0001 => {
                 self.mask = data;
@@ -217,26 +378,25 @@ impl PPU {
 
         match addr {
             0x0000..=0x1FFF => {
-                if (addr as usize) < rom.chr_rom.len() {
-                    rom.chr_rom[addr as usize]
+                let index = rom.mapper.ppu_read_mapper_addr(addr);
+                if index < rom.chr_rom.len() {
+                    rom.chr_rom[index]
                 } else {
                     0
                 }
             },
 
-            0x2000..=0x3EEF => {
+            0x2000..=0x3EFF => {
                 let masked_addr = addr & 0x0FFF;
-                let vram_index = masked_addr & 0x03FF;
-                let name_table = masked_addr / 0x0400;
+                let vram_index = (masked_addr & 0x03FF) as usize;
+                let page = rom.nametable_page(masked_addr / 0x0400);
 
-                let final_idx = if rom.screen_mirroring {
-                    if name_table == 0 || name_table == 2 { 0 } else { 1 }
+                // Pages 0-1 are the console's VRAM, 2-3 come from the cartridge
+                if page < 2 {
+                    self.tbl_name[page][vram_index]
                 } else {
-                    if name_table == 0 || name_table == 1 { 0 } else { 1 }
-                };
-
-                self.tbl_name[final_idx][vram_index as usize]
-
+                    rom.extra_vram.get((page - 2) * 0x0400 + vram_index).copied().unwrap_or(0)
+                }
             },
 
             0x3F00..=0x3FFF => {
@@ -257,23 +417,22 @@ impl PPU {
     pub fn ppu_write(&mut self, addr: u16, data: u8, rom: &mut Rom) {
         let addr = addr & 0x3FFF;
         match addr {
-            0x0000..=0x1FFF => {},
+            0x0000..=0x1FFF => {
+                let index = rom.mapper.ppu_read_mapper_addr(addr);
+                if rom.chr_ram && index < rom.chr_rom.len() {
+                    rom.chr_rom[index] = data;
+                }
+            },
 
             0x2000..=0x3EFF => {
                 let masked_addr = addr & 0x0FFF;
-                let vram_index = masked_addr & 0x03FF;
-                let name_table = masked_addr / 0x0400;
-
-                let final_idx = if rom.screen_mirroring {
-                    if name_table == 0 || name_table == 2 { 0 } else { 1 }
-                    } else {
-                    if name_table == 0 || name_table == 1 { 0 } else { 1 }
-                };
+                let vram_index = (masked_addr & 0x03FF) as usize;
+                let page = rom.nametable_page(masked_addr / 0x0400);
 
-                if final_idx < 0x0400 {
-                    self.tbl_name[final_idx][vram_index as usize] = data;
-                } else {
-                    self.tbl_name[1][vram_index as usize] = data;
+                if page < 2 {
+                    self.tbl_name[page][vram_index] = data;
+                } else if let Some(byte) = rom.extra_vram.get_mut((page - 2) * 0x0400 + vram_index) {
+                    *byte = data;
                 }
             },
 
@@ -291,49 +450,414 @@ impl PPU {
 
     }
 
+    // One 4KB pattern table as a 128x128 image of 16x16 tiles, coloured with
+    // palette `palette_idx` (0-3 background, 4-7 sprites)
     pub fn get_pattern_table(&self, rom: &Rom, table_idx: u8, palette_idx: u8) -> Vec<Color> {
         let base_addr: u16 = (table_idx as u16) << 12;
+        let palette_base = 0x3F00 + (palette_idx as u16 & 0x07) * 4;
+
+        let mut image_data = vec![Color::RGB(0, 0, 0); 128 * 128];
+
+        for tile_index in 0..256 {
+            let tile_addr = base_addr + (tile_index as u16) * 16;
+            let (tile_x, tile_y) = (tile_index % 16, tile_index / 16);
+
+            for row in 0..8 {
+                let plane_0_byte = self.ppu_read(tile_addr + row as u16, rom);
+                let plane_1_byte = self.ppu_read(tile_addr + row as u16 + 8, rom);
+
+                for col in 0..8 {
+                    let pixel_bit_0 = (plane_0_byte >> (7 - col)) & 1;
+                    let pixel_bit_1 = (plane_1_byte >> (7 - col)) & 1;
+                    let color_index = (pixel_bit_1 << 1) | pixel_bit_0;
+
+                    let palette_entry = self.ppu_read(palette_base + color_index as u16, rom);
+                    image_data[(tile_y * 8 + row) * 128 + tile_x * 8 + col] = get_color_from_palette(palette_entry);
+                }
+            }
+        }
+        image_data
+    }
+
+    // The /NMI output, active while the vblank flag and NMI enable are both
+    // set. Clearing either before the CPU samples it cancels the NMI, and
+    // enabling NMI during vblank raises a new one.
+    pub fn nmi_line(&self) -> bool {
+        (self.status & 0x80) != 0 && (self.control & 0x80) != 0
+    }
+
+    pub fn scanline(&self) -> i16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> i16 {
+        self.cycle
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Address the next $2007 access goes to
+    pub fn vram_address(&self) -> u16 {
+        self.vram_addr & 0x3FFF
+    }
+
+    // 8 or 16, from PPUCTRL bit 5
+    pub fn sprite_height(&self) -> usize {
+        if (self.control & 0x20) != 0 { 16 } else { 8 }
+    }
 
-        let mut image_data = Vec::with_capacity(128 * 128);
+    // Sprites in range of `scanline` during sprite evaluation, in OAM order.
+    // Only the first 8 are drawn on the next line; the rest are dropped.
+    pub fn sprites_on_scanline(&self, scanline: i16) -> Vec<usize> {
+        let height = self.sprite_height() as i16;
+        (0..64)
+            .filter(|i| {
+                let row = scanline - self.oam_data[i * 4] as i16;
+                (0..height).contains(&row)
+            })
+            .collect()
+    }
 
-        for tile_y in 0..16 {
-            for tile_x in 0..16 {
-                let tile_index = tile_y * 16 + tile_x;
-                let tile_addr = base_addr + (tile_index as u16) * 16;
+    // OAM entry `index` drawn as an 8x8 or 8x16 image with its palette and
+    // flips, transparent pixels in the backdrop colour
+    pub fn sprite_preview(&self, rom: &Rom, index: usize) -> Vec<Color> {
+        let height = self.sprite_height();
+        let tile_idx = self.oam_data[index * 4 + 1] as u16;
+        let attr = self.oam_data[index * 4 + 2];
+        let flip_h = (attr & 0x40) != 0;
+        let flip_v = (attr & 0x80) != 0;
+        let palette_base = 0x3F10 + (attr as u16 & 0x03) * 4;
+
+        // 8x16 sprites take the bank from bit 0 of the tile index
+        let tile_start = if height == 16 {
+            (tile_idx & 0x01) * 0x1000 + (tile_idx & 0xFE) * 16
+        } else {
+            (if (self.control & 0x08) != 0 { 0x1000 } else { 0x0000 }) + tile_idx * 16
+        };
+
+        let mut image_data = Vec::with_capacity(8 * height);
+        for row in 0..height {
+            let sprite_row = if flip_v { height - 1 - row } else { row };
+            // The bottom half of an 8x16 sprite is the next tile
+            let row_addr = tile_start + (sprite_row / 8) as u16 * 16 + (sprite_row % 8) as u16;
+            let plane_0 = self.ppu_read(row_addr, rom);
+            let plane_1 = self.ppu_read(row_addr + 8, rom);
+
+            for col in 0..8 {
+                let sprite_col = if flip_h { 7 - col } else { col };
+                let pixel_val = (((plane_1 >> (7 - sprite_col)) & 1) << 1) | ((plane_0 >> (7 - sprite_col)) & 1);
+                let palette_addr = if pixel_val == 0 { 0x3F00 } else { palette_base + pixel_val as u16 };
+                image_data.push(get_color_from_palette(self.ppu_read(palette_addr, rom)));
+            }
+        }
+        image_data
+    }
+
+    // Palette RAM as seen through $3F00-$3F1F, mirrors included
+    pub fn palette_ram(&self, rom: &Rom) -> [u8; 32] {
+        let mut palette = [0u8; 32];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = self.ppu_read(0x3F00 + i as u16, rom);
+        }
+        palette
+    }
+
+    // Entry under pixel (x, y) of the 512x480 map of the four logical nametables
+    pub fn nametable_tile(&self, rom: &Rom, x: usize, y: usize) -> NametableTile {
+        let table = (y / 240) * 2 + (x / 256);
+        let (tile_x, tile_y) = ((x % 256) / 8, (y % 240) / 8);
+        let base = 0x2000 + (table as u16) * 0x0400;
+
+        let ppu_addr = base + (tile_y * 32 + tile_x) as u16;
+        let attr_addr = base + 0x03C0 + ((tile_y / 4) * 8 + tile_x / 4) as u16;
+        let attr_byte = self.ppu_read(attr_addr, rom);
+        let shift = ((tile_y % 4) / 2 * 2 + (tile_x % 4) / 2) * 2;
+
+        NametableTile {
+            ppu_addr,
+            tile: self.ppu_read(ppu_addr, rom),
+            attr_addr,
+            attr_byte,
+            palette: (attr_byte >> shift) & 0x03,
+        }
+    }
+
+    // All four logical nametables as palette indices, 512x480, using the
+    // background pattern table selected in PPUCTRL
+    pub fn render_nametables(&self, rom: &Rom) -> Vec<u8> {
+        let bank_offset = ((self.control >> 4) & 1) as u16 * 0x1000;
+        let mut pixels = vec![0u8; 512 * 480];
+
+        for tile_y in 0..60 {
+            for tile_x in 0..64 {
+                let entry = self.nametable_tile(rom, tile_x * 8, tile_y * 8);
+                let tile_start = bank_offset + (entry.tile as u16) * 16;
 
                 for row in 0..8 {
-                    let plane_0_byte = self.ppu_read(tile_addr + row, rom);
-                    let plane_1_byte = self.ppu_read(tile_addr + row + 8, rom);
+                    let plane_0 = self.ppu_read(tile_start + row as u16, rom);
+                    let plane_1 = self.ppu_read(tile_start + row as u16 + 8, rom);
 
                     for col in 0..8 {
-                        let pixel_bit_0 = (plane_0_byte >> (7 - col)) & 1;
-                        let pixel_bit_1 = (plane_1_byte >> (7 - col)) & 1;
+                        let pixel_val = (((plane_1 >> (7 - col)) & 1) << 1) | ((plane_0 >> (7 - col)) & 1);
+                        let palette_addr = if pixel_val == 0 {
+                            0x3F00
+                        } else {
+                            0x3F00 + (entry.palette as u16) * 4 + pixel_val as u16
+                        };
+
+                        pixels[(tile_y * 8 + row) * 512 + tile_x * 8 + col] = self.ppu_read(palette_addr, rom) & 0x3F;
+                    }
+                }
+            }
+        }
+        pixels
+    }
 
-                        let color_index = (pixel_bit_1 << 1) | pixel_bit_0;
+    // Top-left corner of the visible screen in the 512x480 nametable map,
+    // taken from the scroll written to the t register
+    pub fn scroll_origin(&self) -> (usize, usize) {
+        let t = self.temp_addr as usize;
+        let x = ((t & 0x001F) << 3) | self.fine_x as usize;
+        let y = (((t >> 5) & 0x001F) << 3) | ((t >> 12) & 0x07);
+        (x + ((t >> 10) & 1) * 256, y + ((t >> 11) & 1) * 240)
+    }
 
-                        let palette_base = 0x3F00 + (palette_idx * 4) as u16;
+    pub fn render_frame(&mut self, rom: &Rom, mut cdl: Option<&mut CodeDataLog>) {
+        self.render_background(rom, &mut cdl);
+        self.render_sprites(rom, &mut cdl);
+    }
 
-                        let mut final_palette_index = self.ppu_read(palette_base + color_index as u16, rom);
+    // Pattern fetch for rendering, recorded in the code/data log when one is kept
+    fn pattern_read(&self, addr: u16, rom: &Rom, cdl: &mut Option<&mut CodeDataLog>) -> u8 {
+        if let Some(cdl) = cdl {
+            cdl.log_chr(rom.mapper.ppu_read_mapper_addr(addr), CHR_DRAWN);
+        }
+        self.ppu_read(addr, rom)
+    }
 
-                        if final_palette_index == 0 && color_index > 0 {
-                            final_palette_index = 0x30;
-                        }
+    // Frame buffer value for a palette entry under the current greyscale and
+    // emphasis bits. PAL swaps the red and green emphasis bits; they are stored
+    // in RGB order either way.
+    fn output_pixel(&self, color_idx: u8) -> u16 {
+        let color = if (self.mask & 0x01) != 0 { color_idx & 0x30 } else { color_idx & 0x3F };
 
-                        let mut color: Color = get_color_from_palette(final_palette_index);
-                        /*
-                        color = match color_index {
-                            0 => Color::RGB(0, 0, 0),       // Preto
-                            1 => Color::RGB(255, 100, 100), // Cinza Escuro
-                            2 => Color::RGB(170, 255, 170), // Cinza Claro
-                            3 => Color::RGB(255, 255, 255), // Branco
-                            _ => Color::RGB(0,0,0),
+        let mut emphasis = (self.mask >> 5) as u16;
+        if self.region != Region::Ntsc {
+            emphasis = (emphasis & 0x04) | ((emphasis & 0x01) << 1) | ((emphasis & 0x02) >> 1);
+        }
+
+        color as u16 | (emphasis << 6)
+    }
+
+    fn render_background(&mut self, rom: &Rom, cdl: &mut Option<&mut CodeDataLog>) {
+        let bank = (self.control >> 4) & 1;
+        let mut palette_cache = [0u8; 32];
+        for (i, entry) in palette_cache.iter_mut().enumerate() {
+            *entry = self.ppu_read(0x3F00 + i as u16, rom);
+        }
+
+        for y in 0..30 {
+            for x in 0..32 {
+                let tile_idx = self.ppu_read(0x2000 + (y * 32) + x, rom) as u16;
+
+                let bank_offset = bank as u16 * 0x1000;
+                let tile_start = bank_offset + (tile_idx * 16);
+
+                let attr_addr = 0x23C0 + (y / 4) * 8 + (x / 4);
+                let attr_byte = self.ppu_read(attr_addr, rom);
+
+                let shift = ((y % 4) / 2 * 2 + (x % 4) / 2) * 2;
+                let palette_idx = (attr_byte >> shift) & 0x03;
+
+                for row in 0..8 {
+                    let plane_0 = self.pattern_read(tile_start + row, rom, cdl);
+                    let plane_1 = self.pattern_read(tile_start + row + 8, rom, cdl);
+
+                    for col in 0..8 {
+                        let bit_0 = (plane_0 >> (7 - col)) & 1;
+                        let bit_1 = (plane_1 >> (7 - col)) & 1;
+                        let pixel_val = (bit_1 << 1) | bit_0;
+
+                        let color_idx = if pixel_val == 0 {
+                            palette_cache[0]
+                        } else {
+                            palette_cache[(palette_idx as usize * 4) + pixel_val as usize]
                         };
-                        */
-                        image_data.push(color);
+
+                        let screen_x = (x * 8 + (col as u16)) as usize;
+                        let screen_y = (y * 8 + row) as usize;
+
+                        self.frame_buffer[screen_y * SCREEN_WIDTH + screen_x] = self.output_pixel(color_idx);
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_sprites(&mut self, rom: &Rom, cdl: &mut Option<&mut CodeDataLog>) {
+        let oam_ptr = if (self.control & 0x08) != 0 {0x1000} else {0x000};
+
+        let mut palette_cache = [0u8; 16];
+        for (i, entry) in palette_cache.iter_mut().enumerate() {
+            *entry = self.ppu_read(0x3F10 + i as u16, rom);
+        }
+
+        for i in 0..64 {
+            let offset = i * 4;
+
+            let y = self.oam_data[offset] as i32;
+            let tile_idx = self.oam_data[offset + 1] as u16;
+            let attr = self.oam_data[offset + 2];
+            let x = self.oam_data[offset + 3] as i32;
+
+            let flip_h = (attr & 0x40) != 0;
+            let flip_v = (attr & 0x80) != 0;
+            let palette_idx = attr & 0x03;
+
+            let tile_start = oam_ptr + (tile_idx * 16);
+
+            for row in 0..8 {
+                let sprite_row = if flip_v {7 - row} else {row};
+
+                let plane_0 = self.pattern_read(tile_start + sprite_row, rom, cdl);
+                let plane_1 = self.pattern_read(tile_start + sprite_row + 8, rom, cdl);
+
+                for col in 0..8 {
+                    let sprite_col = if flip_h {7 - col} else {col};
+
+                    let bit_0 = (plane_0 >> (7 - sprite_col)) & 1;
+                    let bit_1 = (plane_1 >> (7 - sprite_col)) & 1;
+                    let pixel_val = (bit_1 << 1) | bit_0;
+
+                    if pixel_val == 0 {
+                        continue;
+                    }
+
+                    let screen_x = x + col as i32;
+                    let screen_y = y + row as i32;
+
+                    if (0..SCREEN_WIDTH as i32).contains(&screen_x) && (0..SCREEN_HEIGHT as i32).contains(&screen_y) {
+                        let color_idx = palette_cache[(palette_idx as usize * 4) + pixel_val as usize];
+                        self.frame_buffer[screen_y as usize * SCREEN_WIDTH + screen_x as usize] = self.output_pixel(color_idx);
                     }
                 }
             }
         }
-        image_data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines_file::test_rom;
+    use crate::mapper::{Mapper007, Mirroring};
+
+    fn set_address(ppu: &mut PPU, rom: &mut Rom, addr: u16) {
+        ppu.cpu_write(0x0006, (addr >> 8) as u8, rom);
+        ppu.cpu_write(0x0006, addr as u8, rom);
+    }
+
+    fn read_vram(ppu: &mut PPU, rom: &mut Rom, addr: u16) -> u8 {
+        set_address(ppu, rom, addr);
+        ppu.cpu_read(0x0007, false, rom); // Fills the read buffer
+        ppu.cpu_read(0x0007, false, rom)
+    }
+
+    fn write_vram(ppu: &mut PPU, rom: &mut Rom, addr: u16, data: u8) {
+        set_address(ppu, rom, addr);
+        ppu.cpu_write(0x0007, data, rom);
+    }
+
+    // Writes 1-4 to the four nametables in order and reads what each shows
+    fn nametable_contents(rom: &mut Rom) -> [u8; 4] {
+        let mut ppu = PPU::new(Region::Ntsc);
+        for table in 0..4u16 {
+            write_vram(&mut ppu, rom, 0x2000 + table * 0x0400, table as u8 + 1);
+        }
+        [0, 1, 2, 3].map(|table| read_vram(&mut ppu, rom, 0x2000 + table * 0x0400))
+    }
+
+    #[test]
+    fn nametables_follow_the_board_mirroring() {
+        let mut rom = test_rom(&[], &[]);
+        rom.mirroring = Mirroring::Horizontal;
+        assert_eq!(nametable_contents(&mut rom), [2, 2, 4, 4]);
+        rom.mirroring = Mirroring::Vertical;
+        assert_eq!(nametable_contents(&mut rom), [3, 4, 3, 4]);
+        rom.mirroring = Mirroring::SingleScreenUpper;
+        assert_eq!(nametable_contents(&mut rom), [4, 4, 4, 4]);
+
+        rom.mirroring = Mirroring::FourScreen;
+        rom.extra_vram = vec![0x00; 2 * 1024];
+        assert_eq!(nametable_contents(&mut rom), [1, 2, 3, 4]);
+
+        // $3000-$3EFF mirrors $2000-$2EFF
+        let mut ppu = PPU::new(Region::Ntsc);
+        write_vram(&mut ppu, &mut rom, 0x2C05, 0x77);
+        assert_eq!(read_vram(&mut ppu, &mut rom, 0x3C05), 0x77);
+    }
+
+    #[test]
+    fn axrom_switches_between_single_screen_pages() {
+        let mut rom = test_rom(&[], &[]);
+        rom.mapper = Box::new(Mapper007::new());
+        let mut ppu = PPU::new(Region::Ntsc);
+
+        write_vram(&mut ppu, &mut rom, 0x2000, 0xAA);
+        assert_eq!(read_vram(&mut ppu, &mut rom, 0x2C00), 0xAA);
+
+        // Bit 4 selects the upper page
+        rom.mapper.cpu_write(0x8000, 0x10);
+        assert_eq!(rom.current_mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(read_vram(&mut ppu, &mut rom, 0x2000), 0x00);
+        write_vram(&mut ppu, &mut rom, 0x2400, 0xBB);
+
+        rom.mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(read_vram(&mut ppu, &mut rom, 0x2800), 0xAA);
+        rom.mapper.cpu_write(0x8000, 0x10);
+        assert_eq!(read_vram(&mut ppu, &mut rom, 0x2800), 0xBB);
+    }
+
+    #[test]
+    fn open_bus_bits_decay_unless_refreshed() {
+        let mut rom = test_rom(&[], &[]);
+        let mut ppu = PPU::new(Region::Ntsc);
+        ppu.cpu_write(0x0003, 0xFF, &mut rom);
+        assert_eq!(ppu.cpu_read(0x0000, false, &mut rom), 0xFF);
+
+        // Reading $2002 drives bits 5-7 only
+        ppu.frame = 20;
+        ppu.status = 0xE0;
+        assert_eq!(ppu.cpu_read(0x0002, false, &mut rom), 0xFF);
+
+        ppu.frame = OPEN_BUS_DECAY_FRAMES;
+        assert_eq!(ppu.cpu_read(0x0000, false, &mut rom), 0xFF);
+        ppu.frame = OPEN_BUS_DECAY_FRAMES + 1;
+        assert_eq!(ppu.cpu_read(0x0000, false, &mut rom), 0xE0);
+        ppu.frame = 21 + OPEN_BUS_DECAY_FRAMES;
+        assert_eq!(ppu.cpu_read(0x0000, false, &mut rom), 0x00);
+    }
+
+    #[test]
+    fn palette_reads_skip_the_buffer_and_refill_it_from_the_nametable_below() {
+        let mut rom = test_rom(&[], &[]);
+        let mut ppu = PPU::new(Region::Ntsc);
+        set_address(&mut ppu, &mut rom, 0x2F10);
+        ppu.cpu_write(0x0007, 0x55, &mut rom);
+        set_address(&mut ppu, &mut rom, 0x3F10);
+        ppu.cpu_write(0x0007, 0x2A, &mut rom);
+
+        // The top two bits come from the open bus, last driven by $2006
+        ppu.cpu_write(0x0003, 0xC0, &mut rom);
+        set_address(&mut ppu, &mut rom, 0x3F10);
+        assert_eq!(ppu.cpu_read(0x0007, false, &mut rom), 0x2A);
+        assert_eq!(ppu.cpu_read(0x0007, true, &mut rom), 0x55);
+
+        // The next read from below the palette returns the buffered byte
+        set_address(&mut ppu, &mut rom, 0x2000);
+        assert_eq!(ppu.cpu_read(0x0007, false, &mut rom), 0x55);
+    }
+}
+
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::bus::Bus;
+use crate::call_stack::FrameKind;
+use crate::cpu::{AddrMode, CPU};
+use crate::symbols::SymbolTable;
+
+// Polls of the same $2002 read this close together are a wait loop
+const MAX_WAIT_LOOP_CYCLES: u64 = 16;
+
+const REPORT_ENTRIES: usize = 10;
+
+#[derive(Clone, Copy, Default)]
+pub struct IntervalStats {
+    pub count: u64,
+    pub last: u64,
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl IntervalStats {
+    fn add(&mut self, cycles: u64) {
+        self.min = if self.count == 0 { cycles } else { self.min.min(cycles) };
+        self.max = self.max.max(cycles);
+        self.last = cycles;
+        self.total += cycles;
+        self.count += 1;
+    }
+
+    pub fn average(&self) -> u64 {
+        self.total.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct WaitLoop {
+    pub polls: u64,
+    pub cycles: u64,
+}
+
+// Where the CPU spends its cycles: per instruction address, per function
+// (the innermost frame of the CPU's call stack) and per whole call stack for
+// flamegraphs. Also times NMI to NMI and finds loops spinning on $2002.
+pub struct Profiler {
+    pub total_cycles: u64,
+    pub by_address: HashMap<u16, u64>,
+    pub by_function: HashMap<Option<(FrameKind, u16)>, u64>, // None is code outside any call
+    stacks: HashMap<Vec<(FrameKind, u16)>, u64>,
+
+    pub nmi_intervals: IntervalStats,
+    last_nmi: Option<(usize, u64)>, // NMI count and total cycles when it was seen
+
+    pub wait_loops: HashMap<u16, WaitLoop>,
+    last_poll: Option<(u16, u64)>,
+
+    // The instruction being run
+    pc: u16,
+    stack: Vec<(FrameKind, u16)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            total_cycles: 0,
+            by_address: HashMap::new(),
+            by_function: HashMap::new(),
+            stacks: HashMap::new(),
+
+            nmi_intervals: IntervalStats::default(),
+            last_nmi: None,
+
+            wait_loops: HashMap::new(),
+            last_poll: None,
+
+            pc: 0,
+            stack: Vec::new(),
+        }
+    }
+
+    // Reads of $2002 and its mirrors: absolute operands that are not stores
+    fn polls_status(cpu: &mut CPU, pc: u16) -> bool {
+        let opcode = cpu.bus.peek(pc);
+        let instruction = cpu.instruction(opcode);
+        if instruction.mode != AddrMode::Absolute || instruction.name.starts_with("ST") {
+            return false;
+        }
+        let addr = u16::from_le_bytes([cpu.bus.peek(pc.wrapping_add(1)), cpu.bus.peek(pc.wrapping_add(2))]);
+        (0x2000..=0x3FFF).contains(&addr) && (addr & 0x0007) == 0x0002
+    }
+
+    // Called before each instruction with the CPU as it is about to run it
+    pub fn begin_instruction(&mut self, cpu: &mut CPU) {
+        let pc = cpu.registers.pc;
+        self.pc = pc;
+
+        self.stack.clear();
+        if let Some(call_stack) = &cpu.call_stack {
+            self.stack.extend(call_stack.frames.iter().map(|frame| (frame.kind, frame.entry)));
+        }
+
+        if cpu.nmi_count != self.last_nmi.map_or(0, |(count, _)| count) {
+            if let Some((_, cycles)) = self.last_nmi {
+                self.nmi_intervals.add(self.total_cycles - cycles);
+            }
+            self.last_nmi = Some((cpu.nmi_count, self.total_cycles));
+        }
+
+        if Self::polls_status(cpu, pc) {
+            if let Some((last_pc, cycles)) = self.last_poll
+                && last_pc == pc && self.total_cycles - cycles <= MAX_WAIT_LOOP_CYCLES {
+                let wait_loop = self.wait_loops.entry(pc).or_default();
+                wait_loop.polls += 1;
+                wait_loop.cycles += self.total_cycles - cycles;
+            }
+            self.last_poll = Some((pc, self.total_cycles));
+        }
+    }
+
+    // Cycles of the instruction begun last, interrupt entry included
+    pub fn end_instruction(&mut self, cycles: usize) {
+        let cycles = cycles as u64;
+        self.total_cycles += cycles;
+        *self.by_address.entry(self.pc).or_default() += cycles;
+        *self.by_function.entry(self.stack.last().copied()).or_default() += cycles;
+
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles);
+            }
+        }
+    }
+
+    fn frame_name(symbols: &SymbolTable, bus: &Bus, frame: Option<(FrameKind, u16)>) -> String {
+        let Some((kind, entry)) = frame else {
+            return "main".to_string();
+        };
+        let name = symbols.label_at(bus, entry).map_or(format!("${:04X}", entry), |name| name.to_string());
+        match kind {
+            FrameKind::Subroutine => name,
+            _ => format!("[{}] {}", kind.name(), name),
+        }
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / self.total_cycles as f64 }
+    }
+
+    fn top<K: Copy>(map: &HashMap<K, u64>) -> Vec<(K, u64)> {
+        let mut entries: Vec<(K, u64)> = map.iter().map(|(key, cycles)| (*key, *cycles)).collect();
+        entries.sort_by_key(|(_, cycles)| std::cmp::Reverse(*cycles));
+        entries.truncate(REPORT_ENTRIES);
+        entries
+    }
+
+    pub fn report(&self, symbols: &SymbolTable, bus: &Bus) -> Vec<String> {
+        let mut lines = vec![format!("{} cycles profiled", self.total_cycles)];
+
+        let nmi = &self.nmi_intervals;
+        if nmi.count > 0 {
+            lines.push(format!("NMI to NMI: last {}, avg {}, min {}, max {} cycles",
+                nmi.last, nmi.average(), nmi.min, nmi.max));
+        }
+
+        let waiting: u64 = self.wait_loops.values().map(|wait_loop| wait_loop.cycles).sum();
+        lines.push(format!("$2002 wait loops: {} cycles ({:.1}%)", waiting, self.percent(waiting)));
+        let mut loops: Vec<(&u16, &WaitLoop)> = self.wait_loops.iter().collect();
+        loops.sort_by_key(|(_, wait_loop)| std::cmp::Reverse(wait_loop.cycles));
+        for (pc, wait_loop) in loops.iter().take(3) {
+            let location = symbols.describe(bus, **pc).map_or(format!("${:04X}", pc), |name| format!("${:04X} {}", pc, name));
+            lines.push(format!("  {}: {} polls, {} cycles", location, wait_loop.polls, wait_loop.cycles));
+        }
+
+        lines.push("Functions:".to_string());
+        for (frame, cycles) in Self::top(&self.by_function) {
+            lines.push(format!("  {:5.1}% {:>9} {}", self.percent(cycles), cycles, Self::frame_name(symbols, bus, frame)));
+        }
+
+        lines.push("Addresses:".to_string());
+        for (pc, cycles) in Self::top(&self.by_address) {
+            let name = symbols.describe(bus, pc).unwrap_or_default();
+            lines.push(format!("  {:5.1}% {:>9} ${:04X} {}", self.percent(cycles), cycles, pc, name));
+        }
+        lines
+    }
+
+    // One "outer;inner cycles" line per call stack, the folded format read by
+    // flamegraph.pl, inferno and speedscope
+    pub fn folded_stacks(&self, symbols: &SymbolTable, bus: &Bus) -> String {
+        let mut lines: Vec<String> = self.stacks.iter().map(|(stack, cycles)| {
+            let mut names = vec!["main".to_string()];
+            names.extend(stack.iter().map(|frame| Self::frame_name(symbols, bus, Some(*frame))));
+            format!("{} {}", names.join(";"), cycles)
+        }).collect();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    pub fn save_folded(&self, symbols: &SymbolTable, bus: &Bus, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.folded_stacks(symbols, bus)).map_err(|e| format!("{}: {}", path, e))
+    }
+}
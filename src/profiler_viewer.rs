@@ -0,0 +1,75 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::ttf::Font;
+use sdl2::VideoSubsystem;
+
+use crate::cpu::CPU;
+use crate::debug_window::{DebugView, DebugWindow};
+use crate::debugger::Debugger;
+use crate::profiler::Profiler;
+use crate::screenshot;
+
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 560;
+const LINE_HEIGHT: i32 = 18;
+
+// Live profile of the emulated code since the window opened or was reset
+pub struct ProfilerViewer {
+    window: DebugWindow,
+    status: String,
+}
+
+impl ProfilerViewer {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        Ok(ProfilerViewer {
+            window: DebugWindow::open(video, "Profiler", WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            status: "R: reset, S: save folded stacks".to_string(),
+        })
+    }
+}
+
+impl DebugView for ProfilerViewer {
+    fn window_id(&self) -> u32 {
+        self.window.id()
+    }
+
+    fn handle_event(&mut self, event: &Event, cpu: &mut CPU, debugger: &mut Debugger) {
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::R), repeat: false, .. } => {
+                cpu.profiler = Some(Profiler::new());
+                self.status = "Profile reset".to_string();
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::S), repeat: false, .. } => {
+                if let Some(profiler) = &cpu.profiler {
+                    let path = screenshot::next_numbered_path("profile", "folded");
+                    self.status = match profiler.save_folded(&debugger.symbols, cpu.bus, &path) {
+                        Ok(()) => format!("Saved {}", path),
+                        Err(e) => e,
+                    };
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, cpu: &mut CPU, debugger: &Debugger, font: &mut Font) {
+        let white = Color::RGB(255, 255, 255);
+
+        self.window.canvas.set_draw_color(Color::RGB(24, 24, 24));
+        self.window.canvas.clear();
+
+        let lines = match &cpu.profiler {
+            Some(profiler) => profiler.report(&debugger.symbols, cpu.bus),
+            None => vec!["Not profiling".to_string()],
+        };
+        for (i, line) in lines.iter().enumerate() {
+            self.window.draw_text(font, line, white, 4, i as i32 * LINE_HEIGHT);
+        }
+        self.window.draw_text(font, &self.status, Color::RGB(160, 160, 160), 4, WINDOW_HEIGHT as i32 - LINE_HEIGHT - 4);
+
+        self.window.canvas.present();
+    }
+}
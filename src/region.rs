@@ -0,0 +1,64 @@
+// Console timing variant. Dendy is the PAL-area Famicom clone: PAL frame rate
+// and scanline count, but NTSC-like CPU:PPU ratio and APU tables.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // Master clock divided by 12 (NTSC), 16 (PAL) or 15 (Dendy)
+    pub fn cpu_clock(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    // Scanlines per frame, counting the pre-render line
+    pub fn scanlines(&self) -> i16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Dendy keeps vblank 20 lines long and pads the frame with post-render lines instead
+    pub fn vblank_scanline(&self) -> i16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // PPU dots per CPU cycle, in fifths: 3 for NTSC and Dendy, 3.2 for PAL
+    pub fn ppu_dots_per_cycle_x5(&self) -> u32 {
+        match self {
+            Region::Ntsc | Region::Dendy => 15,
+            Region::Pal => 16,
+        }
+    }
+
+    // Whole CPU cycles in one frame (341 dots per scanline)
+    pub fn cycles_per_frame(&self) -> usize {
+        341 * self.scanlines() as usize * 5 / self.ppu_dots_per_cycle_x5() as usize
+    }
+}
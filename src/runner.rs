@@ -0,0 +1,133 @@
+use std::fs;
+
+use crate::cpu::CPU;
+use crate::png;
+use crate::screenshot::{frame_to_rgb, indexed_dump, ScreenshotOptions};
+
+// Runs one instruction, then the interrupt it may have triggered, ticking the
+// PPU and APU for every cycle. Returns the CPU cycles taken.
+pub fn step_instruction(cpu: &mut CPU) -> usize {
+    let mut profiler = cpu.profiler.take();
+    if let Some(profiler) = profiler.as_mut() {
+        profiler.begin_instruction(cpu);
+    }
+
+    // 1. Executa 1 instrução da CPU
+    let cycles = cpu.step();
+
+    // 2. A PPU roda 3 vezes para cada 1 ciclo de CPU (3.2 no PAL).
+    // In cycle-stepped mode the CPU already ticked it on every bus access.
+    if !cpu.cycle_stepped {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    let interrupt_cycles = cpu.service_interrupts() as usize;
+    if !cpu.cycle_stepped {
+        for _ in 0..interrupt_cycles {
+            cpu.clock();
+        }
+    }
+
+    if let Some(profiler) = profiler.as_mut() {
+        profiler.end_instruction(cycles + interrupt_cycles);
+    }
+    cpu.profiler = profiler;
+
+    cycles + interrupt_cycles
+}
+
+// Runs one frame worth of CPU cycles and renders the PPU frame buffer.
+pub fn run_frame(cpu: &mut CPU) {
+    let cycles_per_frame = cpu.bus.region.cycles_per_frame();
+    let mut cycles_this_frame = 0;
+    while cycles_this_frame < cycles_per_frame {
+        cycles_this_frame += step_instruction(cpu);
+    }
+
+    cpu.bus.ppu.render_frame(&cpu.bus.rom, cpu.bus.cdl.as_mut());
+}
+
+// Runs `frames` frames without a display. `inputs[n]` holds the buttons of both
+// controllers for frame n; frames past the end of the script have nothing pressed.
+pub fn run_frames(cpu: &mut CPU, frames: usize, inputs: &[[u8; 2]]) -> Vec<u16> {
+    for frame in 0..frames {
+        let buttons = inputs.get(frame).copied().unwrap_or([0, 0]);
+        cpu.bus.controller[0].buttons = buttons[0];
+        cpu.bus.controller[1].buttons = buttons[1];
+
+        run_frame(cpu);
+    }
+
+    cpu.bus.ppu.frame_buffer.clone()
+}
+
+// FNV-1a over the frame buffer, stable across platforms.
+pub fn frame_hash(frame: &[u16]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for pixel in frame.iter() {
+        for byte in pixel.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+    hash
+}
+
+// Set to 1 to write golden files from the frames instead of comparing
+const BLESS_VAR: &str = "BLESS_GOLDEN";
+
+// Compares a frame against a golden file, either a PNG image (".png") or a raw
+// indexed dump. A missing golden file is an error unless BLESS_GOLDEN=1, which
+// (re)writes golden files from the frames. On mismatch the actual frame is
+// written next to the golden file for inspection.
+pub fn compare_golden(frame: &[u16], golden_path: &str) -> Result<(), String> {
+    let png = golden_path.ends_with(".png");
+    let (actual, pixels) = if png {
+        let (width, height, rgb) = frame_to_rgb(frame, &ScreenshotOptions::new());
+        (png::encode_rgb(width, height, &rgb), rgb)
+    } else {
+        let dump = indexed_dump(frame);
+        (dump.clone(), dump)
+    };
+
+    if std::env::var(BLESS_VAR).is_ok_and(|value| value == "1") {
+        fs::write(golden_path, &actual).map_err(|e| e.to_string())?;
+        println!("Blessed golden frame {}", golden_path);
+        return Ok(());
+    }
+
+    let expected = fs::read(golden_path)
+        .map_err(|e| format!("Golden frame {}: {} (run with {}=1 to create it)", golden_path, e, BLESS_VAR))?;
+
+    if expected == actual {
+        return Ok(());
+    }
+
+    let actual_path = match golden_path.strip_suffix(".png") {
+        Some(stem) => format!("{}.actual.png", stem),
+        None => format!("{}.actual", golden_path),
+    };
+    fs::write(&actual_path, &actual).map_err(|e| e.to_string())?;
+
+    // Golden PNGs written by another encoder cannot be read back
+    let expected_pixels = if png { png::decode_rgb(&expected).map(|(_, _, rgb)| rgb) } else { Some(expected) };
+    let pixel_size = if png { 3 } else { 2 };
+    let difference = match expected_pixels {
+        Some(expected) if expected.len() == pixels.len() => {
+            let differing_pixels = expected.chunks(pixel_size)
+                .zip(pixels.chunks(pixel_size))
+                .filter(|(a, b)| a != b)
+                .count();
+            format!("in {} pixels", differing_pixels)
+        }
+        Some(_) => "in size".to_string(),
+        None => "(unreadable golden PNG)".to_string(),
+    };
+
+    Err(format!(
+        "Frame differs from {} {} (hash {:016X}), actual frame written to {}",
+        golden_path, difference, frame_hash(frame), actual_path
+    ))
+}
@@ -0,0 +1,158 @@
+use crate::cpu::CPU;
+
+const STATE_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x53]; // "NESS"
+const STATE_VERSION: u8 = 10;
+
+pub struct StateWriter {
+    pub data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        let mut byte = [0u8; 1];
+        self.read_bytes(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let mut bytes = [0u8; 2];
+        self.read_bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let end = self.pos + out.len();
+        if end > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+        out.copy_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        Ok(())
+    }
+}
+
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.write_bytes(&STATE_TAG);
+    writer.write_u8(STATE_VERSION);
+
+    cpu.save_state(&mut writer);
+    cpu.bus.save_state(&mut writer);
+
+    writer.data
+}
+
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<(), String> {
+    let mut reader = StateReader::new(data);
+
+    let mut tag = [0u8; 4];
+    reader.read_bytes(&mut tag)?;
+    if tag != STATE_TAG {
+        return Err("Unsupported save state format".to_string());
+    }
+
+    let version = reader.read_u8()?;
+    if version != STATE_VERSION {
+        return Err(format!("Unsupported save state version: {}", version));
+    }
+
+    cpu.load_state(&mut reader)?;
+    cpu.bus.load_state(&mut reader)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::ines_file::test_rom;
+    use crate::region::Region;
+    use crate::runner;
+
+    // INC $10; JMP $8000
+    const PROGRAM: [u8; 5] = [0xE6, 0x10, 0x4C, 0x00, 0x80];
+
+    #[test]
+    fn load_restores_the_saved_machine() {
+        let mut bus = Bus::new(test_rom(&PROGRAM, &[0x40]), Region::Ntsc);
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        runner::run_frames(&mut cpu, 2, &[]);
+
+        let state = save(&cpu);
+        let (pc, cycles, counter) = (cpu.registers.pc, cpu.cycles, cpu.bus.ram[0x10]);
+
+        runner::run_frames(&mut cpu, 3, &[]);
+        assert_ne!(cpu.bus.ram[0x10], counter);
+
+        load(&mut cpu, &state).unwrap();
+        assert_eq!(cpu.registers.pc, pc);
+        assert_eq!(cpu.cycles, cycles);
+        assert_eq!(cpu.bus.ram[0x10], counter);
+        assert_eq!(save(&cpu), state);
+    }
+
+    #[test]
+    fn rejects_foreign_and_truncated_states() {
+        let mut bus = Bus::new(test_rom(&PROGRAM, &[0x40]), Region::Ntsc);
+        let mut cpu = CPU::new(&mut bus);
+        cpu.reset();
+        let state = save(&cpu);
+
+        let mut foreign = state.clone();
+        foreign[0] = b'X';
+        assert!(load(&mut cpu, &foreign).is_err());
+
+        let mut newer = state.clone();
+        newer[4] = STATE_VERSION + 1;
+        assert!(load(&mut cpu, &newer).is_err());
+
+        assert!(load(&mut cpu, &state[..state.len() / 2]).is_err());
+    }
+}
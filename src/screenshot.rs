@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::Path;
+
+use crate::png;
+use crate::ppu::{pixel_color, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Scanlines hidden by most NTSC televisions at the top and bottom of the picture
+pub const OVERSCAN_LINES: usize = 8;
+
+pub struct ScreenshotOptions {
+    pub crop_overscan: bool,
+    pub scale: usize,
+}
+
+impl ScreenshotOptions {
+    pub fn new() -> Self {
+        ScreenshotOptions {
+            crop_overscan: false,
+            scale: 1,
+        }
+    }
+}
+
+// Converts the frame buffer into packed RGB, returning (width, height, pixels).
+pub fn frame_to_rgb(frame: &[u16], options: &ScreenshotOptions) -> (usize, usize, Vec<u8>) {
+    let scale = options.scale.max(1);
+    let (first_line, last_line) = if options.crop_overscan {
+        (OVERSCAN_LINES, SCREEN_HEIGHT - OVERSCAN_LINES)
+    } else {
+        (0, SCREEN_HEIGHT)
+    };
+
+    let width = SCREEN_WIDTH * scale;
+    let height = (last_line - first_line) * scale;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in first_line..last_line {
+        let mut row = Vec::with_capacity(width * 3);
+        for x in 0..SCREEN_WIDTH {
+            let color = pixel_color(frame[y * SCREEN_WIDTH + x]);
+            for _ in 0..scale {
+                row.extend_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+        for _ in 0..scale {
+            rgb.extend_from_slice(&row);
+        }
+    }
+
+    (width, height, rgb)
+}
+
+pub fn save_png(frame: &[u16], path: &str, options: &ScreenshotOptions) -> Result<(), String> {
+    let (width, height, rgb) = frame_to_rgb(frame, options);
+    fs::write(path, png::encode_rgb(width, height, &rgb)).map_err(|e| e.to_string())
+}
+
+// Raw 256x240 dump, one little-endian u16 per pixel: bits 0-5 hold the NES
+// colour index and bits 6-8 the emphasis bits, so frames compare exactly.
+pub fn indexed_dump(frame: &[u16]) -> Vec<u8> {
+    frame.iter().flat_map(|pixel| pixel.to_le_bytes()).collect()
+}
+
+pub fn save_indexed(frame: &[u16], path: &str) -> Result<(), String> {
+    fs::write(path, indexed_dump(frame)).map_err(|e| e.to_string())
+}
+
+// First "<prefix>_NNN.png" in the working directory that does not exist yet.
+pub fn next_screenshot_path(prefix: &str) -> String {
+    next_numbered_path(prefix, "png")
+}
+
+// First "<prefix>_NNN.<extension>" in the working directory that does not exist yet.
+pub fn next_numbered_path(prefix: &str, extension: &str) -> String {
+    let mut index = 0;
+    loop {
+        let path = format!("{}_{:03}.{}", prefix, index, extension);
+        if !Path::new(&path).exists() {
+            return path;
+        }
+        index += 1;
+    }
+}
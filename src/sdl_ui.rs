@@ -1,21 +1,116 @@
 use sdl2::pixels::{Color, PixelFormatEnum};
-use sdl2::event::Event;
-use sdl2::render::{Canvas, Texture, TextureCreator};
-use sdl2::rect::Rect;
-use sdl2::ttf::Font;
-use sdl2::video::Window;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::render::Texture;
 use sdl2::keyboard::Keycode;
+use sdl2::controller::{self, Axis, GameController};
+use sdl2::{GameControllerSubsystem, VideoSubsystem};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::time::{Duration, Instant};
 use std::thread;
 
 use crate::cpu::CPU;
-use crate::ppu::{PPU, get_color_from_palette};
-use crate::ines_file::Rom;
-use crate::controller::Button;
+use crate::debug_window::DebugView;
+use crate::debugger::Debugger;
+use crate::debugger_viewer::DebuggerViewer;
+use crate::nametable_viewer::NametableViewer;
+use crate::pattern_viewer::PatternViewer;
+use crate::oam_viewer::OamViewer;
+use crate::memory_viewer::MemoryViewer;
+use crate::call_stack::CallStack;
+use crate::call_stack_viewer::CallStackViewer;
+use crate::profiler::Profiler;
+use crate::profiler_viewer::ProfilerViewer;
+use crate::event_log::EventLog;
+use crate::event_viewer::EventViewer;
+use crate::ppu::{PPU, pixel_color, SCREEN_WIDTH, SCREEN_HEIGHT};
+use crate::input::{Action, Device, InputConfig, InputState};
+use crate::savestate;
+use crate::apu::APU;
+use crate::audio::AudioResampler;
+use crate::pacing::{FramePacer, SyncMode};
+use crate::movie::{command, MovieMode, MovieSession};
+use crate::screenshot;
+use crate::Options;
+
+
+// Front-end state that lives outside the emulated machine.
+struct UiState {
+    movie: Option<MovieSession>,
+    input: InputState,
+    key_bindings: HashMap<Keycode, Action>,
+    pad_bindings: HashMap<controller::Button, Action>,
+    pads: HashMap<u32, (GameController, usize)>, // joystick id -> (pad, port)
+    pad_analog: bool,
+    pad_dead_zone: i16,
+    controller_subsystem: GameControllerSubsystem,
+    debugger: Debugger,
+    fast_forward: bool,
+    slow_motion: bool,
+    state_path: String,
+    state_movie_frame: Option<usize>, // Movie frame the state file was saved at, if saved during the movie
+    video: VideoSubsystem,
+    main_window_id: u32,
+    viewers: Vec<(Action, Box<dyn DebugView>)>, // Open debug windows, keyed by the hotkey that toggles them
+}
+
+// Audio kept around this many frames ahead of playback
+const AUDIO_LATENCY_FRAMES: usize = 3;
+
+struct AudioOutput {
+    queue: AudioQueue<f32>,
+    resampler: AudioResampler,
+    target_samples: usize,
+}
+
+fn open_audio(sdl_context: &sdl2::Sdl, sample_rate: u32, cpu_clock: f64, frame_rate: f64) -> Result<AudioOutput, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let desired = AudioSpecDesired {
+        freq: Some(sample_rate as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+
+    let queue: AudioQueue<f32> = audio_subsystem.open_queue(None, &desired)?;
+    let freq = queue.spec().freq as f64;
+    queue.resume();
+
+    Ok(AudioOutput {
+        queue,
+        resampler: AudioResampler::new(cpu_clock, freq),
+        target_samples: (freq / frame_rate) as usize * AUDIO_LATENCY_FRAMES,
+    })
+}
+
+// Audio-clock pacing: the next frame runs once the device has played enough
+fn wait_for_audio(audio: &AudioOutput) {
+    let deadline = Instant::now() + Duration::from_millis(100);
+    while (audio.queue.size() as usize / std::mem::size_of::<f32>()) > audio.target_samples
+        && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// Moves the samples of the last frame from the APU to the audio device
+fn queue_audio(audio: &mut AudioOutput, apu: &mut APU) {
+    audio.resampler.process(&apu.samples);
+    apu.samples.clear();
 
+    let queued = audio.queue.size() as usize / std::mem::size_of::<f32>();
+    audio.resampler.update_rate(queued, audio.target_samples);
+
+    // Drop audio rather than build up latency when emulation outruns playback
+    if queued < audio.target_samples * 4
+        && let Err(e) = audio.queue.queue_audio(&audio.resampler.output) {
+        println!("Failed to queue audio: {}", e);
+    }
+    audio.resampler.output.clear();
+}
 
-pub fn start_ui(mut cpu: CPU) {
+pub fn start_ui(mut cpu: CPU, movie: Option<MovieSession>, options: &Options) {
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -26,7 +121,11 @@ pub fn start_ui(mut cpu: CPU) {
         .build()
         .unwrap();
 
-    let mut canvas = window.into_canvas().build().unwrap();
+    let mut canvas_builder = window.into_canvas();
+    if options.sync_mode == SyncMode::Vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().unwrap();
 
     let ttf_context = sdl2::ttf::init().unwrap();
 
@@ -39,57 +138,118 @@ pub fn start_ui(mut cpu: CPU) {
     ).unwrap();
 
     let font_path = "/usr/share/fonts/TTF/FiraCode-Medium.ttf";
-    let mut font = ttf_context.load_font(font_path, 20).unwrap();
+    let mut font = ttf_context.load_font(font_path, 14).unwrap();
     
     let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let mut audio = None;
+    if options.audio {
+        match open_audio(&sdl_context, options.sample_rate, cpu.bus.region.cpu_clock(), cpu.bus.region.frame_rate()) {
+            Ok(output) => {
+                cpu.bus.apu.output_enabled = true;
+                audio = Some(output);
+            }
+            Err(e) => println!("Audio disabled: {}", e),
+        }
+    }
+
+    let input_config = InputConfig::load(&options.keys_path).unwrap_or_else(|e| {
+        println!("{}: {}, using the default bindings", options.keys_path, e);
+        InputConfig::default_config()
+    });
+
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    if let Some(path) = &input_config.pad_mapping_db
+        && let Err(e) = controller_subsystem.load_mappings(path) {
+        println!("Failed to load gamepad mappings from {}: {}", path, e);
+    }
+
+    let mut ui = UiState {
+        movie,
+        input: InputState::new(input_config.turbo_rate),
+        key_bindings: build_key_bindings(&input_config),
+        pad_bindings: build_pad_bindings(&input_config),
+        pads: HashMap::new(),
+        pad_analog: input_config.pad_analog,
+        pad_dead_zone: input_config.pad_dead_zone,
+        controller_subsystem,
+        debugger: Debugger::new(),
+        fast_forward: false,
+        slow_motion: false,
+        state_path: Path::new(&options.rom_path).with_extension("state").to_string_lossy().to_string(),
+        state_movie_frame: None,
+        video: video_subsystem.clone(),
+        main_window_id: canvas.window().id(),
+        viewers: Vec::new(),
+    };
     
-    let target_duration = Duration::from_micros(16_667);
+    ui.debugger.symbols = crate::load_symbols(options, cpu.bus);
+
+    if options.sync_mode == SyncMode::Audio && audio.is_none() {
+        println!("No audio output to sync to, pacing frames with the timer");
+    }
+    let mut pacer = FramePacer::new(options.sync_mode, cpu.bus.region.frame_rate(), audio.is_some());
+    match canvas.window().display_mode() {
+        Ok(mode) if mode.refresh_rate > 0 => pacer.display_rate = mode.refresh_rate as f64,
+        _ => println!("Display refresh rate unknown, assuming {} Hz", pacer.display_rate),
+    }
 
     let mut frame_count = 0;
     let mut last_fps_check = Instant::now();
     'running: loop {
-        let frame_start = Instant::now();
-        
-        if !handle_input(&mut event_pump, &mut cpu) {
+        if !handle_input(&mut event_pump, &mut cpu, &mut ui, options) {
             break 'running;
         }
 
-        let mut cycles_this_frame = 0;
-        while cycles_this_frame < 29780 {
-            // 1. Executa 1 instrução da CPU
-            let cycles = cpu.step() as usize; 
-            cycles_this_frame += cycles;
+        pacer.speed = if ui.fast_forward {
+            options.fast_forward_speed
+        } else if ui.slow_motion {
+            options.slow_motion_speed
+        } else {
+            1.0
+        };
 
-            // 2. A PPU roda 3 vezes para cada 1 ciclo de CPU
-            for _ in 0..(cycles * 3) {
-                 cpu.bus.ppu.step();
-            }
+        if pacer.audio_paced() && let Some(output) = audio.as_ref() {
+            wait_for_audio(output);
+        }
 
-            if cpu.bus.ppu.emitted_nmi {
-                cpu.nmi();
-                cpu.bus.ppu.emitted_nmi = false;
-                if !handle_input(&mut event_pump, &mut cpu) {
-                    break 'running;
+        let frames = if ui.debugger.paused { 0 } else { pacer.frames_to_run() };
+        for _ in 0..frames {
+            if ui.debugger.at_frame_start() {
+                ui.input.apply(&mut cpu.bus.controller);
+
+                // Movie input replaces the live input once per frame so playback is deterministic
+                if let Some(session) = ui.movie.as_mut() {
+                    session.update(&mut cpu);
                 }
             }
 
+            let complete = ui.debugger.run_frame(&mut cpu);
+
+            match audio.as_mut() {
+                Some(output) if pacer.speed == 1.0 => queue_audio(output, &mut cpu.bus.apu),
+                _ => cpu.bus.apu.samples.clear(),
+            }
+
+            if !complete {
+                break;
+            }
         }
         
-        // render_debug_info(&mut canvas, &mut font, &texture_creator, &mut cpu);
-
-        //render_pattern_table(&mut canvas, &texture_creator, &cpu.bus.ppu, &cpu.bus.rom, 0, 350, 300);
-        //render_pattern_table(&mut canvas, &texture_creator, &cpu.bus.ppu, &cpu.bus.rom, 1, 350, 600);
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
 
-        render_nametable(&mut canvas, &mut screen_texture, &cpu.bus.ppu, &cpu.bus.rom);
-        render_sprites(&mut screen_texture, &cpu.bus.ppu, &cpu.bus.rom);
+        render_screen(&mut screen_texture, &cpu.bus.ppu);
 
         canvas.copy(&screen_texture, None, None).unwrap();
 
         canvas.present();
 
-        frame_count += 1;
+        for (_, viewer) in ui.viewers.iter_mut() {
+            viewer.render(&mut cpu, &ui.debugger, &mut font);
+        }
+
+        frame_count += frames;
 
         if last_fps_check.elapsed() >= Duration::new(1, 0) {
             let fps = frame_count;
@@ -101,68 +261,130 @@ pub fn start_ui(mut cpu: CPU) {
             last_fps_check = Instant::now();
         }
 
-        let elapsed = frame_start.elapsed();
-        if elapsed < target_duration {
+        pacer.wait();
+    }
+
+    if let Some(session) = ui.movie.as_mut()
+        && let Err(e) = session.finish() {
+        println!("Failed to save movie: {}", e);
+    }
+
+    crate::save_cdl(cpu.bus, options);
+    crate::save_profile(&cpu, &ui.debugger.symbols, options);
+}
+
+fn build_key_bindings(config: &InputConfig) -> HashMap<Keycode, Action> {
+    let mut bindings = HashMap::new();
+    for (name, action) in config.keys.iter() {
+        match Keycode::from_name(name) {
+            Some(key) => {
+                bindings.insert(key, *action);
+            }
+            None => println!("Unknown key name in key map: {}", name),
         }
     }
+    bindings
 }
 
-fn handle_input(event_pump: &mut sdl2::EventPump, cpu: &mut CPU) -> bool {
+fn build_pad_bindings(config: &InputConfig) -> HashMap<controller::Button, Action> {
+    let mut bindings = HashMap::new();
+    for (name, action) in config.pad_buttons.iter() {
+        match controller::Button::from_string(name) {
+            Some(button) => {
+                bindings.insert(button, *action);
+            }
+            None => println!("Unknown gamepad button name in key map: {}", name),
+        }
+    }
+    bindings
+}
+
+// Hot-plugged pads take the first free controller port.
+fn connect_pad(ui: &mut UiState, joystick_index: u32) {
+    let port = (0..2).find(|port| !ui.pads.values().any(|(_, used)| used == port));
+    let Some(port) = port else {
+        return;
+    };
+
+    match ui.controller_subsystem.open(joystick_index) {
+        Ok(pad) => {
+            println!("Gamepad connected on port {}: {}", port + 1, pad.name());
+            ui.pads.insert(pad.instance_id(), (pad, port));
+        }
+        Err(e) => println!("Failed to open gamepad {}: {}", joystick_index, e),
+    }
+}
+
+fn disconnect_pad(ui: &mut UiState, joystick_id: u32) {
+    if let Some((pad, port)) = ui.pads.remove(&joystick_id) {
+        println!("Gamepad disconnected from port {}: {}", port + 1, pad.name());
+        ui.input.release_pad(port);
+    }
+}
+
+fn handle_input(event_pump: &mut sdl2::EventPump, cpu: &mut CPU, ui: &mut UiState, options: &Options) -> bool {
     for event in event_pump.poll_iter() {
+        // Events of the debug windows go to their view
+        if let Some(window_id) = event.get_window_id()
+            && window_id != ui.main_window_id {
+            if let Event::Window { win_event: WindowEvent::Close, .. } = event {
+                ui.viewers.retain(|(_, viewer)| viewer.window_id() != window_id);
+                track_debug_state(cpu, ui, options);
+            } else if let Some((_, viewer)) = ui.viewers.iter_mut().find(|(_, viewer)| viewer.window_id() == window_id) {
+                viewer.handle_event(&event, cpu, &mut ui.debugger);
+            }
+            continue;
+        }
+
         match event {
             Event::Quit {..} => {
                 return false
             }
 
-            Event::KeyDown {
-                keycode: Some(Keycode::Space),
-                ..
-            } => {
-                cpu.step();
-                for _ in 0..3 {
-                    cpu.bus.ppu.step();
+            // SDL only sends Quit once every window is closed
+            Event::Window { win_event: WindowEvent::Close, .. } => {
+                return false
+            }
+
+            Event::KeyDown { keycode: Some(key), repeat: false, ..} => {
+                if let Some(action) = ui.key_bindings.get(&key).copied()
+                    && !ui.input.set_action(action, true, Device::Keyboard) {
+                    handle_hotkey(action, cpu, ui, options);
                 }
             }
 
-            Event::KeyDown {
-                keycode: Some(Keycode::R),
-                ..
-            } => {
-                cpu.reset();
-                println!("Reset!");
+            Event::KeyUp { keycode: Some(key), ..} => {
+                if let Some(action) = ui.key_bindings.get(&key).copied()
+                    && !ui.input.set_action(action, false, Device::Keyboard) {
+                    handle_hotkey_release(action, ui);
+                }
             }
 
-            Event::KeyDown {
-                keycode: Some(Keycode::N),
-                ..
-            } => {
-                cpu.nmi();
-                println!("NMI!")
+            Event::ControllerDeviceAdded { which, .. } => connect_pad(ui, which),
+
+            Event::ControllerDeviceRemoved { which, .. } => disconnect_pad(ui, which),
+
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let Some((_, port)) = ui.pads.get(&which)
+                    && let Some(action) = ui.pad_bindings.get(&button).map(|a| a.with_port(*port))
+                    && !ui.input.set_action(action, true, Device::Gamepad) {
+                    handle_hotkey(action, cpu, ui, options);
+                }
             }
 
-            Event::KeyDown { keycode: Some(key), repeat, ..} => {
-                match key  {
-                    Keycode::Up => cpu.bus.controller[0].set_button(Button::UP, true),
-                    Keycode::Down => cpu.bus.controller[0].set_button(Button::DOWN, true),
-                    Keycode::Return => cpu.bus.controller[0].set_button(Button::START, true),
-                    Keycode::Right => cpu.bus.controller[0].set_button(Button::RIGHT, true),
-                    Keycode::Left => cpu.bus.controller[0].set_button(Button::LEFT, true),
-                    Keycode::Z => cpu.bus.controller[0].set_button(Button::A, true),
-                    Keycode::X => cpu.bus.controller[0].set_button(Button::B, true),
-                    _ => {}
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some((_, port)) = ui.pads.get(&which)
+                    && let Some(action) = ui.pad_bindings.get(&button).map(|a| a.with_port(*port))
+                    && !ui.input.set_action(action, false, Device::Gamepad) {
+                    handle_hotkey_release(action, ui);
                 }
             }
 
-            Event::KeyUp { keycode: Some(key), repeat, ..} => {
-                match key  {
-                    Keycode::Up => cpu.bus.controller[0].set_button(Button::UP, false),
-                    Keycode::Down => cpu.bus.controller[0].set_button(Button::DOWN, false),
-                    Keycode::Return => cpu.bus.controller[0].set_button(Button::START, false),
-                    Keycode::Right => cpu.bus.controller[0].set_button(Button::RIGHT, false),
-                    Keycode::Left => cpu.bus.controller[0].set_button(Button::LEFT, false),
-                    Keycode::Z => cpu.bus.controller[0].set_button(Button::A, false),
-                    Keycode::X => cpu.bus.controller[0].set_button(Button::B, false),
-                    _ => {}
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                if ui.pad_analog
+                    && let Some((_, port)) = ui.pads.get(&which)
+                    && (axis == Axis::LeftX || axis == Axis::LeftY) {
+                    ui.input.set_axis(*port, axis == Axis::LeftY, value, ui.pad_dead_zone);
                 }
             }
 
@@ -172,290 +394,180 @@ fn handle_input(event_pump: &mut sdl2::EventPump, cpu: &mut CPU) -> bool {
     return true
 }
 
-fn draw_text<'a>(text: String, color: Color, position: Vec<i32>, canvas: &mut Canvas<Window>, font: &mut Font, texture_creator: &'a TextureCreator<sdl2::video::WindowContext>) {
-    let surface = font.render(&text)
-        .blended(color)
-        .unwrap();
-    let texture = texture_creator
-        .create_texture_from_surface(&surface)
-        .unwrap();
-    let target_rect = Rect::new(
-        position[0].into(),
-        position[1].into(),
-        texture.query().width,
-        texture.query().height,
-    );
-    canvas.copy(&texture, None, Some(target_rect)).unwrap();
-}
+fn handle_hotkey(action: Action, cpu: &mut CPU, ui: &mut UiState, options: &Options) {
+    match action {
+        Action::Step => {
+            ui.debugger.step_into(cpu);
+        }
 
-fn render_debug_info<'a>(
-    canvas: &mut Canvas<Window>,
-    font: &mut Font,
-    texture_creator: &'a TextureCreator<sdl2::video::WindowContext>,
-    cpu: &mut CPU,
-    ) {
-        let font_height = font.height();
-
-        for i in 0x00..0x0F + 1 {
-            let mut text = format!("${:04X}: ", (i as u16) << 4);
-            for j in 0x00..0x0F + 1 {
-                text.push_str(&format!("{:02X} ", cpu.bus.read((i as u16) << 4 | j as u16)));
-            }
-            let surface = font.render(&text)
-            .blended(Color::RGB(255, 255, 255))
-            .unwrap();
-
-            let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .unwrap();
-
-            let target_rect = Rect::new(
-                10,
-                10 + i * texture.query().height as i32,
-                texture.query().width,
-                texture.query().height,
-            );
-            canvas.copy(&texture, None, Some(target_rect)).unwrap();
-        } 
-        
-        for i in 0x00..0x0F + 1 {
-            let mut text = format!("${:04X}: ", 0x8000 | (i as u16) << 4);
-            for j in 0x00..0x0F + 1 {
-                text.push_str(&format!("{:02X} ", cpu.bus.read(0x8000 | (i as u16) << 4 | j as u16)));
+        Action::Reset => {
+            cpu.reset();
+            if let Some(session) = ui.movie.as_mut() {
+                session.record_command(command::SOFT_RESET);
             }
-            let surface = font.render(&text)
-            .blended(Color::RGB(255, 255, 255))
-            .unwrap();
-
-            let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .unwrap();
-
-            let target_rect = Rect::new(
-                10,
-                420 + i * texture.query().height as i32,
-                texture.query().width,
-                texture.query().height,
-            );
-            canvas.copy(&texture, None, Some(target_rect)).unwrap();
+            println!("Reset!");
         }
 
-        draw_text(format!("Status: 0b{:08b} [0x{:02X}]", cpu.registers.f, cpu.registers.f), Color::RGB(255, 255, 255), vec![700, 10],
-        canvas, font, texture_creator
-        );
-        draw_text(format!("PC: ${:04X}", cpu.registers.pc), Color::RGB(255, 255, 255), vec![700, 10 + font_height],
-        canvas, font, texture_creator
-        );
-        draw_text(format!("A: ${:02X} [{}]", cpu.registers.a, cpu.registers.a), Color::RGB(255, 255, 255), vec![700, 10 + font_height * 2],
-        canvas, font, texture_creator
-        );
-        draw_text(format!("X: ${:02X} [{}]", cpu.registers.x, cpu.registers.x), Color::RGB(255, 255, 255), vec![700, 10 + font_height * 3],
-        canvas, font, texture_creator
-        );
-        draw_text(format!("Y: ${:02X} [{}]", cpu.registers.y, cpu.registers.y), Color::RGB(255, 255, 255), vec![700, 10 + font_height * 4],
-        canvas, font, texture_creator
-        );
-        draw_text(format!("Stack Pointer: $00{:02X}", cpu.registers.sp), Color::RGB(255, 255, 255), vec![700, 10 + font_height * 5],
-        canvas, font, texture_creator
-        );
-
-        draw_text(format!("SPACE: Step Instruction | R: RESET"), Color::RGB(255, 255, 255), vec![10, 850],
-        canvas, font, texture_creator
-        );
-        
-    /*
-        let surface = font.render(&debug_text)
-        .blended(Color::RGB(255, 255, 255))
-        .unwrap();
+        Action::Pause => {
+            if ui.debugger.paused {
+                ui.debugger.resume();
+                println!("Resumed");
+            } else {
+                ui.debugger.pause("Paused");
+                println!("Paused");
+            }
+        }
 
-        let texture = texture_creator
-        .create_texture_from_surface(&surface)
-        .unwrap();
+        Action::SaveState => {
+            match fs::write(&ui.state_path, savestate::save(cpu)) {
+                Ok(()) => {
+                    ui.state_movie_frame = ui.movie.as_ref().map(|session| session.frame);
+                    println!("State saved to {}", ui.state_path);
+                }
+                Err(e) => println!("Failed to save state: {}", e),
+            }
+        }
 
-        let target_rect = Rect::new(
-            10,
-            10,
-            texture.query().width,
-            texture.query().height,
-        );
-        
-        canvas.copy(&texture, None, Some(target_rect)).unwrap();
-    */
-}
+        Action::LoadState => {
+            // A running movie has to follow the emulator back to the state's frame
+            let movie = ui.movie.as_mut().filter(|session| session.mode != MovieMode::Finished);
+            if movie.is_some() && ui.state_movie_frame.is_none() {
+                println!("Not loading {}: it was not saved during this movie, which would desync", ui.state_path);
+                return;
+            }
 
-fn render_pattern_table<'a>(
-    canvas: &mut Canvas<Window>,
-    texture_creator: &'a TextureCreator<sdl2::video::WindowContext>,
-    ppu: &PPU,
-    rom: &Rom,
-    table_idx: u8,
-    offset_x: i32,
-    offset_y: i32,
-) {
-    let pattern_table_data = ppu.get_pattern_table(rom, table_idx, 0);
-
-    if let Ok(mut texture) = texture_creator.create_texture_streaming(
-        sdl2::pixels::PixelFormatEnum::ARGB8888,
-        128, 
-        128,
-    ) {
-        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in 0..128 {
-                for x in 0..128 {
-                    let color = pattern_table_data[(y * 128 + x) as usize];
-
-                    let offset = y * pitch + x * 4;
-
-                    buffer[offset] = color.b;
-                    buffer[offset + 1] = color.g;
-                    buffer[offset + 2] = color.r;
-                    buffer[offset + 3] = 0xFF;
+            let result = fs::read(&ui.state_path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| savestate::load(cpu, &data));
+            match result {
+                Ok(()) => {
+                    if let (Some(session), Some(frame)) = (movie, ui.state_movie_frame) {
+                        session.seek(frame);
+                    }
+                    println!("State loaded from {}", ui.state_path);
                 }
+                Err(e) => println!("Failed to load state: {}", e),
             }
-        }).unwrap();
+        }
 
-        let target_rect = Rect::new(
-            offset_x, offset_y,
-            256, 256,
-        );
+        Action::Screenshot => {
+            let path = screenshot::next_screenshot_path("screenshot");
+            match screenshot::save_png(&cpu.bus.ppu.frame_buffer, &path, &options.screenshot) {
+                Ok(()) => println!("Screenshot saved to {}", path),
+                Err(e) => println!("Failed to save screenshot: {}", e),
+            }
+        }
 
-        canvas.copy(&texture, None, Some(target_rect)).unwrap();
-    }
-}
+        Action::Nmi => {
+            cpu.nmi();
+            println!("NMI!")
+        }
 
-fn render_nametable (
-    canvas: &mut Canvas<Window>,
-    texture: &mut Texture,
-    ppu: &PPU,
-    rom: &Rom,
-    )   {
-        let bank = (ppu.control >> 4) & 1;
-        let mut palette_cache = [Color::RGB(0, 0, 0); 32];
-        for i in 0..32 {
-            let color_idx = ppu.ppu_read(0x3F00 + i as u16, rom);
-            palette_cache[i] = get_color_from_palette(color_idx);
+        Action::FastForward => {
+            ui.fast_forward = true;
         }
 
-        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in 0..30 {
-                for x in 0..32 {
-                    let tile_idx = ppu.ppu_read(0x2000 + (y * 32) + x, rom) as u16;
-
-                    let bank_offset = bank as u16 * 0x1000;
-                    let tile_start = bank_offset + (tile_idx * 16);
-
-                    let attr_addr = 0x23C0 + (y / 4) * 8 + (x / 4);
-                    let attr_byte = ppu.ppu_read(attr_addr, rom);
-
-                    let shift = ((y % 4) / 2 * 2 + (x % 4) / 2) * 2;
-                    let palette_idx = (attr_byte >> shift) & 0x03;
-                
-                    for row in 0..8 {
-                        let plane_0 = ppu.ppu_read(tile_start + row, rom);
-                        let plane_1 = ppu.ppu_read(tile_start + row + 8, rom);
-
-                        for col in 0..8 {
-                            let bit_0 = (plane_0 >> (7 - col)) & 1;
-                            let bit_1 = (plane_1 >> (7 - col)) & 1;
-                            let pixel_val = (bit_1 << 1) | bit_0;
-                            
-                            /*
-                            let (r, g, b) = match color_idx {
-                                0 => (0, 0, 0),
-                                1 => (100, 100, 100),
-                                2 => (170, 170, 170),
-                                3 => (255, 255, 255),
-                                _ => (0, 0, 0),
-                            };
-                            */
-                            
-                            let color = if pixel_val == 0 {
-                                palette_cache[0]
-                            } else {
-                                palette_cache[(palette_idx as usize * 4) + pixel_val as usize]
-                            };
-
-                            //let color_addr = 0x3F00 + (palette_idx as u16 * 4) + pixel_val as u16;
-
-                            // let color = get_color_from_palette(ppu.ppu_read(color_addr, rom));
-
-                            let screen_x = (x * 8 + (col as u16)) as usize;
-                            let screen_y = (y * 8 + (row as u16)) as usize;
-
-                            let offset = screen_y * pitch + screen_x * 4;
-                            buffer[offset] = color.b;
-                            buffer[offset + 1] = color.g;
-                            buffer[offset + 2] = color.r;
-                            buffer[offset + 3] = 255;
-                        }
-                    }
-                }
-            }
-        }).unwrap();
+        Action::SlowMotion => {
+            ui.slow_motion = !ui.slow_motion;
+            println!("Slow motion {}", if ui.slow_motion { "on" } else { "off" });
+        }
 
-        //canvas.copy(&texture, None, None).unwrap();
-  
-}
+        Action::NametableViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(NametableViewer::open(video)?)));
+        }
 
-fn render_sprites(
-    texture: &mut Texture,
-    ppu: &PPU,
-    rom: &Rom,
-) {
-    let oam_ptr = if (ppu.control & 0x08) != 0 {0x1000} else {0x000};
-    
-    let mut palette_cache = [Color::RGB(0, 0, 0); 16];
-    for i in 0..16 {
-        let color_idx = ppu.ppu_read(0x3F10 + i as u16, rom);
-        palette_cache[i] = get_color_from_palette(color_idx);
-    }
+        Action::PatternViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(PatternViewer::open(video)?)));
+        }
 
-    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-        for i in 0..64 {
-            let offset = i * 4;
+        Action::OamViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(OamViewer::open(video)?)));
+        }
 
-            let y = ppu.oam_data[offset] as i32;
-            let tile_idx = ppu.oam_data[offset + 1] as u16;
-            let attr = ppu.oam_data[offset + 2];
-            let x = ppu.oam_data[offset + 3] as i32;
+        Action::DebuggerViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(DebuggerViewer::open(video)?)));
+        }
 
-            let flip_h = (attr & 0x40) != 0;
-            let flip_v = (attr & 0x80) != 0;
-            let palette_idx = attr & 0x03;
+        Action::MemoryViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(MemoryViewer::open(video)?)));
+        }
 
-            let tile_start = oam_ptr + (tile_idx * 16);
-            
-            for row in 0..8 {
-                let sprite_row = if flip_v {7 - row} else {row};
+        Action::CallStackViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(CallStackViewer::open(video)?)));
+            track_debug_state(cpu, ui, options);
+        }
 
-                let plane_0 = ppu.ppu_read((tile_start + sprite_row) as u16, rom);
-                let plane_1 = ppu.ppu_read((tile_start + sprite_row + 8) as u16, rom);
+        Action::ProfilerViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(ProfilerViewer::open(video)?)));
+            track_debug_state(cpu, ui, options);
+        }
 
-                for col in 0..8 {
-                    let sprite_col = if flip_h {7 - col} else {col};
+        Action::EventViewer => {
+            toggle_viewer(ui, action, |video| Ok(Box::new(EventViewer::open(video)?)));
+            track_debug_state(cpu, ui, options);
+        }
 
-                    let bit_0 = (plane_0 >> (7 - sprite_col)) & 1;
-                    let bit_1 = (plane_1 >> (7 - sprite_col)) & 1;
-                    let pixel_val = (bit_1 << 1) | bit_0;
+        Action::Button(..) | Action::Turbo(..) => {}
+    }
+}
 
-                    if pixel_val == 0 {
-                        continue;
-                    }
+// The CPU only keeps its shadow call stack for the call stack window and the
+// profiler, which itself runs while its window is open or with --profile.
+// The bus logs register accesses for the event viewer only.
+fn track_debug_state(cpu: &mut CPU, ui: &UiState, options: &Options) {
+    let open = |wanted: Action| ui.viewers.iter().any(|(action, _)| *action == wanted);
+    let profiling = open(Action::ProfilerViewer) || options.profile_path.is_some();
+
+    if !profiling {
+        cpu.profiler = None;
+    } else if cpu.profiler.is_none() {
+        cpu.profiler = Some(Profiler::new());
+    }
+
+    if !profiling && !open(Action::CallStackViewer) {
+        cpu.call_stack = None;
+    } else if cpu.call_stack.is_none() {
+        cpu.call_stack = Some(CallStack::new());
+    }
 
-                    let screen_x = x + col as i32;
-                    let screen_y = y + row as i32;
+    if !open(Action::EventViewer) {
+        cpu.bus.events = None;
+    } else if cpu.bus.events.is_none() {
+        cpu.bus.events = Some(EventLog::new());
+    }
+}
 
-                    if screen_x >= 0 && screen_x < 256 && screen_y >= 0 && screen_y < 240 {
-                        let color = palette_cache[(palette_idx as usize * 4) + pixel_val as usize];
+fn toggle_viewer(ui: &mut UiState, action: Action, open: impl FnOnce(&VideoSubsystem) -> Result<Box<dyn DebugView>, String>) {
+    if let Some(index) = ui.viewers.iter().position(|(a, _)| *a == action) {
+        ui.viewers.remove(index);
+        return;
+    }
 
-                        let buffer_offset = (screen_y as usize * pitch) + (screen_x as usize * 4);
-                        buffer[buffer_offset] = color.b;
-                        buffer[buffer_offset + 1] = color.g;
-                        buffer[buffer_offset + 2] = color.r;
-                    } 
-                }
+    match open(&ui.video) {
+        Ok(viewer) => ui.viewers.push((action, viewer)),
+        Err(e) => println!("Failed to open debug window: {}", e),
+    }
+}
+
+// Fast-forward only lasts while its key is held
+fn handle_hotkey_release(action: Action, ui: &mut UiState) {
+    if action == Action::FastForward {
+        ui.fast_forward = false;
+    }
+}
+
+fn render_screen(texture: &mut Texture, ppu: &PPU) {
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = pixel_color(ppu.frame_buffer[y * SCREEN_WIDTH + x]);
+
+                let offset = y * pitch + x * 4;
+                buffer[offset] = color.b;
+                buffer[offset + 1] = color.g;
+                buffer[offset + 2] = color.r;
+                buffer[offset + 3] = 255;
             }
         }
     }).unwrap();
 }
-
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::expression::parse_number;
+
+// How far past a label an address is still shown relative to it
+const MAX_LABEL_OFFSET: u16 = 16;
+
+const INES_HEADER_SIZE: usize = 16;
+
+// Labels in banked PRG-ROM are kept by ROM offset so they follow bank switches
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolLocation {
+    Cpu(u16),
+    PrgRom(usize),
+}
+
+struct SourceFile {
+    name: String,
+    lines: Vec<String>, // Empty when the source could not be read
+}
+
+// Labels and source lines loaded from ca65 .dbg, FCEUX .nl and Mesen .mlb files
+pub struct SymbolTable {
+    labels: HashMap<SymbolLocation, String>,
+    addresses: HashMap<String, SymbolLocation>,
+    lines: HashMap<SymbolLocation, (usize, usize)>, // Source file index and line number
+    files: Vec<SourceFile>,
+}
+
+struct DbgSegment {
+    start: usize,
+    rom_offset: Option<usize>,
+}
+
+// Splits the key=value list of a .dbg record, keeping commas inside quotes
+fn dbg_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ','))) {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                if let Some((key, value)) = text[start..i].split_once('=') {
+                    fields.insert(key.trim(), value.trim().trim_matches('"'));
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+fn dbg_number(fields: &HashMap<&str, &str>, key: &str) -> Option<usize> {
+    let value = fields.get(key)?;
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => value.parse().ok()?,
+    };
+    Some(number)
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            labels: HashMap::new(),
+            addresses: HashMap::new(),
+            lines: HashMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    // Loads a symbol file by its extension and returns the number of labels in it
+    pub fn load(&mut self, path: &str) -> Result<usize, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let before = self.len();
+
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => self.load_dbg(&text, Path::new(path).parent().unwrap_or(Path::new(""))),
+            Some("mlb") => self.load_mlb(&text),
+            Some("nl") => {
+                // game.nes.ram.nl holds RAM labels, game.nes.<n>.nl those of 16KB bank n
+                let stem = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+                let bank = stem.rsplit('.').next().and_then(|bank| bank.parse().ok());
+                self.load_nl(&text, bank);
+            }
+            _ => return Err(format!("Unknown symbol file type: {}", path)),
+        }
+        Ok(self.len() - before)
+    }
+
+    // Symbol files found next to the ROM: game.dbg, game.mlb and the FCEUX
+    // game.nes.ram.nl / game.nes.<bank>.nl set
+    pub fn load_for_rom(&mut self, rom_path: &str, prg_banks: usize) {
+        let stem = Path::new(rom_path).with_extension("");
+        let mut paths = vec![
+            format!("{}.dbg", stem.display()),
+            format!("{}.mlb", stem.display()),
+            format!("{}.ram.nl", rom_path),
+        ];
+        paths.extend((0..prg_banks).map(|bank| format!("{}.{}.nl", rom_path, bank)));
+
+        for path in paths.iter().filter(|path| Path::new(path).exists()) {
+            match self.load(path) {
+                Ok(count) => println!("Loaded {} symbols from {}", count, path),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    fn add_label(&mut self, location: SymbolLocation, name: &str) {
+        if name.is_empty() {
+            return;
+        }
+        self.labels.entry(location).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), location);
+    }
+
+    // $C000#Label#Comment, optionally $C000/10#Label# for an array
+    fn load_nl(&mut self, text: &str, bank: Option<usize>) {
+        for line in text.lines() {
+            let mut fields = line.split('#');
+            let (Some(addr), Some(name)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let addr = addr.split('/').next().unwrap_or("");
+            let Some(addr) = addr.strip_prefix('$').and_then(|hex| u16::from_str_radix(hex, 16).ok()) else {
+                continue;
+            };
+
+            let location = match bank {
+                Some(bank) if addr >= 0x8000 => SymbolLocation::PrgRom(bank * 0x4000 + (addr as usize & 0x3FFF)),
+                _ => SymbolLocation::Cpu(addr),
+            };
+            self.add_label(location, name.trim());
+        }
+    }
+
+    // P:1234:Label:Comment, with the Mesen 2 memory type names accepted too
+    fn load_mlb(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut fields = line.splitn(4, ':');
+            let (Some(kind), Some(addr), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let addr = addr.split('-').next().unwrap_or("");
+            let Ok(offset) = usize::from_str_radix(addr, 16) else {
+                continue;
+            };
+
+            let location = match kind {
+                "P" | "NesPrgRom" => SymbolLocation::PrgRom(offset),
+                "R" | "NesInternalRam" => SymbolLocation::Cpu(offset as u16),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => SymbolLocation::Cpu(0x6000 + offset as u16),
+                "G" | "NesMemory" => SymbolLocation::Cpu(offset as u16),
+                _ => continue,
+            };
+            self.add_label(location, name.trim());
+        }
+    }
+
+    // ca65/ld65 debug info: labels from "sym" records and source lines from
+    // "line" records, placed through the segments and spans they point to
+    fn load_dbg(&mut self, text: &str, directory: &Path) {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut line_records = Vec::new();
+        let mut symbols = Vec::new();
+
+        for line in text.lines() {
+            let Some((record, rest)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let fields = dbg_fields(rest);
+            let Some(id) = dbg_number(&fields, "id") else {
+                continue;
+            };
+
+            match record {
+                "file" => {
+                    let name = fields.get("name").copied().unwrap_or("").to_string();
+                    let source = std::fs::read_to_string(directory.join(&name))
+                        .or_else(|_| std::fs::read_to_string(&name))
+                        .unwrap_or_default();
+                    files.insert(id, self.files.len());
+                    self.files.push(SourceFile {
+                        name,
+                        lines: source.lines().map(|line| line.to_string()).collect(),
+                    });
+                }
+                "seg" => {
+                    segments.insert(id, DbgSegment {
+                        start: dbg_number(&fields, "start").unwrap_or(0),
+                        rom_offset: dbg_number(&fields, "ooffs").and_then(|offset| offset.checked_sub(INES_HEADER_SIZE)),
+                    });
+                }
+                "span" => {
+                    if let (Some(seg), Some(start)) = (dbg_number(&fields, "seg"), dbg_number(&fields, "start")) {
+                        spans.insert(id, (seg, start));
+                    }
+                }
+                "line" => {
+                    // Macro expansions (type 2) would hide the line using the macro
+                    if dbg_number(&fields, "type") != Some(2)
+                        && let (Some(file), Some(number), Some(span)) = (dbg_number(&fields, "file"), dbg_number(&fields, "line"), fields.get("span")) {
+                        line_records.push((file, number, span.to_string()));
+                    }
+                }
+                "sym" => {
+                    if fields.get("type") == Some(&"lab")
+                        && let (Some(name), Some(value)) = (fields.get("name"), dbg_number(&fields, "val")) {
+                        symbols.push((name.to_string(), value, dbg_number(&fields, "seg")));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let location = |seg: Option<usize>, addr: usize| {
+            match seg.and_then(|seg| segments.get(&seg)) {
+                Some(DbgSegment { start, rom_offset: Some(rom_offset) }) if addr >= 0x8000 => {
+                    SymbolLocation::PrgRom(rom_offset + addr - start)
+                }
+                _ => SymbolLocation::Cpu(addr as u16),
+            }
+        };
+
+        for (name, value, seg) in symbols {
+            self.add_label(location(seg, value), &name);
+        }
+
+        for (file, number, span_ids) in line_records {
+            let Some(&file) = files.get(&file) else {
+                continue;
+            };
+            for span in span_ids.split('+').filter_map(|id| id.parse().ok()).filter_map(|id: usize| spans.get(&id)) {
+                let (seg, offset) = *span;
+                let start = segments.get(&seg).map_or(0, |segment| segment.start);
+                self.lines.entry(location(Some(seg), start + offset)).or_insert((file, number));
+            }
+        }
+    }
+
+    fn location(bus: &Bus, addr: u16) -> SymbolLocation {
+        match bus.prg_rom_offset(addr) {
+            Some(offset) => SymbolLocation::PrgRom(offset),
+            None => SymbolLocation::Cpu(addr),
+        }
+    }
+
+    pub fn label_at(&self, bus: &Bus, addr: u16) -> Option<&str> {
+        self.labels.get(&Self::location(bus, addr))
+            .or_else(|| self.labels.get(&SymbolLocation::Cpu(addr)))
+            .map(|name| name.as_str())
+    }
+
+    // "main_loop" or "main_loop+3" for an address at or shortly after a label
+    pub fn describe(&self, bus: &Bus, addr: u16) -> Option<String> {
+        (0..=MAX_LABEL_OFFSET).find_map(|offset| {
+            let name = self.label_at(bus, addr.wrapping_sub(offset))?;
+            Some(if offset == 0 { name.to_string() } else { format!("{}+{}", name, offset) })
+        })
+    }
+
+    // CPU address of "name" or "name+offset". Labels in PRG-ROM only resolve
+    // while their bank is mapped.
+    pub fn resolve(&self, bus: &Bus, text: &str) -> Result<u16, String> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, parse_number(offset)?),
+            None => (text, 0),
+        };
+        let location = self.addresses.get(name).ok_or(format!("Unknown symbol: {}", name))?;
+
+        let addr = match *location {
+            SymbolLocation::Cpu(addr) => addr,
+            SymbolLocation::PrgRom(rom_offset) => (0x8000..=0xFFFFu32).step_by(0x2000)
+                .map(|base| (base as usize + (rom_offset & 0x1FFF)) as u16)
+                .find(|addr| bus.prg_rom_offset(*addr) == Some(rom_offset))
+                .ok_or(format!("{} is in a bank that is not mapped", name))?,
+        };
+        Ok(addr.wrapping_add(offset as u16))
+    }
+
+    // "main.s:12  lda #$00" for the source line an address was assembled from
+    pub fn source_line(&self, bus: &Bus, addr: u16) -> Option<String> {
+        let &(file, number) = self.lines.get(&Self::location(bus, addr))?;
+        let file = &self.files[file];
+        let text = file.lines.get(number.wrapping_sub(1)).map_or("", |line| line.trim());
+        Some(format!("{}:{}  {}", file.name, number, text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines_file::test_rom;
+    use crate::region::Region;
+    use std::path::PathBuf;
+
+    const DBG: &str = "\
+version\tmajor=2,minor=0
+file\tid=0,name=\"main.s\",size=40,mtime=0x5F000000,mod=0
+seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+seg\tid=1,name=\"ZEROPAGE\",start=0x000000,size=0x0002,addrsize=zeropage,type=rw
+span\tid=0,seg=0,start=0,size=1
+span\tid=1,seg=0,start=1,size=2
+line\tid=0,file=0,line=2,span=0
+line\tid=1,file=0,line=3,span=1
+line\tid=2,file=0,line=9,type=2,span=1
+sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0x8000,seg=0,type=lab
+sym\tid=1,name=\"frame_count\",addrsize=zeropage,scope=0,def=2,val=0x1,seg=1,type=lab
+sym\tid=2,name=\"SIZE\",addrsize=zeropage,scope=0,def=3,val=0x10,type=equ
+";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes_symbols_{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, text: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn splits_dbg_fields_outside_quotes() {
+        let fields = dbg_fields("id=3,name=\"a,b.s\",size=0x10");
+        assert_eq!(fields.get("name"), Some(&"a,b.s"));
+        assert_eq!(dbg_number(&fields, "id"), Some(3));
+        assert_eq!(dbg_number(&fields, "size"), Some(0x10));
+        assert_eq!(dbg_number(&fields, "mtime"), None);
+    }
+
+    #[test]
+    fn loads_fceux_nl_files() {
+        let bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        let dir = test_dir("nl");
+        let mut symbols = SymbolTable::new();
+
+        let ram = write(&dir, "game.nes.ram.nl", "$0010#counter#Frame counter\n$0300/10#buffer#\nnot a label\n");
+        assert_eq!(symbols.load(&ram), Ok(2));
+        let bank0 = write(&dir, "game.nes.0.nl", "$C000#reset#\n");
+        let bank1 = write(&dir, "game.nes.1.nl", "$C000#other_bank#\n");
+        assert_eq!(symbols.load(&bank0), Ok(1));
+        assert_eq!(symbols.load(&bank1), Ok(1));
+
+        assert_eq!(symbols.resolve(&bus, "counter+2"), Ok(0x0012));
+        assert_eq!(symbols.resolve(&bus, "buffer"), Ok(0x0300));
+        // Bank 0 of a 16KB ROM is mapped at $8000 and mirrored at $C000
+        assert_eq!(symbols.resolve(&bus, "reset"), Ok(0x8000));
+        assert_eq!(symbols.label_at(&bus, 0xC000), Some("reset"));
+        assert!(symbols.resolve(&bus, "other_bank").is_err());
+        assert!(symbols.resolve(&bus, "missing").is_err());
+    }
+
+    #[test]
+    fn loads_mesen_mlb_files() {
+        let bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        let dir = test_dir("mlb");
+        let mut symbols = SymbolTable::new();
+        let text = "P:0010:nmi:Handler\nR:0020:player_x\nS:0000:save\nNesPrgRom:0100-0103:table\nX:0000:ignored\n";
+        assert_eq!(symbols.load(&write(&dir, "game.mlb", text)), Ok(4));
+
+        assert_eq!(symbols.resolve(&bus, "nmi"), Ok(0x8010));
+        assert_eq!(symbols.resolve(&bus, "player_x"), Ok(0x0020));
+        assert_eq!(symbols.resolve(&bus, "save"), Ok(0x6000));
+        assert_eq!(symbols.resolve(&bus, "table"), Ok(0x8100));
+        assert_eq!(symbols.describe(&bus, 0x8013).as_deref(), Some("nmi+3"));
+        assert_eq!(symbols.describe(&bus, 0x8010 + MAX_LABEL_OFFSET + 1), None);
+    }
+
+    #[test]
+    fn loads_ca65_dbg_labels_and_source_lines() {
+        let bus = Bus::new(test_rom(&[], &[]), Region::Ntsc);
+        let dir = test_dir("dbg");
+        write(&dir, "main.s", "; Test\nreset:  sei\n        lda #$00\n");
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.load(&write(&dir, "game.dbg", DBG)), Ok(2));
+
+        assert_eq!(symbols.resolve(&bus, "reset"), Ok(0x8000));
+        assert_eq!(symbols.resolve(&bus, "frame_count"), Ok(0x0001));
+        assert!(symbols.resolve(&bus, "SIZE").is_err());
+
+        assert_eq!(symbols.source_line(&bus, 0x8000).as_deref(), Some("main.s:2  reset:  sei"));
+        // The macro expansion on line 9 does not hide the line that used it
+        assert_eq!(symbols.source_line(&bus, 0x8001).as_deref(), Some("main.s:3  lda #$00"));
+        assert_eq!(symbols.source_line(&bus, 0x8003), None);
+    }
+
+    #[test]
+    fn rejects_unknown_file_types() {
+        let dir = test_dir("unknown");
+        let mut symbols = SymbolTable::new();
+        assert!(symbols.load(&write(&dir, "game.sym", "")).is_err());
+        assert!(symbols.load(&dir.join("missing.nl").to_string_lossy()).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Runs the emulator binary headless against small generated ROMs and checks
+// the frames it renders.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const PALETTE: [u8; 32] = [
+    0x0F, 0x11, 0x21, 0x31, 0x0F, 0x16, 0x26, 0x36, 0x0F, 0x1A, 0x2A, 0x3A, 0x0F, 0x13, 0x23, 0x33,
+    0x0F, 0x15, 0x25, 0x35, 0x0F, 0x18, 0x28, 0x38, 0x0F, 0x12, 0x22, 0x32, 0x0F, 0x17, 0x27, 0x37,
+];
+
+// Hash printed for the test card once its picture is stable
+const TEST_CARD_HASH: &str = "A3929C7546CFECE5";
+
+// NROM test card: fills the palette and the first nametable from tables in
+// ROM, loads OAM by DMA from ROM, sets a scroll and turns rendering on.
+fn test_card_rom() -> Vec<u8> {
+    let mut program = vec![
+        0x78,                         // SEI
+        0xA2, 0xFF, 0x9A,             // LDX #$FF; TXS
+        0x2C, 0x02, 0x20,             // BIT $2002
+        0x2C, 0x02, 0x20, 0x10, 0xFB, // Wait for vblank
+        0x2C, 0x02, 0x20, 0x10, 0xFB, // twice, for the PPU to warm up
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, // PPUADDR = $3F00
+        0xA9, 0x00, 0x8D, 0x06, 0x20,
+        0xA2, 0x00,                   // LDX #0
+        0xBD, 0x00, 0x81,             // LDA $8100,X
+        0x8D, 0x07, 0x20,             // STA $2007
+        0xE8, 0xE0, 0x20, 0xD0, 0xF5, // INX; CPX #32; BNE
+        0xA9, 0x20, 0x8D, 0x06, 0x20, // PPUADDR = $2000
+        0xA9, 0x00, 0x8D, 0x06, 0x20,
+    ];
+    for page in 0x82..0x86 {
+        // Copy $xx00-$xxFF to $2007
+        program.extend([0xA2, 0x00, 0xBD, 0x00, page, 0x8D, 0x07, 0x20, 0xE8, 0xD0, 0xF7]);
+    }
+    program.extend([
+        0xA9, 0x86, 0x8D, 0x14, 0x40, // OAM DMA from $8600
+        0xA9, 0x03, 0x8D, 0x05, 0x20, // Scroll X = 3
+        0xA9, 0x00, 0x8D, 0x05, 0x20, // Scroll Y = 0
+        0x8D, 0x00, 0x20,             // PPUCTRL = 0
+        0xA9, 0x1E, 0x8D, 0x01, 0x20, // PPUMASK: background and sprites
+    ]);
+    let spin = 0x8000 + program.len() as u16;
+    program.extend([0x4C, spin as u8, (spin >> 8) as u8]); // JMP to itself
+
+    let mut prg = vec![0xEA; 16 * 1024];
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x100..0x120].copy_from_slice(&PALETTE);
+    for i in 0..960 {
+        prg[0x200 + i] = ((i % 32 + i / 32) % 4) as u8;
+    }
+    for i in 0..64 {
+        prg[0x200 + 960 + i] = (i as u8).wrapping_mul(0x1B);
+    }
+    for n in 0..64 {
+        let flips = [0x00, 0x40, 0x80, 0xC0][n / 16];
+        let priority = if n % 5 == 0 { 0x20 } else { 0x00 };
+        prg[0x600 + n * 4..0x600 + n * 4 + 4].copy_from_slice(&[
+            (16 + (n / 8) * 24) as u8,
+            (1 + n % 3) as u8,
+            (n % 4) as u8 | flips | priority,
+            (16 + (n % 8) * 28) as u8,
+        ]);
+    }
+    prg[0x3FF0] = 0x40; // RTI
+    prg[0x3FFA..].copy_from_slice(&[0xF0, 0xFF, 0x00, 0x80, 0xF0, 0xFF]);
+
+    // Tile 0 is blank, 1 solid, 2 a checkerboard and 3 two crossed diagonals
+    let mut chr = vec![0x00; 8 * 1024];
+    for table in [0x0000, 0x1000] {
+        for row in 0..8 {
+            chr[table + 16 + row] = 0xFF;
+            chr[table + 32 + row] = if row % 2 == 0 { 0xAA } else { 0x55 };
+            chr[table + 40 + row] = if row < 4 { 0xF0 } else { 0x0F };
+            chr[table + 48 + row] = 0x80 >> row;
+            chr[table + 56 + row] = 0x01 << row;
+        }
+    }
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg);
+    rom.extend(chr);
+    rom
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+fn write_rom(name: &str, data: &[u8]) -> PathBuf {
+    let path = temp_path(name);
+    fs::write(&path, data).unwrap();
+    path
+}
+
+fn run_headless(rom: &PathBuf, frames: usize, extra_args: &[&str], bless: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_nes_emulator_rust"));
+    command.arg(rom).arg("--headless").arg(frames.to_string()).args(extra_args);
+    if bless {
+        command.env("BLESS_GOLDEN", "1");
+    } else {
+        command.env_remove("BLESS_GOLDEN");
+    }
+    command.output().unwrap()
+}
+
+fn frame_hash(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines()
+        .find_map(|line| line.split_once(" hash: ").map(|(_, hash)| hash.to_string()))
+        .unwrap_or_else(|| panic!("no frame hash in output:\n{}", stdout))
+}
+
+#[test]
+fn test_card_renders_the_golden_frame() {
+    let rom = write_rom("test_card.nes", &test_card_rom());
+    let output = run_headless(&rom, 10, &[], false);
+    assert!(output.status.success());
+    assert_eq!(frame_hash(&output), TEST_CARD_HASH);
+}
+
+#[test]
+fn rendering_is_the_same_cycle_stepped() {
+    let rom = write_rom("test_card_cycle_stepped.nes", &test_card_rom());
+    let output = run_headless(&rom, 10, &["--cycle-stepped"], false);
+    assert!(output.status.success());
+    assert_eq!(frame_hash(&output), TEST_CARD_HASH);
+}
+
+#[test]
+fn missing_golden_frame_fails_unless_blessed() {
+    let rom = write_rom("test_card_golden.nes", &test_card_rom());
+    let golden = temp_path("test_card_golden.frame");
+    let _ = fs::remove_file(&golden);
+    let golden_arg = golden.to_str().unwrap();
+
+    let output = run_headless(&rom, 10, &["--golden", golden_arg], false);
+    assert!(!output.status.success());
+    assert!(!golden.exists());
+
+    let output = run_headless(&rom, 10, &["--golden", golden_arg], true);
+    assert!(output.status.success());
+    assert!(golden.exists());
+
+    let output = run_headless(&rom, 10, &["--golden", golden_arg], false);
+    assert!(output.status.success());
+
+    // Rendering is only turned on during the first frame
+    let output = run_headless(&rom, 1, &["--golden", golden_arg], false);
+    assert!(!output.status.success());
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name)
+}
+
+#[test]
+fn test_card_matches_the_golden_png() {
+    let rom = write_rom("test_card_png.nes", &test_card_rom());
+    let golden = golden_path("test_card.png");
+    let output = run_headless(&rom, 10, &["--golden", golden.to_str().unwrap()], false);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn golden_mismatch_reports_the_differing_pixels() {
+    let rom = write_rom("test_card_mismatch.nes", &test_card_rom());
+    let golden = temp_path("test_card_mismatch.png");
+    fs::copy(golden_path("test_card.png"), &golden).unwrap();
+
+    let output = run_headless(&rom, 1, &["--golden", golden.to_str().unwrap()], false);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" pixels (hash "), "{}", stdout);
+    assert!(temp_path("test_card_mismatch.actual.png").exists());
+}